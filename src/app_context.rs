@@ -0,0 +1,174 @@
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::Result;
+#[cfg(debug_assertions)]
+use log::info;
+use tokio::sync::broadcast;
+
+use crate::{
+    config::{ColorCfg, Config},
+    controller::Controllers,
+    events::{Event, EventBus},
+    mappings::{ColorMapping, Mapping},
+    sensors::{SensorKinds, SensorRegistry, TemperatureSensor},
+    services::CancelToken,
+    temperature_sensors::external::{ExternalFactory, ExternalTemperatures},
+};
+
+/// Everything the daemon runs on, built from one config.
+pub struct AppContext {
+    pub cfg: Config,
+    pub config_path: PathBuf,
+    pub controllers: Controllers,
+    pub sensors: Arc<Vec<Box<dyn TemperatureSensor>>>,
+    /// Sensor kinds the registry had backends for, to check reloaded configs against.
+    pub sensor_kinds: SensorKinds,
+    pub external: ExternalTemperatures,
+    pub mapping: Arc<Mapping>,
+    pub colors: Arc<Vec<ColorCfg>>,
+    pub color_mappings: Arc<ColorMapping>,
+    pub bus: EventBus,
+    pub cancel: CancelToken,
+}
+
+impl AppContext {
+    /// Builds the context from `cfg`: controllers through `open_controllers`
+    /// ([`Controllers::init_from_cfg`] outside tests), sensors through `registry` with the
+    /// `external` kind added, then the sensor and color mappings.
+    pub fn new<F>(
+        cfg: Config,
+        config_path: PathBuf,
+        mut registry: SensorRegistry,
+        open_controllers: F,
+    ) -> Result<Self>
+    where
+        F: FnOnce(&Config, &EventBus, &CancelToken) -> Result<Controllers>,
+    {
+        let bus = EventBus::default();
+        let cancel = CancelToken::default();
+        let controllers = open_controllers(&cfg, &bus, &cancel)?;
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        registry.register("external", ExternalFactory(external.clone()));
+        let sensors = registry.build_all(&cfg.sensors)?;
+        let sensor_kinds = registry.kinds();
+
+        #[cfg(debug_assertions)]
+        {
+            info!("Loaded {} temperature sensors", sensors.len());
+        }
+
+        Ok(Self {
+            mapping: Arc::new(Mapping::load_mappings(&cfg.mappings)),
+            colors: Arc::new(cfg.colors.clone()),
+            color_mappings: Arc::new(ColorMapping::build_color_mapping(&cfg.color_mappings)),
+            controllers,
+            sensors: Arc::new(sensors),
+            sensor_kinds,
+            external,
+            cfg,
+            config_path,
+            bus,
+            cancel,
+        })
+    }
+
+    /// Receives every [`Event`] published from now on, for code embedding the daemon that
+    /// wants to follow service, color or sensor events.
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.bus.subscribe()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::AppContext;
+    use crate::{
+        config::{Config, ControllerCfg, MappingCfg, SensorCfg},
+        controller::{Controllers, tests::MockController},
+        events::Event,
+        mappings::FanRef,
+        sensors::SensorRegistry,
+    };
+
+    #[tokio::test]
+    async fn context_is_built_from_the_config() {
+        let cfg = Config::builder()
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "Front", &["Silent"])
+                    .fan(2, "Rear", &["Silent"]),
+            )
+            .sensor(SensorCfg::External {
+                id: String::from("cpu"),
+                max_age: 30,
+            })
+            .sensor(SensorCfg::External {
+                id: String::from("gpu"),
+                max_age: 30,
+            })
+            .mapping(MappingCfg::builder("cpu").target(1, 1).target(1, 2).build())
+            .color("red", [255, 0, 0])
+            .color_mapping("red", &[(1, 2)])
+            .build();
+
+        let ctx = AppContext::new(
+            cfg,
+            PathBuf::from("config.yml"),
+            SensorRegistry::default(),
+            |cfg, _, _| {
+                let fans = cfg.controllers[0].fan_count();
+                Ok(Controllers::from(vec![
+                    MockController::with_fans(fans).boxed(),
+                ]))
+            },
+        )
+        .unwrap();
+
+        assert_eq!(ctx.controllers.fan_counts().await, [2]);
+        assert_eq!(ctx.sensors.len(), 2);
+        ctx.external.set("gpu", 50.0).unwrap();
+        assert_eq!(ctx.sensors[1].read_temperature().await.unwrap(), 50.0);
+
+        let fan = |channel| FanRef {
+            controller_id: 1,
+            channel,
+        };
+        let mut driven: Vec<_> = ctx.mapping.fans_for_sensor(&String::from("cpu")).collect();
+        driven.sort();
+        assert_eq!(driven, [fan(1), fan(2)]);
+        assert_eq!(ctx.mapping.fans_for_sensor(&String::from("gpu")).count(), 0);
+
+        let colors: Vec<_> = ctx
+            .color_mappings
+            .iter()
+            .map(|entry| {
+                (
+                    entry.key().clone(),
+                    entry.value().iter().map(|f| *f).collect(),
+                )
+            })
+            .collect::<Vec<(String, Vec<FanRef>)>>();
+        assert_eq!(colors, [(String::from("red"), vec![fan(2)])]);
+        assert_eq!(ctx.colors.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn subscribers_receive_published_events() {
+        let ctx = AppContext::new(
+            Config::builder().build(),
+            PathBuf::from("config.yml"),
+            SensorRegistry::default(),
+            |_, _, _| Ok(Controllers::from(vec![])),
+        )
+        .unwrap();
+        let mut events = ctx.subscribe();
+
+        let started = Event::ServiceStarted {
+            name: String::from("monitoring"),
+        };
+        ctx.bus.publish(started.clone());
+        assert_eq!(events.recv().await.unwrap(), started);
+    }
+}
@@ -0,0 +1,68 @@
+use std::collections::HashMap;
+
+/// Slack for float noise, so a change of exactly `min_change` (e.g. 41.5 -> 41.7) counts.
+const TOLERANCE: f32 = 1e-3;
+
+/// Holds back `TemperatureChanged` while the temperatures match the last ones published.
+#[derive(Debug)]
+pub struct ChangeFilter {
+    min_change: f32,
+    last: Option<HashMap<String, f32>>,
+}
+
+impl ChangeFilter {
+    /// A sensor has to move by `min_change` degrees since the last publish to count as
+    /// changed; 0 publishes every time.
+    pub fn new(min_change: f32) -> Self {
+        Self {
+            min_change,
+            last: None,
+        }
+    }
+
+    /// Whether `temps` should be published, remembering it when so. An empty map never is;
+    /// a sensor appearing or disappearing always is.
+    pub fn should_publish(&mut self, temps: &HashMap<String, f32>) -> bool {
+        if temps.is_empty() {
+            return false;
+        }
+        let changed = self.last.as_ref().is_none_or(|last| {
+            last.len() != temps.len()
+                || temps.iter().any(|(sensor, t)| {
+                    last.get(sensor)
+                        .is_none_or(|prev| (t - prev).abs() > self.min_change - TOLERANCE)
+                })
+        });
+        if changed {
+            self.last = Some(temps.clone());
+        }
+        changed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::ChangeFilter;
+
+    fn temps(entries: &[(&str, f32)]) -> HashMap<String, f32> {
+        entries.iter().map(|&(s, t)| (s.to_string(), t)).collect()
+    }
+
+    #[test]
+    fn identical_readings_publish_once() {
+        let mut filter = ChangeFilter::new(0.2);
+        assert!(!filter.should_publish(&temps(&[])));
+        assert!(filter.should_publish(&temps(&[("cpu", 41.5)])));
+        assert!(!filter.should_publish(&temps(&[("cpu", 41.5)])));
+        assert!(!filter.should_publish(&temps(&[("cpu", 41.6)])));
+        assert!(filter.should_publish(&temps(&[("cpu", 41.7)])));
+        assert!(filter.should_publish(&temps(&[("cpu", 41.7), ("gpu", 50.0)])));
+        assert!(filter.should_publish(&temps(&[("gpu", 50.0)])));
+
+        let mut every_time = ChangeFilter::new(0.0);
+        assert!(every_time.should_publish(&temps(&[("cpu", 41.5)])));
+        assert!(every_time.should_publish(&temps(&[("cpu", 41.5)])));
+    }
+}
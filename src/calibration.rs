@@ -0,0 +1,201 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+use anyhow::{Context, Result, anyhow};
+use log::warn;
+use tokio::time::sleep;
+
+use crate::{config, controller::Controllers, mappings::FanRef};
+
+/// Time a fan gets to reach full speed before its RPM is sampled.
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub const SETTLE: Duration = Duration::from_secs(5);
+const SAMPLES: u32 = 5;
+const SAMPLE_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Max RPM each fan reached at 100%, saved as JSON keyed by `controller:channel`.
+#[derive(Debug, Default)]
+pub struct Calibration {
+    path: Option<PathBuf>,
+    max_rpm: BTreeMap<String, u16>,
+}
+
+impl Calibration {
+    /// Reads the saved calibration; a missing or unreadable file starts empty.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let max_rpm = match fs::read_to_string(&path) {
+            Ok(txt) => serde_json::from_str(&txt).unwrap_or_else(|e| {
+                warn!("Ignoring calibration {}: {e}", path.display());
+                BTreeMap::new()
+            }),
+            Err(_) => BTreeMap::new(),
+        };
+        Self {
+            path: Some(path),
+            max_rpm,
+        }
+    }
+
+    /// Default location: `calibration.json` next to the config file, see [`config::data_file`].
+    pub fn default_path(config_path: &Path) -> PathBuf {
+        config::data_file(config_path, "calibration.json")
+    }
+
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn max_rpm(&self, fan: FanRef) -> Option<u16> {
+        self.max_rpm.get(&fan.to_string()).copied()
+    }
+
+    /// Every calibrated fan with its max RPM; keys that aren't `controller:channel` are
+    /// skipped.
+    pub fn entries(&self) -> impl Iterator<Item = (FanRef, u16)> + '_ {
+        self.max_rpm.iter().filter_map(|(key, &rpm)| {
+            let (controller, channel) = key.split_once(':')?;
+            let fan = FanRef {
+                controller_id: controller.parse().ok()?,
+                channel: channel.parse().ok()?,
+            };
+            Some((fan, rpm))
+        })
+    }
+
+    /// Hands every calibrated max RPM to its controller, for RPM curves. A fan that is no
+    /// longer there is only logged.
+    pub async fn apply_to(&self, controllers: &Controllers) {
+        for (fan, rpm) in self.entries() {
+            let (ctrl, channel) = (fan.controller_id as u8, fan.channel as u8);
+            if let Err(e) = controllers.set_max_rpm(ctrl, channel, rpm).await {
+                warn!("Ignoring calibration of fan {fan}: {e}");
+            }
+        }
+    }
+
+    /// Stores `rpm` as the fan's baseline and writes the file.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn record(&mut self, fan: FanRef, rpm: u16) -> Result<()> {
+        self.max_rpm.insert(fan.to_string(), rpm);
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let txt = serde_json::to_string_pretty(&self.max_rpm)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        fs::write(path, txt).with_context(|| format!("writing {}", path.display()))
+    }
+
+    /// `rpm` as a percentage of what the fan should reach at `speed`, assuming RPM grows
+    /// linearly with duty up to the calibrated maximum.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn health(&self, fan: FanRef, speed: u8, rpm: u16) -> Result<f64> {
+        let max = self
+            .max_rpm(fan)
+            .ok_or(anyhow!("fan {fan} is not calibrated"))?;
+        if speed == 0 || max == 0 {
+            return Err(anyhow!("fan {fan} is stopped; health needs a running fan"));
+        }
+        let expected = max as f64 * speed as f64 / 100.0;
+        Ok(rpm as f64 / expected * 100.0)
+    }
+}
+
+/// Duty (percent) closest to `rpm` for a fan reaching `max_rpm` at 100%, with the same
+/// linear model as [`Calibration::health`]. Targets above the maximum run at 100%.
+pub fn duty_for_rpm(rpm: f32, max_rpm: u16) -> u8 {
+    if max_rpm == 0 {
+        return 100;
+    }
+    (rpm / max_rpm as f32 * 100.0).round().clamp(0.0, 100.0) as u8
+}
+
+/// Runs `fan` at 100% until it settles and returns the average of several RPM readings.
+/// Automatic control is paused meanwhile so the monitoring loop leaves the fan alone; the
+/// fan's previous speed and the auto-control setting are restored afterwards.
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub async fn calibrate(
+    controllers: &Controllers,
+    auto_control: &AtomicBool,
+    fan: FanRef,
+) -> Result<u16> {
+    let (ctrl, channel) = (fan.controller_id as u8, fan.channel as u8);
+    let previous = controllers
+        .fan_stats()
+        .await
+        .into_iter()
+        .find(|&(c, ch, _, _)| (c, ch) == (ctrl, channel))
+        .map(|(_, _, speed, _)| speed)
+        .ok_or(anyhow!("fan {fan} not found"))?;
+    let was_auto = auto_control.swap(false, Ordering::Relaxed);
+    let measured = async {
+        controllers.set_speed_direct(ctrl, channel, 100).await?;
+        sleep(SETTLE).await;
+        let mut total = 0u32;
+        for _ in 0..SAMPLES {
+            total += controllers.channel_rpm(ctrl, channel).await? as u32;
+            sleep(SAMPLE_INTERVAL).await;
+        }
+        Ok::<_, anyhow::Error>((total / SAMPLES) as u16)
+    }
+    .await;
+    let restored = controllers.set_speed_direct(ctrl, channel, previous).await;
+    auto_control.store(was_auto, Ordering::Relaxed);
+    let rpm = measured?;
+    restored?;
+    Ok(rpm)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs, process,
+        sync::atomic::{AtomicBool, Ordering},
+    };
+
+    use super::{Calibration, calibrate};
+    use crate::{
+        controller::{Controllers, tests::MockController},
+        mappings::FanRef,
+    };
+
+    #[tokio::test(start_paused = true)]
+    async fn calibration_records_full_speed_rpm() {
+        let path = env::temp_dir().join(format!("tt_riingd_calibration_{}.json", process::id()));
+        let mock = MockController::with_fans(2);
+        *mock.speeds.lock().unwrap() = vec![40, 60];
+        let speeds = mock.speeds.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let auto_control = AtomicBool::new(true);
+        let fan = FanRef {
+            controller_id: 1,
+            channel: 2,
+        };
+
+        // The mock spins at 20 RPM per percent of duty.
+        let rpm = calibrate(&controllers, &auto_control, fan).await.unwrap();
+        assert_eq!(rpm, 2000);
+        assert_eq!(*speeds.lock().unwrap(), [40, 60]);
+        assert!(auto_control.load(Ordering::Relaxed));
+
+        let mut calibration = Calibration::load(&path);
+        calibration.record(fan, rpm).unwrap();
+        let reloaded = Calibration::load(&path);
+        assert_eq!(reloaded.max_rpm(fan), Some(2000));
+
+        assert_eq!(reloaded.health(fan, 60, 1200).unwrap(), 100.0);
+        assert_eq!(reloaded.health(fan, 50, 900).unwrap(), 90.0);
+        assert!(reloaded.health(fan, 0, 0).is_err());
+        let uncalibrated = FanRef {
+            controller_id: 1,
+            channel: 1,
+        };
+        assert!(reloaded.health(uncalibrated, 40, 800).is_err());
+
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -1,11 +1,102 @@
 use clap::Parser;
 use std::path::PathBuf;
 
+use crate::config::Config;
+
 /// tt-riingd — daemon for TT Riing Quad fan control
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
 pub struct Cli {
-    /// YAML config file path (default: /etc/config.yml)
+    /// YAML config file path (default: /etc/config.yml), or `-` to read it from stdin
     #[arg(short = 'c', long = "config")]
     pub config: Option<PathBuf>,
+
+    /// Override `tick_seconds` from the config
+    #[arg(long)]
+    pub tick_seconds: Option<u16>,
+
+    /// Override `broadcast_interval` from the config
+    #[arg(long)]
+    pub broadcast_interval: Option<u16>,
+
+    /// Override `enable_broadcast` from the config (bare flag means true)
+    #[arg(long, num_args = 0..=1, default_missing_value = "true")]
+    pub enable_broadcast: Option<bool>,
+
+    /// Log every raw HID packet (hex) for protocol debugging
+    #[arg(long)]
+    pub trace_hid: bool,
+
+    /// Override `log_max_kb` from the config (0 disables log rotation)
+    #[arg(long)]
+    pub log_max_kb: Option<u64>,
+
+    /// Override `log_keep` from the config
+    #[arg(long)]
+    pub log_keep: Option<usize>,
+}
+
+impl Cli {
+    pub fn apply_overrides(&self, cfg: &mut Config) {
+        if let Some(tick_seconds) = self.tick_seconds {
+            cfg.tick_seconds = tick_seconds;
+        }
+        if let Some(broadcast_interval) = self.broadcast_interval {
+            cfg.broadcast_interval = broadcast_interval;
+        }
+        if let Some(enable_broadcast) = self.enable_broadcast {
+            cfg.enable_broadcast = enable_broadcast;
+        }
+        if self.trace_hid {
+            cfg.trace_hid = true;
+        }
+        if let Some(log_max_kb) = self.log_max_kb {
+            cfg.log_max_kb = log_max_kb;
+        }
+        if let Some(log_keep) = self.log_keep {
+            cfg.log_keep = log_keep;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+
+    use super::Cli;
+    use crate::config;
+
+    const FILE: &str = "version: 1\ntick_seconds: 9\nbroadcast_interval: 7\n";
+
+    #[test]
+    fn overrides_replace_file_values() {
+        let mut cfg = config::parse(FILE).unwrap();
+        Cli::try_parse_from([
+            "tt_riingd",
+            "--tick-seconds",
+            "1",
+            "--enable-broadcast",
+            "--log-keep",
+            "5",
+        ])
+        .unwrap()
+        .apply_overrides(&mut cfg);
+
+        assert_eq!(cfg.tick_seconds, 1);
+        assert!(cfg.enable_broadcast);
+        assert_eq!(cfg.broadcast_interval, 7);
+        assert_eq!(cfg.log_keep, 5);
+        assert_eq!(cfg.log_max_kb, 1024);
+    }
+
+    #[test]
+    fn no_overrides_keep_file_values() {
+        let mut cfg = config::parse(FILE).unwrap();
+        Cli::try_parse_from(["tt_riingd"])
+            .unwrap()
+            .apply_overrides(&mut cfg);
+
+        assert_eq!(cfg.tick_seconds, 9);
+        assert!(!cfg.enable_broadcast);
+    }
 }
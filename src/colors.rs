@@ -0,0 +1,424 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+use log::error;
+
+use crate::{
+    config::{ColorApplyMode, ColorCfg, Config},
+    controller::Controllers,
+    events::{Event, EventBus, Subscriber},
+    mappings::{ColorMapping, FanRef},
+};
+
+/// Writes the configured static colors to their fans, and `default_color` to the rest.
+/// While any sensor is at its critical limit, every fan shows `alert_color` instead.
+#[derive(Debug, Clone)]
+pub struct ColorApplier {
+    controllers: Controllers,
+    color_map: Arc<ColorMapping>,
+    colors: Arc<Vec<ColorCfg>>,
+    default_color: Option<String>,
+    alert_color: Option<String>,
+    /// Sensors at their critical limit, from [`Event::CriticalTemperature`].
+    critical: HashSet<String>,
+    mode: ColorApplyMode,
+    bus: EventBus,
+    last: Option<(usize, usize)>,
+    /// Color last written to each fan, for [`ColorApplyMode::OnChange`].
+    written: HashMap<FanRef, [u8; 3]>,
+}
+
+impl ColorApplier {
+    pub fn new(
+        controllers: Controllers,
+        color_map: Arc<ColorMapping>,
+        colors: Arc<Vec<ColorCfg>>,
+        default_color: Option<String>,
+        mode: ColorApplyMode,
+        bus: EventBus,
+    ) -> Self {
+        Self {
+            controllers,
+            color_map,
+            colors,
+            default_color,
+            alert_color: None,
+            critical: HashSet::new(),
+            mode,
+            bus,
+            last: None,
+            written: HashMap::new(),
+        }
+    }
+
+    /// Color every fan shows while a sensor is critical.
+    pub fn with_alert_color(mut self, alert_color: Option<String>) -> Self {
+        self.alert_color = alert_color;
+        self
+    }
+
+    /// Takes the colors and color mappings of a reloaded config. Fans it no longer colors
+    /// are turned black on the next pass, unless it has no colors left at all.
+    pub fn reload(&mut self, cfg: &Config) {
+        self.color_map = Arc::new(ColorMapping::build_color_mapping(&cfg.color_mappings));
+        self.colors = Arc::new(cfg.colors.clone());
+        self.default_color = cfg.default_color.clone();
+        self.alert_color = cfg.alert_color.clone();
+        self.mode = cfg.color_apply_mode;
+    }
+
+    /// Whether there is nothing to write: no colors, or neither mappings, a default color nor
+    /// an alert color to use them.
+    pub fn is_idle(&self) -> bool {
+        self.colors.is_empty()
+            || (self.color_map.is_empty()
+                && self.default_color.is_none()
+                && self.alert_color.is_none())
+    }
+
+    /// Events to pass to [`Self::observe`].
+    pub fn subscribe(&self) -> Subscriber {
+        self.bus.subscriber()
+    }
+
+    /// Tracks critical sensors; returns whether the alert started or ended, so the caller
+    /// can apply right away.
+    pub fn observe(&mut self, event: &Event) -> bool {
+        let Event::CriticalTemperature { sensor, active, .. } = event else {
+            return false;
+        };
+        let was_alerting = !self.critical.is_empty();
+        if *active {
+            self.critical.insert(sensor.clone());
+        } else {
+            self.critical.remove(sensor);
+        }
+        let alerting = !self.critical.is_empty();
+        alerting != was_alerting
+    }
+
+    async fn all_fans(&self) -> Vec<FanRef> {
+        self.controllers
+            .fan_counts()
+            .await
+            .into_iter()
+            .enumerate()
+            .flat_map(|(idx, count)| {
+                (1..=count).map(move |channel| FanRef {
+                    controller_id: idx + 1,
+                    channel,
+                })
+            })
+            .collect()
+    }
+
+    /// Applies every mapped color, then `default_color` to fans no mapping covers, or only
+    /// `alert_color` to all fans while a sensor is critical, and returns
+    /// `(applied, failed)` writes. With [`ColorApplyMode::OnChange`] fans already showing their
+    /// color are skipped and not counted. Publishes [`Event::ColorChanged`] only if at least one write
+    /// succeeded and the counts differ from the previous pass, so the periodic re-apply stays
+    /// quiet.
+    pub async fn apply(&mut self) -> (usize, usize) {
+        if self.is_idle() {
+            return (0, 0);
+        }
+        let rgb_of = |name: &str| self.colors.iter().find(|c| c.color == name).map(|c| c.rgb);
+        let alert = self.alert_color.as_deref().and_then(rgb_of);
+        let map = match alert {
+            Some(rgb) if !self.critical.is_empty() => vec![(rgb, self.all_fans().await)],
+            _ => self.mapped_colors().await,
+        };
+        self.write(map).await
+    }
+
+    /// Mapped colors, then `default_color` for the fans no mapping covers, then black for
+    /// fans written before that neither covers.
+    async fn mapped_colors(&self) -> Vec<([u8; 3], Vec<FanRef>)> {
+        let rgb_of = |name: &str| self.colors.iter().find(|c| c.color == name).map(|c| c.rgb);
+        let mut mapped = HashSet::new();
+        let mut map: Vec<_> = self
+            .color_map
+            .iter()
+            .filter_map(|entry| {
+                let fans: Vec<FanRef> = entry.value().iter().map(|fan| *fan).collect();
+                mapped.extend(fans.iter().copied());
+                rgb_of(entry.key()).map(|rgb| (rgb, fans))
+            })
+            .collect();
+        if let Some(rgb) = self.default_color.as_deref().and_then(rgb_of) {
+            let unmapped: Vec<_> = self
+                .all_fans()
+                .await
+                .into_iter()
+                .filter(|fan| !mapped.contains(fan))
+                .collect();
+            map.push((rgb, unmapped));
+        }
+        // Fans the alert painted that nothing else covers are switched off again.
+        let covered: HashSet<_> = map.iter().flat_map(|(_, fans)| fans.iter()).collect();
+        let stale: Vec<_> = self
+            .written
+            .keys()
+            .filter(|fan| !covered.contains(fan))
+            .copied()
+            .collect();
+        if !stale.is_empty() {
+            map.push(([0, 0, 0], stale));
+        }
+        map
+    }
+
+    async fn write(&mut self, map: Vec<([u8; 3], Vec<FanRef>)>) -> (usize, usize) {
+        let (mut applied, mut failed) = (0, 0);
+        for (rgb, fans) in map {
+            for fan in fans {
+                if self.mode == ColorApplyMode::OnChange && self.written.get(&fan) == Some(&rgb) {
+                    continue;
+                }
+                let ret = self
+                    .controllers
+                    .update_channel_color(
+                        fan.controller_id as u8,
+                        fan.channel as u8,
+                        rgb[0],
+                        rgb[1],
+                        rgb[2],
+                    )
+                    .await;
+                match ret {
+                    Ok(()) => {
+                        applied += 1;
+                        self.written.insert(fan, rgb);
+                    }
+                    Err(e) => {
+                        failed += 1;
+                        error!("update_channel_color error: {e}");
+                    }
+                }
+            }
+        }
+        if applied > 0 && self.last != Some((applied, failed)) {
+            self.bus.publish(Event::ColorChanged { applied, failed });
+        }
+        self.last = Some((applied, failed));
+        (applied, failed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, sync::Arc};
+
+    use super::ColorApplier;
+    use crate::{
+        config::{ColorApplyMode, ColorCfg, ColorMappingCfg, Config, FanTarget},
+        controller::{Controllers, tests::MockController},
+        events::{Event, EventBus},
+        mappings::ColorMapping,
+        thresholds::CriticalAlerts,
+    };
+
+    /// Fans 1 and 2 of controller 1 are mapped to blue.
+    fn applier(mock: MockController, default_color: Option<&str>, bus: &EventBus) -> ColorApplier {
+        applier_with_mode(mock, default_color, ColorApplyMode::Always, bus)
+    }
+
+    fn applier_with_mode(
+        mock: MockController,
+        default_color: Option<&str>,
+        mode: ColorApplyMode,
+        bus: &EventBus,
+    ) -> ColorApplier {
+        let color_map = ColorMapping::build_color_mapping(&[ColorMappingCfg {
+            color: String::from("blue"),
+            targets: vec![
+                FanTarget {
+                    controller: 1,
+                    fan_idx: 1,
+                },
+                FanTarget {
+                    controller: 1,
+                    fan_idx: 2,
+                },
+            ],
+        }]);
+        let colors = vec![
+            ColorCfg {
+                color: String::from("blue"),
+                rgb: [0, 0, 255],
+            },
+            ColorCfg {
+                color: String::from("red"),
+                rgb: [255, 0, 0],
+            },
+        ];
+        ColorApplier::new(
+            Controllers::from(vec![mock.boxed()]),
+            Arc::new(color_map),
+            Arc::new(colors),
+            default_color.map(String::from),
+            mode,
+            bus.clone(),
+        )
+    }
+
+    #[tokio::test]
+    async fn failed_writes_are_not_reported_as_changed() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+
+        let mut none = applier(MockController::with_fans(0), None, &bus);
+        assert_eq!(none.apply().await, (0, 2));
+        assert!(events.try_recv().is_err());
+
+        let mut partial = applier(MockController::with_fans(1), None, &bus);
+        assert_eq!(partial.apply().await, (1, 1));
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::ColorChanged {
+                applied: 1,
+                failed: 1
+            }
+        );
+        partial.apply().await;
+        assert!(events.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn empty_color_config_writes_and_publishes_nothing() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mock = MockController::with_fans(2);
+        let colors = mock.colors.clone();
+        let mut idle = ColorApplier::new(
+            Controllers::from(vec![mock.boxed()]),
+            Arc::new(ColorMapping::build_color_mapping(&[])),
+            Arc::new(vec![]),
+            None,
+            ColorApplyMode::Always,
+            bus.clone(),
+        );
+
+        assert!(idle.is_idle());
+        assert_eq!(idle.apply().await, (0, 0));
+        assert!(events.try_recv().is_err());
+        assert!(
+            colors
+                .lock()
+                .unwrap()
+                .iter()
+                .all(|history| history.len() == 1)
+        );
+        assert!(!applier(MockController::with_fans(2), None, &bus).is_idle());
+    }
+
+    #[tokio::test]
+    async fn reload_recolors_the_fans() {
+        let mock = MockController::with_fans(3);
+        let colors = mock.colors.clone();
+        let mut applier = applier(mock, None, &EventBus::default());
+        applier.apply().await;
+
+        applier.reload(
+            &Config::builder()
+                .color("red", [255, 0, 0])
+                .color_mapping("red", &[(1, 3)])
+                .build(),
+        );
+        assert_eq!(applier.apply().await, (3, 0));
+        let last: Vec<_> = colors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|history| *history.last().unwrap())
+            .collect();
+        assert_eq!(last, [(0, 0, 0), (0, 0, 0), (255, 0, 0)]);
+    }
+
+    #[tokio::test]
+    async fn unmapped_fans_get_the_default_color() {
+        let mock = MockController::with_fans(3);
+        let colors = mock.colors.clone();
+        let mut applier = applier(mock, Some("red"), &EventBus::default());
+
+        assert_eq!(applier.apply().await, (3, 0));
+        let last: Vec<_> = colors
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|history| *history.last().unwrap())
+            .collect();
+        assert_eq!(last, [(0, 0, 255), (0, 0, 255), (255, 0, 0)]);
+    }
+
+    #[tokio::test]
+    async fn on_change_mode_skips_unchanged_colors() {
+        let mock = MockController::with_fans(2);
+        let colors = mock.colors.clone();
+        let writes = || colors.lock().unwrap().iter().map(Vec::len).sum::<usize>();
+        let mut applier =
+            applier_with_mode(mock, None, ColorApplyMode::OnChange, &EventBus::default());
+
+        assert_eq!(applier.apply().await, (2, 0));
+        let after_first = writes();
+        assert_eq!(applier.apply().await, (0, 0));
+        assert_eq!(writes(), after_first);
+
+        Arc::make_mut(&mut applier.colors)[0].rgb = [0, 255, 0];
+        assert_eq!(applier.apply().await, (2, 0));
+        assert_eq!(writes(), after_first + 2);
+    }
+
+    #[tokio::test]
+    async fn alert_color_shows_while_a_sensor_is_critical() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mock = MockController::with_fans(3);
+        let colors = mock.colors.clone();
+        let last = || -> Vec<_> {
+            colors
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|history| *history.last().unwrap())
+                .collect()
+        };
+        let mut applier =
+            applier_with_mode(mock, None, ColorApplyMode::OnChange, &EventBus::default())
+                .with_alert_color(Some(String::from("red")));
+        let mut alerts = CriticalAlerts::new(HashMap::from([(String::from("cpu"), 95.0)]), bus);
+
+        applier.apply().await;
+        let before = last();
+        assert_eq!(&before[..2], [(0, 0, 255), (0, 0, 255)]);
+
+        alerts.update("cpu", 80.0);
+        alerts.update("cpu", 96.0);
+        let event = events.try_recv().unwrap();
+        assert_eq!(
+            event,
+            Event::CriticalTemperature {
+                sensor: String::from("cpu"),
+                temperature: 96.0,
+                active: true
+            }
+        );
+        assert!(applier.observe(&event));
+        assert_eq!(applier.apply().await, (3, 0));
+        assert_eq!(last(), [(255, 0, 0); 3]);
+
+        alerts.update("cpu", 97.0);
+        assert!(events.try_recv().is_err());
+        alerts.update("cpu", 90.0);
+        let event = events.try_recv().unwrap();
+        assert!(matches!(
+            event,
+            Event::CriticalTemperature { active: false, .. }
+        ));
+        assert!(applier.observe(&event));
+        assert_eq!(applier.apply().await, (3, 0));
+        assert_eq!(last(), [(0, 0, 255), (0, 0, 255), (0, 0, 0)]);
+    }
+}
@@ -1,21 +1,86 @@
 use crate::fan_curve::Point;
-use anyhow::{Context, Result};
-use log::info;
+use anyhow::{Context, Result, anyhow};
+use log::{info, warn};
 use serde::{Deserialize, Serialize};
 use std::{
+    collections::{HashMap, HashSet},
     env, fs,
+    io::Read,
+    net::SocketAddr,
     path::{Path, PathBuf},
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Config {
     pub version: u8,
+    #[serde(default = "defaults::profile")]
+    pub profile: String,
     #[serde(default = "defaults::tick_seconds")]
     pub tick_seconds: u16,
     #[serde(default = "defaults::enable_broadcast")]
     pub enable_broadcast: bool,
     #[serde(default = "defaults::broadcast_interval")]
     pub broadcast_interval: u16,
+    /// Degrees a sensor must move since the last `TemperatureChanged` before it is sent
+    /// again; 0 sends it on every broadcast tick.
+    #[serde(default = "defaults::broadcast_min_change")]
+    pub broadcast_min_change: f32,
+    #[serde(default = "defaults::history_length")]
+    pub history_length: usize,
+    /// Log every raw HID packet at debug level.
+    #[serde(default)]
+    pub trace_hid: bool,
+    /// Curve id used when a fan's `active_curve` is not among its curves.
+    #[serde(default)]
+    pub fallback_curve: Option<String>,
+    /// Times to try opening each USB controller at startup, for hubs that enumerate late.
+    #[serde(default = "defaults::usb_open_attempts")]
+    pub usb_open_attempts: u32,
+    #[serde(default = "defaults::usb_open_delay_ms")]
+    pub usb_open_delay_ms: u64,
+    /// Copy a config that fails to reload to `<name>.rejected` next to it.
+    #[serde(default)]
+    pub keep_rejected_config: bool,
+    /// Milliseconds to wait before initializing the controllers, for USB stacks that drop
+    /// writes right after boot.
+    #[serde(default)]
+    pub startup_delay_ms: u64,
+    /// Degrees temperatures are rounded to before a curve is evaluated, so fans sharing a
+    /// curve and sensor reuse one result per tick; 0 evaluates the exact reading.
+    #[serde(default)]
+    pub curve_resolution: f32,
+    /// Speed (percent) written to every fan right after init instead of one computed from
+    /// the current temperatures.
+    #[serde(default)]
+    pub startup_speed: Option<u8>,
+    /// Where fan RPM calibration is saved; defaults to `calibration.json` next to the config.
+    #[serde(default)]
+    pub calibration_file: Option<PathBuf>,
+    /// Where state set at runtime, such as `SetEnabled`, is saved; defaults to `state.json`
+    /// next to the config.
+    #[serde(default)]
+    pub state_file: Option<PathBuf>,
+    /// Run the fan update loops on their own threads instead of the shared runtime.
+    #[serde(default)]
+    pub dedicated_monitoring_thread: bool,
+    /// Seconds a fan update loop may go without ticking before every fan is set to
+    /// `panic_speed`; the watchdog is off when unset.
+    #[serde(default)]
+    pub watchdog_timeout: Option<u64>,
+    #[serde(default = "defaults::panic_speed")]
+    pub panic_speed: u8,
+    /// Degrees a fan must cool below a band's `from` before dropping to the lower band.
+    #[serde(default = "defaults::curve_band_hysteresis")]
+    pub curve_band_hysteresis: f32,
+    /// Size in KiB at which the daemon log file is rotated; 0 lets it grow.
+    #[serde(default = "defaults::log_max_kb")]
+    pub log_max_kb: u64,
+    /// Rotated copies of the log file to keep.
+    #[serde(default = "defaults::log_keep")]
+    pub log_keep: usize,
+    /// Serve the HTTP/JSON control API; off when unset.
+    #[serde(default)]
+    pub http_api: Option<HttpApiCfg>,
     #[serde(default)]
     pub controllers: Vec<ControllerCfg>,
     #[serde(default)]
@@ -25,9 +90,31 @@ pub struct Config {
     #[serde(default)]
     pub mappings: Vec<MappingCfg>,
     #[serde(default)]
+    pub fan_groups: Vec<FanGroupCfg>,
+    #[serde(default)]
+    pub thresholds: Vec<ThresholdCfg>,
+    /// Local-time windows that cap or override the fan speeds; the first matching one wins.
+    #[serde(default)]
+    pub schedules: Vec<ScheduleCfg>,
+    /// Classify each sensor as rising, falling or stable and emit `ThermalTrend` on change.
+    #[serde(default)]
+    pub trend: Option<TrendCfg>,
+    #[serde(default)]
     pub colors: Vec<ColorCfg>,
+    /// Entry of `colors` applied to every fan no `color_mappings` entry covers.
+    #[serde(default)]
+    pub default_color: Option<String>,
+    /// Entry of `colors` every fan shows while a sensor is at its critical limit.
+    #[serde(default)]
+    pub alert_color: Option<String>,
+    #[serde(default)]
+    pub color_apply_mode: ColorApplyMode,
     #[serde(default)]
     pub color_mappings: Vec<ColorMappingCfg>,
+    /// Reject a config that maps a fan to two different colors, instead of keeping the
+    /// first entry's color.
+    #[serde(default)]
+    pub strict_color_mappings: bool,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -35,9 +122,31 @@ pub struct Config {
 pub enum ControllerCfg {
     RiingQuad {
         id: String,
+        /// Shown in logs and status output instead of the generated `TTRiingQuad<id>`.
+        #[serde(default)]
+        name: Option<String>,
         usb: UsbSelector,
         #[serde(default)]
         fans: Vec<FanCfg>,
+        /// Curve id every fan of the controller starts on, added to fans that don't list it;
+        /// fans may then leave out `curve`.
+        #[serde(default)]
+        curve: Option<String>,
+        #[serde(default = "defaults::brightness")]
+        brightness: f32,
+        #[serde(default = "defaults::read_rpm")]
+        read_rpm: bool,
+        /// Minimum sum of fan speeds (percent) across this controller's fans.
+        #[serde(default)]
+        airflow_floor: Option<u16>,
+        /// Update this controller's fans every `tick_seconds` instead of the global tick.
+        #[serde(default)]
+        tick_seconds: Option<u16>,
+        #[serde(default)]
+        led_order: LedOrder,
+        /// Init handshake, for firmware revisions that answer it differently.
+        #[serde(default)]
+        init: InitCfg,
     },
 }
 
@@ -45,27 +154,108 @@ pub enum ControllerCfg {
 pub struct FanCfg {
     pub idx: u8,
     pub name: String,
+    /// Defaults to the first entry of `curve`.
+    #[serde(default)]
     pub active_curve: String,
-    // pub curve: HashMap<String, CurveCfg>,
-    pub curve: Vec<String>,
+    #[serde(default)]
+    pub curve: Vec<CurveRef>,
+    /// Switch `active_curve` automatically by temperature of the mapped sensor.
+    #[serde(default)]
+    pub curve_bands: Vec<CurveBandCfg>,
+    /// Never run slower than `curve` asks for at `sensor`'s temperature.
+    #[serde(default)]
+    pub floor: Option<CurveFloorCfg>,
+    /// Spin a standing fan up at 100% before giving it a low speed.
+    #[serde(default)]
+    pub kickstart: Option<KickstartCfg>,
+}
+
+/// A pulse at 100% that gets a standing fan turning before it is set below `below`%,
+/// which it may not start from on its own.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct KickstartCfg {
+    #[serde(default = "defaults::kickstart_below")]
+    pub below: u8,
+    #[serde(default = "defaults::kickstart_ms")]
+    pub ms: u64,
+}
+
+/// A fan's curve: the id of an entry in `curves`, or a curve written out in place.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum CurveRef {
+    Id(String),
+    Inline(CurveCfg),
+}
+
+impl CurveRef {
+    pub fn id(&self) -> String {
+        match self {
+            CurveRef::Id(id) => id.clone(),
+            CurveRef::Inline(curve) => curve.get_id(),
+        }
+    }
+}
+
+impl PartialEq<&str> for CurveRef {
+    fn eq(&self, other: &&str) -> bool {
+        matches!(self, CurveRef::Id(id) if id == other)
+    }
+}
+
+/// A second curve evaluated against another sensor; the fan runs at the higher of the two
+/// speeds.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurveFloorCfg {
+    pub sensor: String,
+    /// Id of an entry in `curves`.
+    pub curve: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CurveBandCfg {
+    /// Lowest temperature of the band.
+    pub from: f32,
+    pub curve: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(tag = "kind", rename_all = "kebab-case")]
 pub enum CurveCfg {
     Constant {
+        #[serde(default)]
         id: String,
         speed: u8,
     },
+    /// `spds` are percent, or target RPM with `rpm: true`.
     StepCurve {
+        #[serde(default)]
         id: String,
         tmps: Vec<f32>,
-        spds: Vec<u8>,
+        spds: Vec<u16>,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        rpm: bool,
     },
     Bezier {
+        #[serde(default)]
+        id: String,
+        points: Vec<Point>,
+    },
+    /// Smooth spline passing through each `(x: temp, y: speed)` point.
+    CatmullRom {
+        #[serde(default)]
         id: String,
         points: Vec<Point>,
     },
+    /// Fan stays off below `off_below`, then ramps from `ramp_start_speed` to `max_speed`.
+    ZeroRpmRamp {
+        #[serde(default)]
+        id: String,
+        off_below: f32,
+        ramp_start_speed: u8,
+        max_temp: f32,
+        max_speed: u8,
+    },
 }
 
 impl CurveCfg {
@@ -74,14 +264,192 @@ impl CurveCfg {
             CurveCfg::Constant { id, .. } => id.clone(),
             CurveCfg::StepCurve { id, .. } => id.clone(),
             CurveCfg::Bezier { id, .. } => id.clone(),
+            CurveCfg::CatmullRom { id, .. } => id.clone(),
+            CurveCfg::ZeroRpmRamp { id, .. } => id.clone(),
+        }
+    }
+
+    /// Whether the curve gives target RPM, which only a calibrated fan can turn into a speed.
+    pub fn is_rpm(&self) -> bool {
+        matches!(self, CurveCfg::StepCurve { rpm: true, .. })
+    }
+
+    fn set_id(&mut self, new: String) {
+        match self {
+            CurveCfg::Constant { id, .. }
+            | CurveCfg::StepCurve { id, .. }
+            | CurveCfg::Bezier { id, .. }
+            | CurveCfg::CatmullRom { id, .. }
+            | CurveCfg::ZeroRpmRamp { id, .. } => *id = new,
         }
     }
+
+    pub fn validate(&self) -> Result<()> {
+        if let CurveCfg::Bezier { id, points } = self {
+            if points.len() != 4 {
+                anyhow::bail!("curve `{id}`: Bezier needs 4 points, got {}", points.len());
+            }
+            // Non-decreasing control x keeps x(t) monotonic, so each temperature has one speed.
+            if points.windows(2).any(|w| w[0].x > w[1].x) || points[0].x >= points[3].x {
+                anyhow::bail!("curve `{id}`: Bezier point x values must be increasing");
+            }
+        }
+        if let CurveCfg::CatmullRom { id, points } = self {
+            if points.len() < 2 {
+                anyhow::bail!("curve `{id}`: Catmull-Rom needs at least 2 points");
+            }
+            if points.windows(2).any(|w| w[0].x >= w[1].x) {
+                anyhow::bail!(
+                    "curve `{id}`: Catmull-Rom point x values must be strictly increasing"
+                );
+            }
+        }
+        if let CurveCfg::StepCurve {
+            id,
+            tmps,
+            spds,
+            rpm,
+        } = self
+        {
+            if tmps.is_empty() || spds.is_empty() {
+                anyhow::bail!("curve `{id}`: tmps and spds must not be empty");
+            }
+            if tmps.len() != spds.len() {
+                anyhow::bail!("curve `{id}`: {} tmps but {} spds", tmps.len(), spds.len());
+            }
+            if let Some(speed) = spds.iter().find(|&&s| !rpm && s > 100) {
+                anyhow::bail!(
+                    "curve `{id}`: speed {speed} is out of range 0-100; set `rpm: true` for RPM targets"
+                );
+            }
+            if let Some(w) = tmps.windows(2).find(|w| w[0] >= w[1]) {
+                anyhow::bail!(
+                    "curve `{id}`: tmps must be strictly increasing, got {} then {}",
+                    w[0],
+                    w[1]
+                );
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MappingCfg {
     pub sensor: String,
     pub targets: Vec<FanTarget>,
+    /// Curve id the targets follow while this sensor sets their speed, instead of their
+    /// active curve.
+    #[serde(default)]
+    pub curve: Option<String>,
+}
+
+/// Fans that always run at the speed computed from `leader`'s curve.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FanGroupCfg {
+    pub leader: FanTarget,
+    pub members: Vec<FanTarget>,
+}
+
+/// Raises `alert` when `sensor` crosses `value` in `direction`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ThresholdCfg {
+    pub sensor: String,
+    pub value: f32,
+    pub direction: Direction,
+    pub alert: String,
+    /// Degrees the sensor must move back past `value` before the alert can fire again.
+    #[serde(default = "defaults::threshold_hysteresis")]
+    pub hysteresis: f32,
+}
+
+/// A daily window, in the daemon's local time, during which every fan is capped at
+/// `max_speed` and/or runs `curve` instead of its own. `to` before `from` wraps past midnight.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleCfg {
+    /// Start of the window, `HH:MM`.
+    pub from: String,
+    /// End of the window, `HH:MM`, exclusive.
+    pub to: String,
+    #[serde(default)]
+    pub max_speed: Option<u8>,
+    /// Id of an entry in `curves`, evaluated at each fan's mapped sensor.
+    #[serde(default)]
+    pub curve: Option<String>,
+}
+
+impl ScheduleCfg {
+    /// Start and end of the window in minutes since midnight.
+    pub fn minutes(&self) -> Result<(u16, u16)> {
+        Ok((parse_time_of_day(&self.from)?, parse_time_of_day(&self.to)?))
+    }
+}
+
+fn parse_time_of_day(time: &str) -> Result<u16> {
+    let (hours, minutes) = time
+        .split_once(':')
+        .and_then(|(h, m)| Some((h.parse::<u16>().ok()?, m.parse::<u16>().ok()?)))
+        .filter(|&(h, m)| h < 24 && m < 60)
+        .ok_or_else(|| anyhow!("`{time}` is not a time of day (HH:MM)"))?;
+    Ok(hours * 60 + minutes)
+}
+
+/// Where the HTTP API listens. It has no authentication, so keep it on loopback unless the
+/// network is trusted.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HttpApiCfg {
+    /// `address:port`.
+    #[serde(default = "defaults::http_bind")]
+    pub bind: String,
+}
+
+/// When the color service writes a fan's color.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorApplyMode {
+    /// On every pass, which also restores colors a replugged controller lost.
+    #[default]
+    Always,
+    /// Only when the fan's color differs from the one last written to it.
+    OnChange,
+}
+
+/// Order in which a fan's LEDs take the color bytes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum LedOrder {
+    Rgb,
+    #[default]
+    Grb,
+    Bgr,
+}
+
+impl LedOrder {
+    /// Puts an RGB color in this byte order.
+    pub fn arrange(self, [red, green, blue]: [u8; 3]) -> (u8, u8, u8) {
+        match self {
+            LedOrder::Rgb => (red, green, blue),
+            LedOrder::Grb => (green, red, blue),
+            LedOrder::Bgr => (blue, green, red),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Direction {
+    Rising,
+    Falling,
+}
+
+/// A sensor is rising or falling when its last `window` history samples moved by more
+/// than `delta` degrees.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct TrendCfg {
+    #[serde(default = "defaults::trend_window")]
+    pub window: usize,
+    #[serde(default = "defaults::trend_delta")]
+    pub delta: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -96,7 +464,468 @@ pub struct FanTarget {
     pub fan_idx: u8,
 }
 
-mod defaults {
+impl Config {
+    pub fn controller_count(&self) -> usize {
+        self.controllers.len()
+    }
+
+    pub fn fan_count(&self) -> usize {
+        self.controllers.iter().map(ControllerCfg::fan_count).sum()
+    }
+
+    /// `active_curve` of a fan, by 1-based controller and channel.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn active_curve(&self, controller: u8, channel: u8) -> Option<&str> {
+        let ControllerCfg::RiingQuad { fans, .. } = self
+            .controllers
+            .get((controller as usize).checked_sub(1)?)?;
+        fans.iter()
+            .find(|fan| fan.idx == channel)
+            .map(|fan| fan.active_curve.as_str())
+    }
+
+    /// Whether `curve` is among the curves of a fan, by 1-based controller and channel.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn fan_has_curve(&self, controller: u8, channel: u8, curve: &str) -> bool {
+        let Some(ControllerCfg::RiingQuad { fans, .. }) = (controller as usize)
+            .checked_sub(1)
+            .and_then(|idx| self.controllers.get(idx))
+        else {
+            return false;
+        };
+        fans.iter()
+            .find(|fan| fan.idx == channel)
+            .is_some_and(|fan| fan.curve.iter().any(|c| c == &curve))
+    }
+
+    /// Whether `curve` names an RPM curve in `curves`. Only a fan's own curves are resolved
+    /// through its calibration; floors, mappings and schedules need percent curves.
+    fn is_rpm_curve(&self, curve: &str) -> bool {
+        self.curves
+            .iter()
+            .any(|c| c.get_id() == curve && c.is_rpm())
+    }
+
+    /// Moves curves defined inline in fans into `curves`, replacing them with their id.
+    /// Inline curves without an id get `<controller>/fan<idx>`, suffixed if already taken.
+    pub fn merge_inline_curves(&mut self) {
+        let mut taken: HashSet<String> = self.curves.iter().map(CurveCfg::get_id).collect();
+        for ctrl in &mut self.controllers {
+            let ControllerCfg::RiingQuad {
+                id: ctrl_id, fans, ..
+            } = ctrl;
+            for fan in fans {
+                for entry in &mut fan.curve {
+                    let CurveRef::Inline(curve) = entry else {
+                        continue;
+                    };
+                    let mut curve = curve.clone();
+                    if curve.get_id().is_empty() {
+                        let base = format!("{ctrl_id}/fan{}", fan.idx);
+                        let id = (1..)
+                            .map(|n| match n {
+                                1 => base.clone(),
+                                n => format!("{base}-{n}"),
+                            })
+                            .find(|id| !taken.contains(id))
+                            .unwrap_or(base);
+                        curve.set_id(id);
+                    }
+                    taken.insert(curve.get_id());
+                    *entry = CurveRef::Id(curve.get_id());
+                    self.curves.push(curve);
+                }
+                if fan.active_curve.is_empty() {
+                    fan.active_curve = fan.curve.first().map(CurveRef::id).unwrap_or_default();
+                }
+            }
+        }
+    }
+
+    /// Rejects curves that would silently misbehave when evaluated.
+    pub fn validate(&self) -> Result<()> {
+        if let Some(ControllerCfg::RiingQuad { id, .. }) =
+            self.controllers.iter().find(|c| c.tick_seconds(1) == 0)
+        {
+            anyhow::bail!("controller `{id}`: tick_seconds must be at least 1");
+        }
+        if self.startup_delay_ms > MAX_STARTUP_DELAY_MS {
+            anyhow::bail!(
+                "startup_delay_ms {} is over {MAX_STARTUP_DELAY_MS}",
+                self.startup_delay_ms
+            );
+        }
+        if !(0.0..=MAX_CURVE_RESOLUTION).contains(&self.curve_resolution) {
+            anyhow::bail!(
+                "curve_resolution {} is out of range 0-{MAX_CURVE_RESOLUTION}",
+                self.curve_resolution
+            );
+        }
+        if let Some(speed) = self.startup_speed.filter(|&s| s > 100) {
+            anyhow::bail!("startup_speed {speed} is out of range 0-100");
+        }
+        if !(self.broadcast_min_change >= 0.0 && self.broadcast_min_change.is_finite()) {
+            anyhow::bail!(
+                "broadcast_min_change {} must be 0 or more",
+                self.broadcast_min_change
+            );
+        }
+        if self.panic_speed > 100 {
+            anyhow::bail!("panic_speed {} is out of range 0-100", self.panic_speed);
+        }
+        if let Some(timeout) = self.watchdog_timeout {
+            let slowest = self
+                .controllers
+                .iter()
+                .map(|c| c.tick_seconds(self.tick_seconds))
+                .chain([self.tick_seconds])
+                .max()
+                .unwrap_or_default();
+            if timeout <= slowest as u64 {
+                anyhow::bail!(
+                    "watchdog_timeout {timeout} must be longer than the slowest tick ({slowest}s)"
+                );
+            }
+        }
+        if let Some(http) = &self.http_api
+            && let Err(e) = http.bind.parse::<SocketAddr>()
+        {
+            anyhow::bail!("http_api bind `{}` is not an address:port: {e}", http.bind);
+        }
+        if let Some(trend) = self.trend
+            && (trend.window < 2 || trend.window > self.history_length)
+        {
+            anyhow::bail!(
+                "trend window {} must be between 2 and history_length ({})",
+                trend.window,
+                self.history_length
+            );
+        }
+        if self.strict_color_mappings {
+            let mut claimed: HashMap<(u8, u8), &str> = HashMap::new();
+            for entry in &self.color_mappings {
+                for target in &entry.targets {
+                    let fan = (target.controller, target.fan_idx);
+                    let first = *claimed.entry(fan).or_insert(&entry.color);
+                    if first != entry.color {
+                        anyhow::bail!(
+                            "color_mappings: fan {}:{} is mapped to both `{first}` and `{}`",
+                            fan.0,
+                            fan.1,
+                            entry.color
+                        );
+                    }
+                }
+            }
+        }
+        if let Some(name) = &self.default_color
+            && !self.colors.iter().any(|c| &c.color == name)
+        {
+            anyhow::bail!("default_color `{name}` is not defined in `colors`");
+        }
+        if let Some(name) = &self.alert_color
+            && !self.colors.iter().any(|c| &c.color == name)
+        {
+            anyhow::bail!("alert_color `{name}` is not defined in `colors`");
+        }
+        if let Some(curve) = &self.fallback_curve
+            && !self.curves.iter().any(|c| &c.get_id() == curve)
+        {
+            anyhow::bail!("fallback_curve `{curve}` is not defined in `curves`");
+        }
+        for ctrl in &self.controllers {
+            let ControllerCfg::RiingQuad {
+                id,
+                fans,
+                curve,
+                init,
+                ..
+            } = ctrl;
+            if let Some(curve) = curve
+                && !self.curves.iter().any(|c| &c.get_id() == curve)
+            {
+                anyhow::bail!("controller `{id}`: curve `{curve}` is not defined in `curves`");
+            }
+            if let Some(fan) = fans.iter().find(|fan| fan.curve.is_empty()) {
+                anyhow::bail!(
+                    "controller `{id}`: fan {} needs a `curve`, or the controller one",
+                    fan.idx
+                );
+            }
+            if let Some(packet) = &init.packet
+                && (packet.is_empty() || packet.len() > MAX_INIT_PACKET)
+            {
+                anyhow::bail!(
+                    "controller `{id}`: init packet must be 1-{MAX_INIT_PACKET} bytes, got {}",
+                    packet.len()
+                );
+            }
+            for fan in fans {
+                let Some(kick) = fan.kickstart else { continue };
+                if kick.below == 0 || kick.below > 100 {
+                    anyhow::bail!(
+                        "controller `{id}`: fan {} kickstart below {} is out of range 1-100",
+                        fan.idx,
+                        kick.below
+                    );
+                }
+                if kick.ms > MAX_KICKSTART_MS {
+                    anyhow::bail!(
+                        "controller `{id}`: fan {} kickstart of {} ms is over {MAX_KICKSTART_MS}",
+                        fan.idx,
+                        kick.ms
+                    );
+                }
+            }
+            let missing = fans
+                .iter()
+                .filter_map(|fan| fan.floor.as_ref())
+                .find(|floor| !self.curves.iter().any(|c| c.get_id() == floor.curve));
+            if let Some(floor) = missing {
+                anyhow::bail!(
+                    "controller `{id}`: floor curve `{}` is not defined in `curves`",
+                    floor.curve
+                );
+            }
+            let rpm = fans
+                .iter()
+                .filter_map(|fan| fan.floor.as_ref())
+                .find(|floor| self.is_rpm_curve(&floor.curve));
+            if let Some(floor) = rpm {
+                anyhow::bail!(
+                    "controller `{id}`: floor curve `{}` targets RPM, which only a fan's own curves can",
+                    floor.curve
+                );
+            }
+            let unknown = fans
+                .iter()
+                .filter_map(|fan| fan.floor.as_ref())
+                .find(|floor| !self.sensors.iter().any(|s| s.id() == floor.sensor));
+            if let Some(floor) = unknown {
+                anyhow::bail!(
+                    "controller `{id}`: floor sensor `{}` is not defined in `sensors`",
+                    floor.sensor
+                );
+            }
+        }
+        for mapping in &self.mappings {
+            if let Some(curve) = &mapping.curve {
+                if !self.curves.iter().any(|c| &c.get_id() == curve) {
+                    anyhow::bail!(
+                        "sensor `{}` mapping: curve `{curve}` is not defined in `curves`",
+                        mapping.sensor
+                    );
+                }
+                if self.is_rpm_curve(curve) {
+                    anyhow::bail!(
+                        "sensor `{}` mapping: curve `{curve}` targets RPM, which only a fan's own curves can",
+                        mapping.sensor
+                    );
+                }
+            }
+            let fanless = mapping.targets.iter().find_map(|t| {
+                let ctrl = self
+                    .controllers
+                    .get((t.controller as usize).checked_sub(1)?)?;
+                (ctrl.fan_count() == 0).then_some(ctrl)
+            });
+            if let Some(ControllerCfg::RiingQuad { id, .. }) = fanless {
+                anyhow::bail!(
+                    "sensor `{}` is mapped to controller `{id}`, which has no fans",
+                    mapping.sensor
+                );
+            }
+        }
+        // A built-in kind only ends up as `Custom` when its own fields didn't parse.
+        if let Some(SensorCfg::Custom { id, kind, .. }) = self.sensors.iter().find(|s| {
+            matches!(s, SensorCfg::Custom { kind, .. } if BUILTIN_SENSOR_KINDS.contains(&kind.as_str()))
+        }) {
+            anyhow::bail!("sensor `{id}`: missing or invalid fields for kind `{kind}`");
+        }
+        for schedule in &self.schedules {
+            let window = format!("schedule {}-{}", schedule.from, schedule.to);
+            let (from, to) = schedule.minutes().with_context(|| window.clone())?;
+            if from == to {
+                anyhow::bail!("{window}: from and to must differ");
+            }
+            match (schedule.max_speed, &schedule.curve) {
+                (None, None) => anyhow::bail!("{window}: needs max_speed or curve"),
+                (Some(speed), _) if speed > 100 => {
+                    anyhow::bail!("{window}: max_speed {speed} is out of range 0-100")
+                }
+                (_, Some(curve)) if !self.curves.iter().any(|c| &c.get_id() == curve) => {
+                    anyhow::bail!("{window}: curve `{curve}` is not defined in `curves`")
+                }
+                (_, Some(curve)) if self.is_rpm_curve(curve) => {
+                    anyhow::bail!(
+                        "{window}: curve `{curve}` targets RPM, which only a fan's own curves can"
+                    )
+                }
+                _ => {}
+            }
+        }
+        if self.curves.iter().any(|c| c.get_id().is_empty()) {
+            anyhow::bail!("every entry in `curves` needs an id");
+        }
+        // Inline curves with an explicit id land in `curves` too, so this also catches one
+        // reusing a global id.
+        let mut ids = HashSet::new();
+        if let Some(id) = self
+            .curves
+            .iter()
+            .map(CurveCfg::get_id)
+            .find(|id| !ids.insert(id.clone()))
+        {
+            anyhow::bail!("curve id `{id}` is defined more than once");
+        }
+        self.curves.iter().try_for_each(CurveCfg::validate)
+    }
+
+    /// Top-level sections whose value differs between this config and `pending`, in field
+    /// order; [`restart_sections`] tells which of them a reload can't apply.
+    pub fn analyze_changes(&self, pending: &Config) -> Result<Vec<String>> {
+        let sections = |cfg: &Config| match serde_yaml::to_value(cfg).context("serialize YAML")? {
+            serde_yaml::Value::Mapping(map) => Ok(map),
+            _ => Err(anyhow::anyhow!("config did not serialize to a mapping")),
+        };
+        let (current, pending) = (sections(self)?, sections(pending)?);
+        Ok(current
+            .keys()
+            .chain(pending.keys().filter(|key| !current.contains_key(*key)))
+            .filter(|key| current.get(*key) != pending.get(*key))
+            .filter_map(|key| key.as_str().map(String::from))
+            .collect())
+    }
+
+    /// Likely mistakes that still make a runnable config: fans whose `active_curve` isn't
+    /// among their curves, and fans given a color but no sensor, which never change speed.
+    pub fn lint(&self) -> Vec<String> {
+        let mut issues = Vec::new();
+        for (idx, ctrl) in self.controllers.iter().enumerate() {
+            let ControllerCfg::RiingQuad { id, fans, .. } = ctrl;
+            for fan in fans {
+                if !fan.curve.iter().any(|c| c.id() == fan.active_curve) {
+                    issues.push(format!(
+                        "controller `{id}`: fan {} active_curve `{}` is not in its curve list",
+                        fan.idx, fan.active_curve
+                    ));
+                }
+                let target = |t: &FanTarget| (t.controller, t.fan_idx) == (idx as u8 + 1, fan.idx);
+                let colored = self
+                    .color_mappings
+                    .iter()
+                    .any(|m| m.targets.iter().any(target));
+                let mapped = self.mappings.iter().any(|m| m.targets.iter().any(target));
+                if colored && !mapped {
+                    issues.push(format!(
+                        "fan {}:{} has a color mapping but no sensor mapping, so its speed is never updated",
+                        idx + 1,
+                        fan.idx
+                    ));
+                }
+            }
+        }
+        issues
+    }
+}
+
+impl ControllerCfg {
+    pub fn fan_count(&self) -> usize {
+        match self {
+            ControllerCfg::RiingQuad { fans, .. } => fans.len(),
+        }
+    }
+
+    /// `controller `id` (VID:PID)`, for errors about the device.
+    pub fn describe(&self) -> String {
+        match self {
+            ControllerCfg::RiingQuad { id, usb, .. } => {
+                format!("controller `{id}` ({:04X}:{:04X})", usb.vid, usb.pid)
+            }
+        }
+    }
+
+    /// The configured `name`, or `TTRiingQuad<id>` without one.
+    pub fn name(&self) -> String {
+        match self {
+            ControllerCfg::RiingQuad { id, name, .. } => {
+                name.clone().unwrap_or_else(|| format!("TTRiingQuad{id}"))
+            }
+        }
+    }
+
+    /// Applies the controller's `curve` to its fans: appended to their curves where missing
+    /// and made their `active_curve`.
+    pub fn apply_curve(&mut self) {
+        let ControllerCfg::RiingQuad { fans, curve, .. } = self;
+        let Some(curve) = curve else {
+            return;
+        };
+        for fan in fans {
+            if !fan.curve.iter().any(|c| c == &curve.as_str()) {
+                fan.curve.push(CurveRef::Id(curve.clone()));
+            }
+            fan.active_curve = curve.clone();
+        }
+    }
+
+    /// Seconds between this controller's fan updates, falling back to `default`.
+    pub fn tick_seconds(&self, default: u16) -> u16 {
+        match self {
+            ControllerCfg::RiingQuad { tick_seconds, .. } => tick_seconds.unwrap_or(default),
+        }
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            version: 1,
+            profile: defaults::profile(),
+            tick_seconds: defaults::tick_seconds(),
+            enable_broadcast: defaults::enable_broadcast(),
+            broadcast_interval: defaults::broadcast_interval(),
+            broadcast_min_change: defaults::broadcast_min_change(),
+            history_length: defaults::history_length(),
+            trace_hid: false,
+            fallback_curve: None,
+            usb_open_attempts: defaults::usb_open_attempts(),
+            usb_open_delay_ms: defaults::usb_open_delay_ms(),
+            keep_rejected_config: false,
+            startup_delay_ms: 0,
+            curve_resolution: 0.0,
+            startup_speed: None,
+            calibration_file: None,
+            state_file: None,
+            dedicated_monitoring_thread: false,
+            watchdog_timeout: None,
+            panic_speed: defaults::panic_speed(),
+            curve_band_hysteresis: defaults::curve_band_hysteresis(),
+            log_max_kb: defaults::log_max_kb(),
+            log_keep: defaults::log_keep(),
+            http_api: None,
+            controllers: vec![],
+            curves: vec![],
+            sensors: vec![],
+            mappings: vec![],
+            fan_groups: vec![],
+            thresholds: vec![],
+            schedules: vec![],
+            trend: None,
+            colors: vec![],
+            default_color: None,
+            alert_color: None,
+            color_apply_mode: ColorApplyMode::default(),
+            color_mappings: vec![],
+            strict_color_mappings: false,
+        }
+    }
+}
+
+pub(crate) mod defaults {
+    pub fn profile() -> String {
+        String::from("default")
+    }
     pub fn tick_seconds() -> u16 {
         2
     }
@@ -106,6 +935,79 @@ mod defaults {
     pub fn broadcast_interval() -> u16 {
         2
     }
+    pub fn broadcast_min_change() -> f32 {
+        0.2
+    }
+    pub fn history_length() -> usize {
+        60
+    }
+    pub fn usb_open_attempts() -> u32 {
+        5
+    }
+    pub fn usb_open_delay_ms() -> u64 {
+        1000
+    }
+    pub fn panic_speed() -> u8 {
+        100
+    }
+    pub fn curve_band_hysteresis() -> f32 {
+        2.0
+    }
+    pub fn log_max_kb() -> u64 {
+        1024
+    }
+    pub fn log_keep() -> usize {
+        3
+    }
+    pub fn kickstart_below() -> u8 {
+        30
+    }
+    pub fn kickstart_ms() -> u64 {
+        500
+    }
+    pub fn http_bind() -> String {
+        String::from("127.0.0.1:8787")
+    }
+    pub fn threshold_hysteresis() -> f32 {
+        2.0
+    }
+    pub fn trend_window() -> usize {
+        5
+    }
+    pub fn trend_delta() -> f32 {
+        1.0
+    }
+    pub fn max_age() -> u64 {
+        30
+    }
+    pub fn brightness() -> f32 {
+        1.0
+    }
+    pub fn read_rpm() -> bool {
+        true
+    }
+    pub fn init_status() -> u8 {
+        0xFC
+    }
+}
+
+/// The packet a controller is initialized with and the status it has to answer.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct InitCfg {
+    #[serde(default = "defaults::init_status")]
+    pub status: u8,
+    /// Sent instead of `FE 33`, without the leading report id.
+    #[serde(default)]
+    pub packet: Option<Vec<u8>>,
+}
+
+impl Default for InitCfg {
+    fn default() -> Self {
+        Self {
+            status: defaults::init_status(),
+            packet: None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -124,14 +1026,156 @@ pub enum SensorCfg {
         chip: String,
         feature: String,
     },
+    /// Value pushed by another program via `SetExternalTemperature`.
+    External {
+        id: String,
+        /// Seconds after which the last pushed value is considered stale.
+        #[serde(default = "defaults::max_age")]
+        max_age: u64,
+    },
+    /// `input` (e.g. `temp1_input`) of the `/sys/class/hwmon` device named `hwmon_name`.
+    Hwmon {
+        id: String,
+        hwmon_name: String,
+        input: String,
+    },
+    /// Temperature of a drive, by NVMe controller (`nvme0`) or block device (`sda`) name.
+    Drive { id: String, device: String },
+    /// Any other `kind`, built by the backend registered for it in
+    /// [`crate::sensors::SensorRegistry`]; the entry's other keys end up in `params`.
+    #[serde(untagged)]
+    Custom {
+        kind: String,
+        id: String,
+        #[serde(flatten)]
+        params: serde_yaml::Mapping,
+    },
 }
 
+/// Payload of a Riing HID output report, after the report id.
+const MAX_INIT_PACKET: usize = 64;
+
+/// Longest `startup_delay_ms`; a bus that needs more has other problems.
+const MAX_STARTUP_DELAY_MS: u64 = 60_000;
+
+/// Coarsest `curve_resolution`; beyond this the steps are audible.
+const MAX_CURVE_RESOLUTION: f32 = 5.0;
+
+/// Longest kickstart pulse; the fan runs at 100% for its whole length.
+const MAX_KICKSTART_MS: u64 = 5_000;
+
+const BUILTIN_SENSOR_KINDS: [&str; 4] = ["lm-sensors", "external", "hwmon", "drive"];
+
+/// Top-level sections a reload applies while the daemon runs: the fan loops take the
+/// curves, mappings, groups and schedules, the color service the colors, and the rest is
+/// read where it is used. Changes to any other section take effect after a restart.
+pub const HOT_SECTIONS: [&str; 13] = [
+    "profile",
+    "keep_rejected_config",
+    "curve_band_hysteresis",
+    "curves",
+    "mappings",
+    "fan_groups",
+    "schedules",
+    "colors",
+    "default_color",
+    "alert_color",
+    "color_apply_mode",
+    "color_mappings",
+    "strict_color_mappings",
+];
+
+impl SensorCfg {
+    pub fn id(&self) -> &str {
+        match self {
+            SensorCfg::LmSensors { id, .. }
+            | SensorCfg::External { id, .. }
+            | SensorCfg::Hwmon { id, .. }
+            | SensorCfg::Drive { id, .. }
+            | SensorCfg::Custom { id, .. } => id,
+        }
+    }
+
+    /// The `kind` tag the entry was written with.
+    pub fn kind(&self) -> &str {
+        match self {
+            SensorCfg::LmSensors { .. } => "lm-sensors",
+            SensorCfg::External { .. } => "external",
+            SensorCfg::Hwmon { .. } => "hwmon",
+            SensorCfg::Drive { .. } => "drive",
+            SensorCfg::Custom { kind, .. } => kind,
+        }
+    }
+}
+
+/// A named color, given either as `rgb: [r, g, b]` or as `hex: "#RRGGBB"`.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(try_from = "RawColorCfg")]
 pub struct ColorCfg {
     pub color: String,
     pub rgb: [u8; 3],
 }
 
+#[derive(Deserialize)]
+struct RawColorCfg {
+    color: String,
+    /// Wider than `u8` so out-of-range components get an error naming the color.
+    rgb: Option<Vec<i64>>,
+    hex: Option<String>,
+}
+
+impl TryFrom<RawColorCfg> for ColorCfg {
+    type Error = anyhow::Error;
+
+    fn try_from(raw: RawColorCfg) -> Result<Self> {
+        let rgb = match (raw.rgb, raw.hex) {
+            (Some(rgb), None) => parse_rgb(&raw.color, &rgb)?,
+            (None, Some(hex)) => parse_hex(&hex)?,
+            (Some(_), Some(_)) => anyhow::bail!("color `{}`: set rgb or hex, not both", raw.color),
+            (None, None) => anyhow::bail!("color `{}`: missing rgb or hex", raw.color),
+        };
+        Ok(Self {
+            color: raw.color,
+            rgb,
+        })
+    }
+}
+
+/// Checks that `rgb` of color `color` has three components in `0..=255`.
+fn parse_rgb(color: &str, rgb: &[i64]) -> Result<[u8; 3]> {
+    let [red, green, blue] = rgb else {
+        anyhow::bail!(
+            "color `{color}`: rgb needs 3 components [r, g, b], got {}",
+            rgb.len()
+        );
+    };
+    let component = |name: &str, value: i64| {
+        u8::try_from(value).map_err(|_| {
+            anyhow::anyhow!("color `{color}`: rgb {name} component {value} is outside 0-255")
+        })
+    };
+    Ok([
+        component("red", *red)?,
+        component("green", *green)?,
+        component("blue", *blue)?,
+    ])
+}
+
+/// Parses `#RRGGBB`, `RRGGBB` or the `#RGB` shorthand.
+pub fn parse_hex(hex: &str) -> Result<[u8; 3]> {
+    let digits = hex.strip_prefix('#').unwrap_or(hex);
+    if !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        anyhow::bail!("invalid hex color `{hex}`");
+    }
+    let expanded: String = match digits.len() {
+        3 => digits.chars().flat_map(|c| [c, c]).collect(),
+        6 => digits.to_string(),
+        _ => anyhow::bail!("invalid hex color `{hex}`: expected #RRGGBB or #RGB"),
+    };
+    let channel = |i: usize| u8::from_str_radix(&expanded[i..i + 2], 16);
+    Ok([channel(0)?, channel(2)?, channel(4)?])
+}
+
 fn locate_config() -> Result<PathBuf> {
     // 2) ENV
     if let Ok(env_path) = env::var("TT_RIINGD_CONFIG") {
@@ -158,21 +1202,577 @@ fn locate_config() -> Result<PathBuf> {
     anyhow::bail!("файл конфигурации не найден ни в одном из стандартных мест")
 }
 
-pub fn load(path: Option<PathBuf>) -> Result<Config> {
-    let path = path.unwrap_or_else(|| locate_config().expect("Failed to load config"));
+pub fn resolve(path: Option<PathBuf>) -> Result<PathBuf> {
+    path.map_or_else(locate_config, Ok)
+}
+
+/// Whether `--config` asks for the config on stdin.
+pub fn is_stdin(path: &Path) -> bool {
+    path == Path::new("-")
+}
+
+/// Directory for the daemon's own files when the config came from stdin.
+pub const STDIN_DATA_DIR: &str = "/var/lib/tt_riingd";
+
+/// `name` next to the config file, or in [`STDIN_DATA_DIR`] for a config piped in on
+/// stdin, which has no directory to sit next to.
+pub fn data_file(config_path: &Path, name: &str) -> PathBuf {
+    if is_stdin(config_path) {
+        Path::new(STDIN_DATA_DIR).join(name)
+    } else {
+        config_path.with_file_name(name)
+    }
+}
+
+pub fn load(path: &Path) -> Result<Config> {
     info!("Used config: {}", path.display());
-    let txt = fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
-    let cfg: Config = serde_yaml::from_str(&txt).context("parse YAML")?;
+    let file = fs::File::open(path).with_context(|| format!("reading {}", path.display()))?;
+    read(file, &path.display().to_string())
+}
+
+/// Reads, parses and validates a config from `reader`; `source` names it in errors.
+pub fn read(mut reader: impl Read, source: &str) -> Result<Config> {
+    let mut txt = String::new();
+    reader
+        .read_to_string(&mut txt)
+        .with_context(|| format!("reading {source}"))?;
+    let cfg = parse(&txt).with_context(|| format!("parsing {source}"))?;
+    check(cfg).with_context(|| format!("validating {source}"))
+}
+
+/// Rejects configs the daemon cannot run: unknown versions and invalid settings.
+pub fn check(cfg: Config) -> Result<Config> {
     if cfg.version != 1 {
         anyhow::bail!("unsupported config version {}", cfg.version);
     }
+    cfg.validate()?;
+    for issue in cfg.lint() {
+        warn!("Config: {issue}");
+    }
     Ok(cfg)
 }
 
+pub fn parse(txt: &str) -> Result<Config> {
+    let mut cfg: Config = serde_yaml::from_str(txt).map_err(|e| parse_error(txt, &e))?;
+    cfg.merge_inline_curves();
+    cfg.controllers
+        .iter_mut()
+        .for_each(ControllerCfg::apply_curve);
+    Ok(cfg)
+}
+
+fn parse_error(txt: &str, err: &serde_yaml::Error) -> anyhow::Error {
+    let Some(loc) = err.location() else {
+        return anyhow!("parse YAML: {err}");
+    };
+    let snippet = txt
+        .lines()
+        .nth(loc.line().saturating_sub(1))
+        .unwrap_or_default();
+    anyhow!(
+        "parse YAML: {err}\n  --> line {}, column {}\n   | {snippet}\n   | {:>col$}",
+        loc.line(),
+        loc.column(),
+        "^",
+        col = loc.column(),
+    )
+}
+
+/// Sections of `changed`, from [`Config::analyze_changes`], outside [`HOT_SECTIONS`].
+pub fn restart_sections(changed: &[String]) -> Vec<&str> {
+    changed
+        .iter()
+        .map(String::as_str)
+        .filter(|section| !HOT_SECTIONS.contains(section))
+        .collect()
+}
+
+pub fn to_yaml(cfg: &Config) -> Result<String> {
+    serde_yaml::to_string(cfg).context("serialize YAML")
+}
+
 #[allow(dead_code)]
 pub fn save(path: &Path, cfg: &Config) -> Result<()> {
     let tmp = path.with_extension("yml.tmp");
-    fs::write(&tmp, serde_yaml::to_string(cfg)?)?;
+    fs::write(&tmp, to_yaml(cfg)?)?;
     fs::rename(tmp, path)?;
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::{
+        Config, ControllerCfg, CurveCfg, HOT_SECTIONS, KickstartCfg, MappingCfg, SensorCfg,
+        data_file, is_stdin, parse, parse_hex, read, restart_sections, to_yaml,
+    };
+
+    const SAMPLE: &str = r#"
+version: 1
+controllers:
+  - id: "quad-1"
+    kind: riing-quad
+    usb: { vid: 0x264A, pid: 0x232B }
+    fans:
+      - { idx: 1, name: "Front", active_curve: "Silent", curve: ["Silent"] }
+      - { idx: 2, name: "Rear", active_curve: "Silent", curve: ["Silent"] }
+  - id: "quad-2"
+    kind: riing-quad
+    usb: { vid: 0x264A, pid: 0x232C }
+    fans:
+      - { idx: 1, name: "Top", active_curve: "Silent", curve: ["Silent"] }
+curves:
+  - { id: "Silent", kind: constant, speed: 30 }
+"#;
+
+    #[test]
+    fn counts_and_default_profile() {
+        let cfg: Config = serde_yaml::from_str(SAMPLE).unwrap();
+        assert_eq!(cfg.profile, "default");
+        assert_eq!(cfg.controller_count(), 2);
+        assert_eq!(cfg.fan_count(), 3);
+    }
+
+    #[test]
+    fn parse_error_reports_line_and_snippet() {
+        let broken = "version: 1\ntick_seconds: 2\ncontrollers: [\n  - id: oops\n";
+        let msg = parse(broken).unwrap_err().to_string();
+        assert!(msg.contains("line "), "no line number in: {msg}");
+        assert!(msg.contains("column "), "no column in: {msg}");
+
+        let wrong_type = "version: 1\ntick_seconds: fast\n";
+        let msg = parse(wrong_type).unwrap_err().to_string();
+        assert!(msg.contains("line 2"), "wrong line in: {msg}");
+        assert!(msg.contains("tick_seconds: fast"), "no snippet in: {msg}");
+    }
+
+    #[test]
+    fn exported_yaml_round_trips() {
+        let cfg = parse(SAMPLE).unwrap();
+        let exported = to_yaml(&cfg).unwrap();
+        let reparsed = parse(&exported).unwrap();
+        assert_eq!(reparsed.fan_count(), cfg.fan_count());
+        assert_eq!(reparsed.curves.len(), cfg.curves.len());
+        assert_eq!(to_yaml(&reparsed).unwrap(), exported);
+    }
+
+    fn step(tmps: &[f32], spds: &[u16]) -> Config {
+        Config {
+            curves: vec![CurveCfg::StepCurve {
+                id: String::from("Steps"),
+                tmps: tmps.to_vec(),
+                spds: spds.to_vec(),
+                rpm: false,
+            }],
+            ..Config::default()
+        }
+    }
+
+    #[test]
+    fn step_curve_validation() {
+        step(&[30.0, 50.0, 70.0], &[20, 40, 100])
+            .validate()
+            .unwrap();
+
+        let err = |cfg: Config| cfg.validate().unwrap_err().to_string();
+        let msg = err(step(&[60.0, 40.0], &[20, 40]));
+        assert!(
+            msg.contains("`Steps`") && msg.contains("strictly increasing"),
+            "{msg}"
+        );
+        let msg = err(step(&[40.0, 40.0], &[20, 40]));
+        assert!(msg.contains("strictly increasing"), "{msg}");
+        let msg = err(step(&[30.0, 50.0], &[20]));
+        assert!(msg.contains("`Steps`: 2 tmps but 1 spds"), "{msg}");
+        let msg = err(step(&[30.0, 50.0], &[20, 1200]));
+        assert!(msg.contains("speed 1200 is out of range 0-100"), "{msg}");
+        let msg = err(step(&[], &[]));
+        assert!(msg.contains("`Steps`") && msg.contains("empty"), "{msg}");
+    }
+
+    #[test]
+    fn bezier_validation() {
+        let bezier = |points| Config {
+            curves: vec![CurveCfg::bezier("Smooth", points)],
+            ..Config::default()
+        };
+        bezier([(30.0, 20.0), (50.0, 20.0), (50.0, 100.0), (80.0, 100.0)])
+            .validate()
+            .unwrap();
+
+        let msg = bezier([(30.0, 20.0), (70.0, 20.0), (40.0, 100.0), (80.0, 100.0)])
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.contains("`Smooth`") && msg.contains("increasing"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn catmull_rom_validation() {
+        let spline = |points: &[(f32, f32)]| Config {
+            curves: vec![CurveCfg::catmull_rom("Smooth", points)],
+            ..Config::default()
+        };
+        spline(&[(30.0, 20.0), (60.0, 50.0), (80.0, 100.0)])
+            .validate()
+            .unwrap();
+        assert!(spline(&[(30.0, 20.0)]).validate().is_err());
+        let msg = spline(&[(30.0, 20.0), (30.0, 50.0)])
+            .validate()
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("strictly increasing"), "{msg}");
+    }
+
+    #[test]
+    fn hex_colors() {
+        assert_eq!(parse_hex("#0000FF").unwrap(), [0, 0, 255]);
+        assert_eq!(parse_hex("ff8000").unwrap(), [255, 128, 0]);
+        assert_eq!(parse_hex("#0af").unwrap(), [0, 170, 255]);
+        assert!(parse_hex("#12345").is_err());
+        assert!(parse_hex("#GG0000").is_err());
+        assert!(parse_hex("#+1+1+1").is_err());
+
+        let cfg = parse(
+            "version: 1\ncolors:\n  - { color: blue, hex: \"#0000FF\" }\n  - { color: red, rgb: [255, 0, 0] }\n",
+        )
+        .unwrap();
+        assert_eq!(cfg.colors[0].rgb, [0, 0, 255]);
+        assert_eq!(cfg.colors[1].rgb, [255, 0, 0]);
+
+        let msg = parse("version: 1\ncolors:\n  - { color: bad, hex: \"#00ZZ00\" }\n")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("invalid hex color `#00ZZ00`"), "{msg}");
+        let msg = parse("version: 1\ncolors:\n  - { color: both, rgb: [0, 0, 0], hex: \"000\" }\n")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("not both"), "{msg}");
+    }
+
+    #[test]
+    fn config_reads_from_any_reader() {
+        let yaml = "version: 1\nprofile: piped\ntick_seconds: 4\n";
+        let cfg = read(yaml.as_bytes(), "stdin").unwrap();
+        assert_eq!(cfg.profile, "piped");
+        assert_eq!(cfg.tick_seconds, 4);
+
+        let msg = format!(
+            "{:#}",
+            read("version: 2\n".as_bytes(), "stdin").unwrap_err()
+        );
+        assert!(msg.starts_with("validating stdin"), "{msg}");
+        assert!(is_stdin(Path::new("-")));
+        assert!(!is_stdin(Path::new("./-")));
+        assert_eq!(
+            data_file(Path::new("-"), "state.json"),
+            Path::new("/var/lib/tt_riingd/state.json")
+        );
+        assert_eq!(
+            data_file(Path::new("/etc/tt_riingd/config.yml"), "state.json"),
+            Path::new("/etc/tt_riingd/state.json")
+        );
+    }
+
+    #[test]
+    fn rgb_components_are_range_checked() {
+        let cfg = parse("version: 1\ncolors:\n  - { color: teal, rgb: [0, 128, 128] }\n").unwrap();
+        assert_eq!(cfg.colors[0].rgb, [0, 128, 128]);
+
+        let msg = parse("version: 1\ncolors:\n  - { color: hot, rgb: [300, 0, 0] }\n")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.contains("color `hot`: rgb red component 300 is outside 0-255"),
+            "{msg}"
+        );
+        let msg = parse("version: 1\ncolors:\n  - { color: cold, rgb: [0, 0, -1] }\n")
+            .unwrap_err()
+            .to_string();
+        assert!(msg.contains("rgb blue component -1"), "{msg}");
+        let msg = parse("version: 1\ncolors:\n  - { color: short, rgb: [0, 0] }\n")
+            .unwrap_err()
+            .to_string();
+        assert!(
+            msg.contains("color `short`: rgb needs 3 components [r, g, b], got 2"),
+            "{msg}"
+        );
+    }
+
+    #[test]
+    fn inline_curves_join_the_curve_library() {
+        let cfg = parse(
+            r#"
+version: 1
+controllers:
+  - id: "quad-1"
+    kind: riing-quad
+    usb: { vid: 0x264A, pid: 0x232B }
+    fans:
+      - { idx: 1, name: "Front", curve: [{ kind: constant, speed: 40 }, "Silent"] }
+      - idx: 2
+        name: "Rear"
+        active_curve: "Silent"
+        curve: ["Silent", { id: "Ramp", kind: step-curve, tmps: [30.0, 60.0], spds: [20, 80] }]
+curves:
+  - { id: "Silent", kind: constant, speed: 30 }
+  - { id: "quad-1/fan1", kind: constant, speed: 50 }
+"#,
+        )
+        .unwrap();
+        cfg.validate().unwrap();
+
+        let ControllerCfg::RiingQuad { fans, .. } = &cfg.controllers[0];
+        assert_eq!(fans[0].curve, vec!["quad-1/fan1-2", "Silent"]);
+        assert_eq!(fans[0].active_curve, "quad-1/fan1-2");
+        assert_eq!(fans[1].curve, vec!["Silent", "Ramp"]);
+        assert_eq!(fans[1].active_curve, "Silent");
+
+        let ids: Vec<_> = cfg.curves.iter().map(CurveCfg::get_id).collect();
+        assert_eq!(ids, ["Silent", "quad-1/fan1", "quad-1/fan1-2", "Ramp"]);
+        assert!(matches!(
+            cfg.curves[2],
+            CurveCfg::Constant { speed: 40, .. }
+        ));
+
+        let clash = parse(
+            r#"
+version: 1
+controllers:
+  - id: "quad-1"
+    kind: riing-quad
+    usb: { vid: 0x264A, pid: 0x232B }
+    fans:
+      - { idx: 1, name: "Front", curve: [{ id: "Silent", kind: constant, speed: 60 }] }
+curves:
+  - { id: "Silent", kind: constant, speed: 30 }
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            clash.validate().unwrap_err().to_string(),
+            "curve id `Silent` is defined more than once"
+        );
+    }
+
+    #[test]
+    fn controller_curve_applies_to_every_fan() {
+        let yaml = |curve: &str| {
+            format!(
+                r#"
+version: 1
+controllers:
+  - id: "quad-1"
+    kind: riing-quad
+    usb: {{ vid: 0x264A, pid: 0x232B }}
+    curve: "{curve}"
+    fans:
+      - {{ idx: 1, name: "Front" }}
+      - {{ idx: 2, name: "Rear", active_curve: "Silent", curve: ["Silent", "Loud"] }}
+curves:
+  - {{ id: "Silent", kind: constant, speed: 30 }}
+  - {{ id: "Loud", kind: constant, speed: 90 }}
+"#
+            )
+        };
+        let cfg = parse(&yaml("Loud")).unwrap();
+        cfg.validate().unwrap();
+
+        let ControllerCfg::RiingQuad { fans, .. } = &cfg.controllers[0];
+        assert_eq!(fans[0].curve, vec!["Loud"]);
+        assert_eq!(fans[1].curve, vec!["Silent", "Loud"]);
+        assert!(fans.iter().all(|fan| fan.active_curve == "Loud"));
+
+        let err = parse(&yaml("Turbo")).unwrap().validate().unwrap_err();
+        assert!(
+            err.to_string().contains("curve `Turbo` is not defined"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn fanless_controller_takes_colors_but_no_sensors() {
+        let leds = || {
+            Config::builder()
+                .controller(ControllerCfg::riing_quad("leds", 0x264A, 0x232B))
+                .color("red", [255, 0, 0])
+                .color_mapping("red", &[(1, 1), (1, 2)])
+        };
+        leds().build().validate().unwrap();
+        assert!(leds().build().lint().is_empty());
+
+        let err = leds()
+            .mapping(MappingCfg::builder("cpu").target(1, 1).build())
+            .build()
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sensor `cpu` is mapped to controller `leds`, which has no fans"
+        );
+    }
+
+    #[test]
+    fn curve_references_must_exist() {
+        let cfg = || Config::builder().curve(CurveCfg::constant("Quiet", 30));
+        cfg().fallback_curve("Quiet").build().validate().unwrap();
+
+        let err = cfg()
+            .fallback_curve("Qiuet")
+            .build()
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "fallback_curve `Qiuet` is not defined in `curves`"
+        );
+
+        let floored = |sensor: &str| {
+            cfg()
+                .sensor(SensorCfg::External {
+                    id: String::from("cpu"),
+                    max_age: 30,
+                })
+                .controller(
+                    ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                        .fan(1, "Front", &["Quiet"])
+                        .floor(sensor, "Quiet"),
+                )
+                .build()
+                .validate()
+        };
+        floored("cpu").unwrap();
+        assert_eq!(
+            floored("cpuu").unwrap_err().to_string(),
+            "controller `quad-1`: floor sensor `cpuu` is not defined in `sensors`"
+        );
+    }
+
+    #[test]
+    fn only_cold_sections_need_a_restart() {
+        let cfg = Config::default();
+        let mut pending = Config::builder()
+            .profile("quiet")
+            .curve(CurveCfg::constant("Silent", 30))
+            .color("red", [255, 0, 0])
+            .build();
+        let changed = cfg.analyze_changes(&pending).unwrap();
+        assert_eq!(changed, ["profile", "curves", "colors"]);
+        assert!(restart_sections(&changed).is_empty());
+
+        pending.tick_seconds = 5;
+        let changed = cfg.analyze_changes(&pending).unwrap();
+        assert_eq!(restart_sections(&changed), ["tick_seconds"]);
+
+        let yaml = serde_yaml::to_value(&cfg).unwrap();
+        for section in HOT_SECTIONS {
+            assert!(
+                yaml.get(section).is_some(),
+                "{section} is not a config section"
+            );
+        }
+    }
+
+    #[test]
+    fn rpm_curves_are_only_for_fans() {
+        let rpm_curve = CurveCfg::StepCurve {
+            id: String::from("Rpm"),
+            tmps: vec![30.0, 60.0],
+            spds: vec![600, 1800],
+            rpm: true,
+        };
+        let cfg = || {
+            Config::builder().curve(rpm_curve.clone()).controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B).fan(1, "Front", &["Rpm"]),
+            )
+        };
+        cfg().build().validate().unwrap();
+
+        let err = cfg()
+            .mapping(MappingCfg::builder("cpu").target(1, 1).curve("Rpm").build())
+            .build()
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "sensor `cpu` mapping: curve `Rpm` targets RPM, which only a fan's own curves can"
+        );
+    }
+
+    #[test]
+    fn kickstart_defaults_and_limits() {
+        let cfg = parse(
+            r#"
+version: 1
+controllers:
+  - id: "quad-1"
+    kind: riing-quad
+    usb: { vid: 0x264A, pid: 0x232B }
+    curve: "Silent"
+    fans:
+      - { idx: 1, name: "Front", kickstart: {} }
+      - { idx: 2, name: "Rear", kickstart: { below: 20, ms: 300 } }
+curves:
+  - { id: "Silent", kind: constant, speed: 15 }
+"#,
+        )
+        .unwrap();
+        cfg.validate().unwrap();
+        let ControllerCfg::RiingQuad { fans, .. } = &cfg.controllers[0];
+        assert_eq!(fans[0].kickstart, Some(KickstartCfg { below: 30, ms: 500 }));
+        assert_eq!(fans[1].kickstart, Some(KickstartCfg { below: 20, ms: 300 }));
+
+        let kicked = |below, ms| {
+            Config::builder()
+                .curve(CurveCfg::constant("Silent", 15))
+                .controller(
+                    ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                        .fan(1, "Front", &["Silent"])
+                        .kickstart(below, ms),
+                )
+                .build()
+                .validate()
+        };
+        assert!(kicked(30, 5_000).is_ok());
+        assert!(kicked(0, 500).is_err());
+        assert!(kicked(101, 500).is_err());
+        let err = kicked(30, 5_001).unwrap_err();
+        assert!(err.to_string().contains("fan 1 kickstart"), "{err}");
+    }
+
+    #[test]
+    fn lint_finds_unlisted_curves_and_uncontrolled_fans() {
+        let mut cfg = Config::builder()
+            .curve(CurveCfg::constant("Silent", 30))
+            .curve(CurveCfg::constant("Loud", 90))
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "Front", &["Silent"])
+                    .fan(2, "Rear", &["Silent"]),
+            )
+            .mapping(MappingCfg::builder("cpu").target(1, 1).build())
+            .color("red", [255, 0, 0])
+            .color_mapping("red", &[(1, 1), (1, 2)])
+            .build();
+        let ControllerCfg::RiingQuad { fans, .. } = &mut cfg.controllers[0];
+        fans[0].active_curve = String::from("Loud");
+        cfg.validate().unwrap();
+
+        assert_eq!(
+            cfg.lint(),
+            [
+                "controller `quad-1`: fan 1 active_curve `Loud` is not in its curve list",
+                "fan 1:2 has a color mapping but no sensor mapping, so its speed is never updated",
+            ]
+        );
+
+        let ControllerCfg::RiingQuad { fans, .. } = &mut cfg.controllers[0];
+        fans[0].active_curve = String::from("Silent");
+        cfg.mappings = vec![MappingCfg::builder("cpu").target(1, 1).target(1, 2).build()];
+        assert!(cfg.lint().is_empty());
+    }
+}
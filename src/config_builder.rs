@@ -0,0 +1,383 @@
+//! Typed builders for assembling a [`Config`] in code instead of YAML.
+
+use crate::{
+    config::{
+        ColorCfg, ColorMappingCfg, Config, ControllerCfg, CurveCfg, CurveFloorCfg, CurveRef,
+        FanCfg, FanTarget, InitCfg, KickstartCfg, LedOrder, MappingCfg, ScheduleCfg, SensorCfg,
+        UsbSelector, defaults,
+    },
+    fan_curve::Point,
+};
+
+impl Config {
+    pub fn builder() -> ConfigBuilder {
+        ConfigBuilder::default()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct ConfigBuilder {
+    cfg: Config,
+}
+
+impl ConfigBuilder {
+    pub fn profile(mut self, profile: impl Into<String>) -> Self {
+        self.cfg.profile = profile.into();
+        self
+    }
+
+    pub fn tick_seconds(mut self, tick_seconds: u16) -> Self {
+        self.cfg.tick_seconds = tick_seconds;
+        self
+    }
+
+    pub fn broadcast(mut self, interval: u16) -> Self {
+        self.cfg.enable_broadcast = true;
+        self.cfg.broadcast_interval = interval;
+        self
+    }
+
+    pub fn fallback_curve(mut self, id: impl Into<String>) -> Self {
+        self.cfg.fallback_curve = Some(id.into());
+        self
+    }
+
+    pub fn controller(mut self, controller: impl Into<ControllerCfg>) -> Self {
+        self.cfg.controllers.push(controller.into());
+        self
+    }
+
+    pub fn curve(mut self, curve: CurveCfg) -> Self {
+        self.cfg.curves.push(curve);
+        self
+    }
+
+    pub fn sensor(mut self, sensor: SensorCfg) -> Self {
+        self.cfg.sensors.push(sensor);
+        self
+    }
+
+    pub fn mapping(mut self, mapping: impl Into<MappingCfg>) -> Self {
+        self.cfg.mappings.push(mapping.into());
+        self
+    }
+
+    pub fn color(mut self, name: impl Into<String>, rgb: [u8; 3]) -> Self {
+        self.cfg.colors.push(ColorCfg {
+            color: name.into(),
+            rgb,
+        });
+        self
+    }
+
+    pub fn color_mapping(mut self, color: impl Into<String>, targets: &[(u8, u8)]) -> Self {
+        self.cfg.color_mappings.push(ColorMappingCfg {
+            color: color.into(),
+            targets: fan_targets(targets),
+        });
+        self
+    }
+
+    pub fn schedule(mut self, schedule: ScheduleCfg) -> Self {
+        self.cfg.schedules.push(schedule);
+        self
+    }
+
+    pub fn build(self) -> Config {
+        self.cfg
+    }
+}
+
+/// Builds a [`ControllerCfg::RiingQuad`].
+#[derive(Debug)]
+pub struct RiingQuadBuilder {
+    id: String,
+    name: Option<String>,
+    usb: UsbSelector,
+    fans: Vec<FanCfg>,
+    curve: Option<String>,
+    brightness: f32,
+    read_rpm: bool,
+    airflow_floor: Option<u16>,
+    tick_seconds: Option<u16>,
+    led_order: LedOrder,
+    init: InitCfg,
+}
+
+impl ControllerCfg {
+    pub fn riing_quad(id: impl Into<String>, vid: u16, pid: u16) -> RiingQuadBuilder {
+        RiingQuadBuilder {
+            id: id.into(),
+            name: None,
+            usb: UsbSelector {
+                vid,
+                pid,
+                serial: None,
+            },
+            fans: vec![],
+            curve: None,
+            brightness: defaults::brightness(),
+            read_rpm: defaults::read_rpm(),
+            airflow_floor: None,
+            tick_seconds: None,
+            led_order: LedOrder::default(),
+            init: InitCfg::default(),
+        }
+    }
+}
+
+impl RiingQuadBuilder {
+    pub fn name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    pub fn serial(mut self, serial: impl Into<String>) -> Self {
+        self.usb.serial = Some(serial.into());
+        self
+    }
+
+    /// Adds a fan; the first entry of `curves` becomes its active curve.
+    pub fn fan(mut self, idx: u8, name: impl Into<String>, curves: &[&str]) -> Self {
+        self.fans.push(FanCfg {
+            idx,
+            name: name.into(),
+            active_curve: curves.first().copied().unwrap_or_default().to_string(),
+            curve: curves.iter().map(|c| CurveRef::Id(c.to_string())).collect(),
+            curve_bands: vec![],
+            floor: None,
+            kickstart: None,
+        });
+        self
+    }
+
+    /// Puts every fan on `curve`, as the `curve` shorthand does in YAML.
+    pub fn curve(mut self, curve: impl Into<String>) -> Self {
+        self.curve = Some(curve.into());
+        self
+    }
+
+    /// Gives the fan added last a floor: `curve` evaluated at `sensor`'s temperature.
+    pub fn floor(mut self, sensor: impl Into<String>, curve: impl Into<String>) -> Self {
+        if let Some(fan) = self.fans.last_mut() {
+            fan.floor = Some(CurveFloorCfg {
+                sensor: sensor.into(),
+                curve: curve.into(),
+            });
+        }
+        self
+    }
+
+    /// Gives the fan added last a kickstart pulse of `ms` before speeds under `below`%.
+    pub fn kickstart(mut self, below: u8, ms: u64) -> Self {
+        if let Some(fan) = self.fans.last_mut() {
+            fan.kickstart = Some(KickstartCfg { below, ms });
+        }
+        self
+    }
+
+    pub fn led_order(mut self, led_order: LedOrder) -> Self {
+        self.led_order = led_order;
+        self
+    }
+
+    pub fn brightness(mut self, brightness: f32) -> Self {
+        self.brightness = brightness;
+        self
+    }
+
+    pub fn read_rpm(mut self, read_rpm: bool) -> Self {
+        self.read_rpm = read_rpm;
+        self
+    }
+
+    pub fn airflow_floor(mut self, floor: u16) -> Self {
+        self.airflow_floor = Some(floor);
+        self
+    }
+
+    pub fn tick_seconds(mut self, tick_seconds: u16) -> Self {
+        self.tick_seconds = Some(tick_seconds);
+        self
+    }
+
+    /// Accepts `status` as the answer to the init packet, instead of 0xFC.
+    pub fn init_status(mut self, status: u8) -> Self {
+        self.init.status = status;
+        self
+    }
+
+    pub fn build(self) -> ControllerCfg {
+        let mut ctrl = ControllerCfg::RiingQuad {
+            id: self.id,
+            name: self.name,
+            usb: self.usb,
+            fans: self.fans,
+            curve: self.curve,
+            brightness: self.brightness,
+            read_rpm: self.read_rpm,
+            airflow_floor: self.airflow_floor,
+            tick_seconds: self.tick_seconds,
+            led_order: self.led_order,
+            init: self.init,
+        };
+        ctrl.apply_curve();
+        ctrl
+    }
+}
+
+impl From<RiingQuadBuilder> for ControllerCfg {
+    fn from(builder: RiingQuadBuilder) -> Self {
+        builder.build()
+    }
+}
+
+impl CurveCfg {
+    pub fn constant(id: impl Into<String>, speed: u8) -> Self {
+        CurveCfg::Constant {
+            id: id.into(),
+            speed,
+        }
+    }
+
+    pub fn step(id: impl Into<String>, tmps: &[f32], spds: &[u8]) -> Self {
+        CurveCfg::StepCurve {
+            id: id.into(),
+            tmps: tmps.to_vec(),
+            spds: spds.iter().map(|&s| s as u16).collect(),
+            rpm: false,
+        }
+    }
+
+    pub fn bezier(id: impl Into<String>, points: [(f32, f32); 4]) -> Self {
+        CurveCfg::Bezier {
+            id: id.into(),
+            points: points.into_iter().map(Point::from).collect(),
+        }
+    }
+
+    pub fn catmull_rom(id: impl Into<String>, points: &[(f32, f32)]) -> Self {
+        CurveCfg::CatmullRom {
+            id: id.into(),
+            points: points.iter().copied().map(Point::from).collect(),
+        }
+    }
+
+    pub fn zero_rpm_ramp(
+        id: impl Into<String>,
+        off_below: f32,
+        ramp_start_speed: u8,
+        max_temp: f32,
+        max_speed: u8,
+    ) -> Self {
+        CurveCfg::ZeroRpmRamp {
+            id: id.into(),
+            off_below,
+            ramp_start_speed,
+            max_temp,
+            max_speed,
+        }
+    }
+}
+
+/// Builds a [`MappingCfg`] from a sensor id and its `(controller, fan_idx)` targets.
+#[derive(Debug)]
+pub struct MappingBuilder {
+    sensor: String,
+    targets: Vec<FanTarget>,
+    curve: Option<String>,
+}
+
+impl MappingCfg {
+    pub fn builder(sensor: impl Into<String>) -> MappingBuilder {
+        MappingBuilder {
+            sensor: sensor.into(),
+            targets: vec![],
+            curve: None,
+        }
+    }
+}
+
+impl MappingBuilder {
+    pub fn target(mut self, controller: u8, fan_idx: u8) -> Self {
+        self.targets.push(FanTarget {
+            controller,
+            fan_idx,
+        });
+        self
+    }
+
+    /// Curve the targets follow while this sensor drives them.
+    pub fn curve(mut self, curve: impl Into<String>) -> Self {
+        self.curve = Some(curve.into());
+        self
+    }
+
+    pub fn build(self) -> MappingCfg {
+        MappingCfg {
+            sensor: self.sensor,
+            targets: self.targets,
+            curve: self.curve,
+        }
+    }
+}
+
+impl From<MappingBuilder> for MappingCfg {
+    fn from(builder: MappingBuilder) -> Self {
+        builder.build()
+    }
+}
+
+fn fan_targets(targets: &[(u8, u8)]) -> Vec<FanTarget> {
+    targets
+        .iter()
+        .map(|&(controller, fan_idx)| FanTarget {
+            controller,
+            fan_idx,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{Config, ControllerCfg, CurveCfg, MappingCfg};
+
+    #[test]
+    fn builds_controller_curve_and_mapping() {
+        let cfg = Config::builder()
+            .tick_seconds(1)
+            .controller(ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B).fan(
+                1,
+                "Front",
+                &["Silent"],
+            ))
+            .curve(CurveCfg::constant("Silent", 30))
+            .mapping(MappingCfg::builder("cpu").target(1, 1))
+            .build();
+
+        assert_eq!(cfg.version, 1);
+        assert_eq!(cfg.tick_seconds, 1);
+        assert_eq!(cfg.fan_count(), 1);
+
+        let ControllerCfg::RiingQuad { id, usb, fans, .. } = &cfg.controllers[0];
+        assert_eq!(id, "quad-1");
+        assert_eq!((usb.vid, usb.pid), (0x264A, 0x232B));
+        assert_eq!(fans[0].active_curve, "Silent");
+        assert_eq!(fans[0].curve, vec!["Silent"]);
+
+        assert_eq!(cfg.curves[0].get_id(), "Silent");
+        assert_eq!(cfg.mappings[0].sensor, "cpu");
+        assert_eq!(cfg.mappings[0].targets[0].controller, 1);
+        assert_eq!(cfg.mappings[0].targets[0].fan_idx, 1);
+    }
+
+    #[test]
+    fn built_config_matches_yaml_defaults() {
+        let built = Config::builder().build();
+        let parsed = crate::config::parse("version: 1\n").unwrap();
+        assert_eq!(
+            crate::config::to_yaml(&built).unwrap(),
+            crate::config::to_yaml(&parsed).unwrap()
+        );
+    }
+}
@@ -0,0 +1,465 @@
+#[cfg(test)]
+use std::sync::{Mutex, PoisonError};
+use std::{
+    fmt::Debug,
+    fs,
+    os::unix::fs::MetadataExt,
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, SystemTime},
+};
+
+use anyhow::Result;
+use log::warn;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tokio::{
+    sync::{mpsc, watch},
+    time::sleep,
+};
+
+use crate::{
+    config::{self, Config},
+    sensors::SensorKinds,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchEvent {
+    /// The file disappeared; the daemon keeps running on the config it already loaded.
+    Missing,
+    /// The file is back after being reported missing.
+    Recreated,
+    /// The file's modification time changed, or another file was renamed over it.
+    Modified,
+}
+
+/// Modification time and inode of the file at the watched path.
+type Stamp = (SystemTime, u64);
+
+/// Polls a config file for deletion, recreation and modification, on an interval and
+/// whenever [`FileEvents`] reports a change.
+///
+/// The path itself is polled rather than a directory, so there is no watch to re-establish:
+/// an editor that saves by renaming a new file over the old one is seen through the
+/// changed inode, even when the new file keeps the old modification time.
+#[derive(Debug)]
+pub struct ConfigWatcher {
+    path: PathBuf,
+    modified: Option<Stamp>,
+    missing: bool,
+}
+
+impl ConfigWatcher {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let modified = stamp(&path);
+        Self {
+            missing: modified.is_none(),
+            path,
+            modified,
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn is_missing(&self) -> bool {
+        self.missing
+    }
+
+    pub fn poll(&mut self) -> Option<WatchEvent> {
+        let modified = stamp(&self.path);
+        let event = match (self.missing, modified) {
+            (false, None) => Some(WatchEvent::Missing),
+            (true, Some(_)) => Some(WatchEvent::Recreated),
+            (false, Some(m)) if Some(m) != self.modified => Some(WatchEvent::Modified),
+            _ => None,
+        };
+        self.missing = modified.is_none();
+        self.modified = modified;
+        event
+    }
+}
+
+/// How long [`FileEvents::changed`] waits after the first event, so an editor's save is
+/// finished before the file is read.
+const SETTLE: Duration = Duration::from_millis(100);
+
+/// Wakes the config watcher as soon as the config file changes, so an edit doesn't wait
+/// for the next poll.
+///
+/// The file's directory is watched through inotify, as a save by rename replaces the file's
+/// inode. If the watch can't be set up, or the directory itself is removed, this never
+/// fires again and [`ConfigWatcher::poll`] on its interval still catches every change.
+#[derive(Debug)]
+pub struct FileEvents {
+    rx: mpsc::UnboundedReceiver<()>,
+    /// Keeps `rx` open, so `changed` waits rather than returns when there is no watch.
+    _tx: mpsc::UnboundedSender<()>,
+    _watcher: Option<RecommendedWatcher>,
+}
+
+impl FileEvents {
+    pub fn new(path: &Path) -> Self {
+        let (tx, rx) = mpsc::unbounded_channel();
+        let watcher = watch_dir(path, tx.clone())
+            .inspect_err(|e| {
+                warn!(
+                    "Cannot watch {} for changes, polling it instead: {e}",
+                    path.display()
+                )
+            })
+            .ok();
+        Self {
+            rx,
+            _tx: tx,
+            _watcher: watcher,
+        }
+    }
+
+    /// Waits until the file was created, written, removed or renamed, then [`SETTLE`] more
+    /// for the rest of the save.
+    pub async fn changed(&mut self) {
+        self.rx.recv().await;
+        sleep(SETTLE).await;
+        while self.rx.try_recv().is_ok() {}
+    }
+}
+
+fn watch_dir(path: &Path, tx: mpsc::UnboundedSender<()>) -> notify::Result<RecommendedWatcher> {
+    let name = path.file_name().map(|name| name.to_os_string());
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        let ours = event.is_ok_and(|event| {
+            !matches!(event.kind, EventKind::Access(_))
+                && event
+                    .paths
+                    .iter()
+                    .any(|path| path.file_name() == name.as_deref())
+        });
+        if ours {
+            let _ = tx.send(());
+        }
+    })?;
+    watcher.watch(dir, RecursiveMode::NonRecursive)?;
+    Ok(watcher)
+}
+
+/// Where [`ConfigReloader`] reads the config from.
+pub trait ConfigSource: Send + Debug {
+    fn load(&self) -> Result<Config>;
+    /// Keeps a copy of a config that failed to load, for sources that can.
+    fn keep_rejected(&self) {}
+}
+
+/// The config file on disk.
+#[derive(Debug)]
+pub struct FileSource {
+    path: PathBuf,
+}
+
+impl FileSource {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self { path: path.into() }
+    }
+}
+
+impl ConfigSource for FileSource {
+    fn load(&self) -> Result<Config> {
+        config::load(&self.path)
+    }
+
+    /// Copies the file to `<name>.rejected` next to it.
+    fn keep_rejected(&self) {
+        let aside = rejected_path(&self.path);
+        if let Err(e) = fs::copy(&self.path, &aside) {
+            warn!("Failed to keep rejected config {}: {e}", aside.display());
+        }
+    }
+}
+
+/// YAML held in memory, so reloads can be exercised without touching disk.
+#[cfg(test)]
+#[derive(Debug, Clone, Default)]
+pub struct MemorySource(Arc<Mutex<String>>);
+
+#[cfg(test)]
+impl MemorySource {
+    pub fn set(&self, yaml: &str) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = yaml.to_string();
+    }
+}
+
+#[cfg(test)]
+impl ConfigSource for MemorySource {
+    fn load(&self) -> Result<Config> {
+        config::check(config::parse(
+            &self.0.lock().unwrap_or_else(PoisonError::into_inner),
+        )?)
+    }
+}
+
+/// Re-validates the config on change, remembers the last version that loaded and hands it
+/// to the running services.
+#[derive(Debug)]
+pub struct ConfigReloader {
+    good: Config,
+    last_error: Option<String>,
+    source: Box<dyn ConfigSource>,
+    sensor_kinds: Option<SensorKinds>,
+    updates: watch::Sender<Arc<Config>>,
+}
+
+impl ConfigReloader {
+    pub fn new(good: Config, source: impl ConfigSource + 'static) -> Self {
+        Self {
+            updates: watch::Sender::new(Arc::new(good.clone())),
+            good,
+            last_error: None,
+            source: Box::new(source),
+            sensor_kinds: None,
+        }
+    }
+
+    /// Receives every config a reload accepts that differs from the one before, starting
+    /// from the config given to [`Self::new`].
+    pub fn subscribe(&self) -> watch::Receiver<Arc<Config>> {
+        self.updates.subscribe()
+    }
+
+    /// Also rejects configs with a sensor `kind` outside `kinds`, which parse as custom
+    /// sensors and would otherwise only fail when the daemon restarts.
+    pub fn with_sensor_kinds(mut self, kinds: SensorKinds) -> Self {
+        self.sensor_kinds = Some(kinds);
+        self
+    }
+
+    /// Reloads from YAML in memory instead of a file; returns the handle to change it.
+    #[cfg(test)]
+    pub fn in_memory(good: Config) -> (Self, MemorySource) {
+        let source = MemorySource::default();
+        (Self::new(good, source.clone()), source)
+    }
+
+    /// Last config that loaded successfully.
+    pub fn config(&self) -> &Config {
+        &self.good
+    }
+
+    pub fn last_error(&self) -> Option<&str> {
+        self.last_error.as_deref()
+    }
+
+    /// Loads the config from its source and sends it to subscribers if anything changed. On
+    /// failure the previous config stays current, the error is kept and, with
+    /// `keep_rejected_config`, the source keeps a copy of the broken one.
+    pub fn reload(&mut self) -> Result<()> {
+        let loaded = self.source.load().and_then(|cfg| {
+            if let Some(kinds) = &self.sensor_kinds {
+                kinds.check(&cfg.sensors)?;
+            }
+            Ok(cfg)
+        });
+        match loaded {
+            Ok(cfg) => {
+                let unchanged = self
+                    .good
+                    .analyze_changes(&cfg)
+                    .is_ok_and(|changed| changed.is_empty());
+                if !unchanged {
+                    self.updates.send_replace(Arc::new(cfg.clone()));
+                }
+                self.good = cfg;
+                self.last_error = None;
+                Ok(())
+            }
+            Err(e) => {
+                self.last_error = Some(format!("{e:#}"));
+                if self.good.keep_rejected_config {
+                    self.source.keep_rejected();
+                }
+                Err(e)
+            }
+        }
+    }
+}
+
+fn rejected_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".rejected");
+    path.with_file_name(name)
+}
+
+fn stamp(path: &Path) -> Option<Stamp> {
+    let meta = fs::metadata(path).ok()?;
+    Some((meta.modified().ok()?, meta.ino()))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env, fs,
+        fs::File,
+        process,
+        time::{Duration, SystemTime},
+    };
+
+    use tokio::time::timeout;
+
+    use super::{ConfigReloader, ConfigWatcher, FileEvents, FileSource, WatchEvent, rejected_path};
+    use crate::{
+        config::{Config, SensorCfg},
+        sensors::SensorRegistry,
+    };
+
+    #[test]
+    fn missing_status_toggles_on_delete_and_recreate() {
+        let path = env::temp_dir().join(format!("tt_riingd_watch_{}.yml", process::id()));
+        fs::write(&path, "version: 1\n").unwrap();
+
+        let mut watcher = ConfigWatcher::new(&path);
+        assert!(!watcher.is_missing());
+        assert_eq!(watcher.poll(), None);
+
+        fs::remove_file(&path).unwrap();
+        assert_eq!(watcher.poll(), Some(WatchEvent::Missing));
+        assert!(watcher.is_missing());
+        assert_eq!(watcher.poll(), None);
+
+        fs::write(&path, "version: 1\n").unwrap();
+        assert_eq!(watcher.poll(), Some(WatchEvent::Recreated));
+        assert!(!watcher.is_missing());
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn write_and_rename_saves_are_modifications() {
+        let dir = env::temp_dir().join(format!("tt_riingd_rename_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yml");
+        fs::write(&path, "version: 1\n").unwrap();
+        let mut watcher = ConfigWatcher::new(&path);
+
+        let file = File::options().write(true).open(&path).unwrap();
+        file.set_modified(SystemTime::UNIX_EPOCH).unwrap();
+        assert_eq!(watcher.poll(), Some(WatchEvent::Modified));
+        assert_eq!(watcher.poll(), None);
+
+        // A save that renames a new file with the same modification time over the config.
+        let saved = dir.join("config.yml.tmp");
+        fs::write(&saved, "version: 1\nprofile: quiet\n").unwrap();
+        File::options()
+            .write(true)
+            .open(&saved)
+            .unwrap()
+            .set_modified(SystemTime::UNIX_EPOCH)
+            .unwrap();
+        fs::rename(&saved, &path).unwrap();
+        assert_eq!(watcher.poll(), Some(WatchEvent::Modified));
+        assert!(!watcher.is_missing());
+        assert_eq!(watcher.poll(), None);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn file_events_follow_only_the_config() {
+        let dir = env::temp_dir().join(format!("tt_riingd_events_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.yml");
+        fs::write(&path, "version: 1\n").unwrap();
+        let mut events = FileEvents::new(&path);
+        let wait = Duration::from_millis(500);
+
+        fs::write(dir.join("state.json"), "{}").unwrap();
+        assert!(timeout(wait, events.changed()).await.is_err());
+
+        let saved = dir.join("config.yml.tmp");
+        fs::write(&saved, "version: 1\nprofile: quiet\n").unwrap();
+        fs::rename(&saved, &path).unwrap();
+        timeout(Duration::from_secs(5), events.changed())
+            .await
+            .unwrap();
+        assert!(timeout(wait, events.changed()).await.is_err());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn failed_reload_keeps_last_good_config() {
+        let path = env::temp_dir().join(format!("tt_riingd_reload_{}.yml", process::id()));
+        let mut reloader = ConfigReloader::new(Config::default(), FileSource::new(&path));
+
+        fs::write(
+            &path,
+            "version: 1\nprofile: quiet\nkeep_rejected_config: true\n",
+        )
+        .unwrap();
+        reloader.reload().unwrap();
+        assert_eq!(reloader.last_error(), None);
+        assert_eq!(reloader.config().profile, "quiet");
+
+        fs::write(&path, "version: 1\nprofile: [broken\n").unwrap();
+        assert!(reloader.reload().is_err());
+        assert!(reloader.last_error().unwrap().contains("parse YAML"));
+        assert_eq!(reloader.config().profile, "quiet");
+        let rejected = rejected_path(&path);
+        assert_eq!(
+            fs::read_to_string(&rejected).unwrap(),
+            "version: 1\nprofile: [broken\n"
+        );
+
+        fs::write(&path, "version: 1\nprofile: loud\n").unwrap();
+        reloader.reload().unwrap();
+        assert_eq!(reloader.last_error(), None);
+        assert_eq!(reloader.config().profile, "loud");
+
+        fs::remove_file(&path).unwrap();
+        fs::remove_file(&rejected).unwrap();
+    }
+
+    #[test]
+    fn in_memory_reload_swaps_config() {
+        let (mut reloader, source) = ConfigReloader::in_memory(Config::default());
+        let mut updates = reloader.subscribe();
+
+        source.set("version: 1\nprofile: quiet\ntick_seconds: 5\n");
+        reloader.reload().unwrap();
+        assert_eq!(reloader.config().profile, "quiet");
+        assert_eq!(reloader.config().tick_seconds, 5);
+        assert_eq!(updates.borrow_and_update().profile, "quiet");
+
+        reloader.reload().unwrap();
+        assert!(!updates.has_changed().unwrap());
+
+        source.set("version: 2\nprofile: loud\n");
+        assert!(reloader.reload().is_err());
+        assert!(reloader.last_error().unwrap().contains("version 2"));
+        assert_eq!(reloader.config().profile, "quiet");
+        assert!(!updates.has_changed().unwrap());
+    }
+
+    #[test]
+    fn unknown_sensor_kind_is_rejected_on_reload() {
+        let mut registry = SensorRegistry::default();
+        registry.register("fixed", |_: &SensorCfg| Ok(None));
+        let (reloader, source) = ConfigReloader::in_memory(Config::default());
+        let mut reloader = reloader.with_sensor_kinds(registry.kinds());
+
+        source.set("version: 1\nsensors:\n  - { id: cpu, kind: fixed, temp: 40 }\n");
+        reloader.reload().unwrap();
+        assert_eq!(reloader.config().sensors.len(), 1);
+
+        source.set("version: 1\nprofile: quiet\nsensors:\n  - { id: cpu, kind: fixd, temp: 40 }\n");
+        assert!(reloader.reload().is_err());
+        assert_eq!(
+            reloader.last_error(),
+            Some("sensor `cpu`: no backend for kind `fixd`")
+        );
+        assert_eq!(reloader.config().profile, Config::default().profile);
+    }
+}
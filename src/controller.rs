@@ -1,14 +1,28 @@
-use std::{collections::HashMap, slice::Iter as SliceIter, sync::Arc};
+use std::{collections::HashMap, slice::Iter as SliceIter, sync::Arc, time::Duration};
 
 use anyhow::{Ok, Result, anyhow};
 use futures::stream::{Iter as FutureIter, StreamExt, iter};
 use hidapi::HidApi;
+use tokio::time::sleep;
 
-use crate::{config::Config, drivers, fan_controller::FanController, fan_curve::FanCurve};
+use crate::{
+    config::Config, drivers, events::EventBus, fan_controller::FanController, fan_curve::FanCurve,
+    services::CancelToken,
+};
+
+/// White/off cycles shown by [`Controllers::identify_fan`].
+pub const IDENTIFY_BLINKS: usize = 3;
+pub const IDENTIFY_INTERVAL: Duration = Duration::from_millis(250);
 
 #[derive(Debug, Clone)]
 pub struct Controllers(Arc<Vec<Box<dyn FanController>>>);
 
+impl From<Vec<Box<dyn FanController>>> for Controllers {
+    fn from(devices: Vec<Box<dyn FanController>>) -> Self {
+        Self(Arc::new(devices))
+    }
+}
+
 impl Controllers {
     pub fn init(init_speed: u8) -> Result<Self> {
         let api = HidApi::new()?;
@@ -21,8 +35,7 @@ impl Controllers {
         Ok(Self(Arc::new(controllers)))
     }
 
-    pub fn init_from_cfg(cfg: &Config) -> Result<Self> {
-        let api = HidApi::new()?;
+    pub fn init_from_cfg(cfg: &Config, events: &EventBus, cancel: &CancelToken) -> Result<Self> {
         let mut controllers = Vec::<Box<dyn FanController>>::new();
         let curve_map: HashMap<String, FanCurve> = cfg
             .curves
@@ -31,20 +44,19 @@ impl Controllers {
             .collect();
 
         controllers.extend(drivers::tt_riing_quad::TTRiingQuad::find_controllers(
-            &api,
-            &cfg.controllers,
-            &curve_map,
+            cfg, &curve_map, events, cancel,
         )?);
 
         Ok(Self(Arc::new(controllers)))
     }
 
-    pub async fn send_init(&self) -> Result<()> {
-        self.async_iter()
-            .fold(Ok(()), |acc, device| async {
-                acc.and(device.send_init().await)
-            })
-            .await
+    /// Number of controllers, numbered from 1.
+    pub fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    pub async fn send_init(&self, controller: u8) -> Result<()> {
+        self.get_device(controller)?.send_init().await
     }
 
     pub async fn update_speeds(&self, temp: f32) -> Result<()> {
@@ -61,6 +73,39 @@ impl Controllers {
             .await
     }
 
+    pub async fn update_channels(&self, controller: u8, channels: &[(u8, f32)]) -> Result<()> {
+        self.get_device(controller)?.update_channels(channels).await
+    }
+
+    pub async fn channel_speed(&self, controller: u8, channel: u8, temp: f32) -> Result<u8> {
+        self.get_device(controller)?
+            .channel_speed(channel, temp)
+            .await
+    }
+
+    /// Starts a new tick for every controller's curve cache.
+    pub async fn clear_curve_caches(&self) {
+        for device in self.0.iter() {
+            device.clear_curve_cache().await;
+        }
+    }
+
+    pub async fn set_max_rpm(&self, controller: u8, channel: u8, rpm: u16) -> Result<()> {
+        self.get_device(controller)?.set_max_rpm(channel, rpm).await
+    }
+
+    pub async fn set_channel_speeds(&self, controller: u8, speeds: &[(u8, u8)]) -> Result<()> {
+        self.get_device(controller)?
+            .set_channel_speeds(speeds)
+            .await
+    }
+
+    pub async fn set_speed_direct(&self, controller: u8, channel: u8, speed: u8) -> Result<()> {
+        self.get_device(controller)?
+            .set_speed_direct(channel, speed)
+            .await
+    }
+
     pub async fn update_channel_color(
         &self,
         controller: u8,
@@ -74,6 +119,92 @@ impl Controllers {
             .await
     }
 
+    pub async fn update_channel_color_zones(
+        &self,
+        controller: u8,
+        channel: u8,
+        zones: &[[u8; 3]],
+    ) -> Result<()> {
+        self.get_device(controller)?
+            .update_channel_color_zones(channel, zones)
+            .await
+    }
+
+    /// Blinks the fan's LED white a few times, then restores its previous color.
+    pub async fn identify_fan(&self, controller: u8, channel: u8) -> Result<()> {
+        let device = self.get_device(controller)?;
+        let (red, green, blue) = device.channel_color(channel).await?;
+        let flashed = async {
+            for _ in 0..IDENTIFY_BLINKS {
+                device.update_channel_color(channel, 255, 255, 255).await?;
+                sleep(IDENTIFY_INTERVAL).await;
+                device.update_channel_color(channel, 0, 0, 0).await?;
+                sleep(IDENTIFY_INTERVAL).await;
+            }
+            Ok(())
+        }
+        .await;
+        let restored = device.update_channel_color(channel, red, green, blue).await;
+        flashed.and(restored)
+    }
+
+    /// Switches the fan to `curve` and returns the revert to run for the trial: after
+    /// `duration` it switches the fan back to the curve it was on, unless something else
+    /// switched it meanwhile.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub async fn try_curve(
+        &self,
+        controller: u8,
+        channel: u8,
+        curve: &str,
+        duration: Duration,
+    ) -> Result<impl Future<Output = Result<()>> + Send + 'static> {
+        let previous = self.get_active_curve(controller, channel).await?;
+        self.switch_curve(controller, channel, curve).await?;
+        let (controllers, trial) = (self.clone(), curve.to_string());
+        Ok(async move {
+            sleep(duration).await;
+            if controllers.get_active_curve(controller, channel).await? == trial {
+                controllers
+                    .switch_curve(controller, channel, &previous)
+                    .await?;
+            }
+            Ok(())
+        })
+    }
+
+    pub async fn fan_count(&self, controller: u8) -> Result<usize> {
+        Ok(self.get_device(controller)?.fan_count().await)
+    }
+
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub async fn channel_rpm(&self, controller: u8, channel: u8) -> Result<u16> {
+        self.get_device(controller)?.channel_rpm(channel).await
+    }
+
+    /// `(controller, channel, speed, rpm)` of every fan, all 1-based.
+    pub async fn fan_stats(&self) -> Vec<(u8, u8, u8, u16)> {
+        let mut stats = vec![];
+        for (idx, device) in self.0.iter().enumerate() {
+            let fans = device.fan_stats().await.into_iter().enumerate();
+            stats.extend(fans.map(|(ch, (speed, rpm))| (idx as u8 + 1, ch as u8 + 1, speed, rpm)));
+        }
+        stats
+    }
+
+    /// `(speed, rpm)` of every fan on `controller`, read from the device now.
+    pub async fn read_all_fans(&self, controller: u8) -> Result<Vec<(u8, u16)>> {
+        self.get_device(controller)?.read_all_fans().await
+    }
+
+    /// Number of fans on each controller, in controller order.
+    pub async fn fan_counts(&self) -> Vec<usize> {
+        self.async_iter()
+            .then(|device| device.fan_count())
+            .collect()
+            .await
+    }
+
     pub async fn switch_curve(&self, controller: u8, channel: u8, curve: &str) -> Result<()> {
         self.get_device(controller)?
             .switch_curve(channel, curve)
@@ -88,6 +219,11 @@ impl Controllers {
         self.get_device(controller)?.firmware_version().await
     }
 
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub async fn connected_ports(&self, controller: u8) -> Result<Vec<u8>> {
+        self.get_device(controller)?.connected_ports().await
+    }
+
     pub async fn update_curve_data(
         &self,
         controller: u8,
@@ -114,3 +250,252 @@ impl Controllers {
         iter(self.0.iter())
     }
 }
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc, Mutex, PoisonError,
+            atomic::{AtomicUsize, Ordering},
+        },
+        time::Duration,
+    };
+
+    use anyhow::{Result, anyhow};
+    use async_trait::async_trait;
+    use tokio::time::{Instant, sleep};
+
+    use super::{Controllers, IDENTIFY_BLINKS};
+    use crate::{fan_controller::FanController, fan_curve::FanCurve};
+
+    type ColorLog = Arc<Mutex<Vec<Vec<(u8, u8, u8)>>>>;
+
+    /// Records every color written, per channel, and the last speed and curve set on each
+    /// channel. The curve speed of a channel is the temperature itself.
+    #[derive(Debug, Default)]
+    pub(crate) struct MockController {
+        pub colors: ColorLog,
+        pub speeds: Arc<Mutex<Vec<u8>>>,
+        /// Number of `set_channel_speeds` calls.
+        pub writes: Arc<AtomicUsize>,
+        /// How long each `set_channel_speeds` call takes, like a slow HID write.
+        pub write_delay: Duration,
+        /// Active curve per channel, empty until switched.
+        pub curves: Arc<Mutex<HashMap<u8, String>>>,
+        /// When `send_init` was last called.
+        pub initialized_at: Arc<Mutex<Option<Instant>>>,
+        /// Curve data written with `update_curve_data`, by channel and curve id.
+        pub curve_data: Arc<Mutex<HashMap<(u8, String), FanCurve>>>,
+        /// Error `send_init` fails with, if any.
+        pub init_error: Option<String>,
+    }
+
+    impl MockController {
+        pub fn with_fans(count: usize) -> Self {
+            Self {
+                colors: Arc::new(Mutex::new(vec![vec![(0, 0, 0)]; count])),
+                speeds: Arc::new(Mutex::new(vec![0; count])),
+                writes: Arc::default(),
+                write_delay: Duration::ZERO,
+                curves: Arc::default(),
+                initialized_at: Arc::default(),
+                curve_data: Arc::default(),
+                init_error: None,
+            }
+        }
+
+        pub fn failing_init(mut self, error: &str) -> Self {
+            self.init_error = Some(error.to_string());
+            self
+        }
+
+        pub fn write_delay(mut self, delay: Duration) -> Self {
+            self.write_delay = delay;
+            self
+        }
+
+        pub fn boxed(self) -> Box<dyn FanController> {
+            Box::new(self)
+        }
+    }
+
+    #[async_trait]
+    impl FanController for MockController {
+        async fn send_init(&self) -> Result<()> {
+            *self
+                .initialized_at
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner) = Some(Instant::now());
+            match &self.init_error {
+                Some(e) => Err(anyhow!("{e}")),
+                None => Ok(()),
+            }
+        }
+        async fn fan_count(&self) -> usize {
+            self.colors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .len()
+        }
+        async fn update_speeds(&self, _temp: f32) -> Result<()> {
+            Ok(())
+        }
+        async fn channel_speed(&self, _channel: u8, temp: f32) -> Result<u8> {
+            Ok(temp as u8)
+        }
+        async fn set_channel_speeds(&self, speeds: &[(u8, u8)]) -> Result<()> {
+            self.writes.fetch_add(1, Ordering::Relaxed);
+            if !self.write_delay.is_zero() {
+                tokio::time::sleep(self.write_delay).await;
+            }
+            let mut current = self.speeds.lock().unwrap_or_else(PoisonError::into_inner);
+            for &(channel, speed) in speeds {
+                *current
+                    .get_mut((channel - 1) as usize)
+                    .ok_or(anyhow!("Fan not found"))? = speed;
+            }
+            Ok(())
+        }
+        async fn set_speed_direct(&self, channel: u8, speed: u8) -> Result<()> {
+            self.set_channel_speeds(&[(channel, speed)]).await
+        }
+        async fn update_channel_color(
+            &self,
+            channel: u8,
+            red: u8,
+            green: u8,
+            blue: u8,
+        ) -> Result<()> {
+            let mut colors = self.colors.lock().unwrap_or_else(PoisonError::into_inner);
+            let history = colors
+                .get_mut((channel - 1) as usize)
+                .ok_or(anyhow!("Fan not found"))?;
+            history.push((red, green, blue));
+            Ok(())
+        }
+        async fn channel_rpm(&self, channel: u8) -> Result<u16> {
+            let speeds = self.speeds.lock().unwrap_or_else(PoisonError::into_inner);
+            let speed = speeds
+                .get((channel - 1) as usize)
+                .ok_or(anyhow!("Fan not found"))?;
+            Ok(*speed as u16 * 20)
+        }
+        async fn fan_stats(&self) -> Vec<(u8, u16)> {
+            let speeds = self.speeds.lock().unwrap_or_else(PoisonError::into_inner);
+            speeds
+                .iter()
+                .map(|&speed| (speed, speed as u16 * 20))
+                .collect()
+        }
+        async fn channel_color(&self, channel: u8) -> Result<(u8, u8, u8)> {
+            let colors = self.colors.lock().unwrap_or_else(PoisonError::into_inner);
+            colors
+                .get((channel - 1) as usize)
+                .and_then(|history| history.last().copied())
+                .ok_or(anyhow!("Fan not found"))
+        }
+        async fn switch_curve(&self, channel: u8, curve: &str) -> Result<()> {
+            self.curves
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert(channel, curve.to_string());
+            Ok(())
+        }
+        async fn get_active_curve(&self, channel: u8) -> Result<String> {
+            let curves = self.curves.lock().unwrap_or_else(PoisonError::into_inner);
+            Ok(curves.get(&channel).cloned().unwrap_or_default())
+        }
+        async fn firmware_version(&self) -> Result<(u8, u8, u8)> {
+            Ok((1, 0, 0))
+        }
+        async fn connected_ports(&self) -> Result<Vec<u8>> {
+            Ok((1..=self
+                .colors
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .len() as u8)
+                .collect())
+        }
+        async fn update_curve_data(
+            &self,
+            channel: u8,
+            curve: &str,
+            curve_data: &FanCurve,
+        ) -> Result<()> {
+            self.curve_data
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .insert((channel, curve.to_string()), curve_data.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn identify_restores_previous_color() {
+        let colors = Arc::new(Mutex::new(vec![vec![(10, 20, 30)], vec![(1, 2, 3)]]));
+        let mock = MockController {
+            colors: colors.clone(),
+            ..Default::default()
+        };
+        let controllers = Controllers(Arc::new(vec![Box::new(mock)]));
+
+        controllers.identify_fan(1, 1).await.unwrap();
+
+        {
+            let colors = colors.lock().unwrap();
+            assert_eq!(colors[0].len(), 1 + IDENTIFY_BLINKS * 2 + 1);
+            assert!(colors[0].contains(&(255, 255, 255)));
+            assert_eq!(colors[0].last(), Some(&(10, 20, 30)));
+            assert_eq!(colors[1], vec![(1, 2, 3)]);
+        }
+        assert!(controllers.identify_fan(1, 3).await.is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tried_curve_reverts_after_the_window() {
+        let mock = MockController::with_fans(2);
+        let curves = mock.curves.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        controllers.switch_curve(1, 1, "Silent").await.unwrap();
+        let active = || curves.lock().unwrap().get(&1).cloned().unwrap();
+
+        let revert = controllers
+            .try_curve(1, 1, "Loud", Duration::from_secs(5))
+            .await
+            .unwrap();
+        let trial = tokio::spawn(revert);
+        sleep(Duration::from_secs(4)).await;
+        assert_eq!(active(), "Loud");
+        trial.await.unwrap().unwrap();
+        assert_eq!(active(), "Silent");
+
+        // A curve switched to during the trial is kept.
+        let revert = controllers
+            .try_curve(1, 1, "Loud", Duration::from_secs(5))
+            .await
+            .unwrap();
+        controllers.switch_curve(1, 1, "Balanced").await.unwrap();
+        revert.await.unwrap();
+        assert_eq!(active(), "Balanced");
+        assert!(
+            controllers
+                .try_curve(2, 1, "Loud", Duration::from_secs(5))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn default_read_all_fans_reads_each_channel() {
+        let mock = MockController::with_fans(2);
+        *mock.speeds.lock().unwrap() = vec![30, 60];
+        let controllers = Controllers::from(vec![mock.boxed()]);
+
+        assert_eq!(
+            controllers.read_all_fans(1).await.unwrap(),
+            [(30, 600), (60, 1200)]
+        );
+        assert!(controllers.read_all_fans(2).await.is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::HashMap;
+
+use crate::{
+    config::{Config, ControllerCfg, CurveBandCfg},
+    mappings::FanRef,
+};
+
+/// Picks a fan's curve from temperature bands. Moving up happens at a band's `from`; moving
+/// down only once the temperature is `hysteresis` degrees below it.
+#[derive(Debug, Clone)]
+pub struct CurveBands {
+    bands: Vec<CurveBandCfg>,
+    hysteresis: f32,
+    current: Option<usize>,
+}
+
+impl CurveBands {
+    pub fn new(bands: &[CurveBandCfg], hysteresis: f32) -> Option<Self> {
+        if bands.is_empty() {
+            return None;
+        }
+        let mut bands = bands.to_vec();
+        bands.sort_by(|a, b| a.from.total_cmp(&b.from));
+        Some(Self {
+            bands,
+            hysteresis: hysteresis.max(0.0),
+            current: None,
+        })
+    }
+
+    /// Builds the bands of every configured fan, keyed like [`crate::mappings::Mapping`].
+    pub fn from_cfg(cfg: &Config) -> HashMap<FanRef, CurveBands> {
+        cfg.controllers
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, ctrl)| {
+                let ControllerCfg::RiingQuad { fans, .. } = ctrl;
+                fans.iter().filter_map(move |fan| {
+                    let fan_ref = FanRef {
+                        controller_id: idx + 1,
+                        channel: fan.idx as usize,
+                    };
+                    Self::new(&fan.curve_bands, cfg.curve_band_hysteresis).map(|b| (fan_ref, b))
+                })
+            })
+            .collect()
+    }
+
+    fn band_at(&self, temp: f32) -> usize {
+        self.bands
+            .iter()
+            .rposition(|band| temp >= band.from)
+            .unwrap_or(0)
+    }
+
+    /// Returns the curve to switch to when `temp` crosses into another band.
+    pub fn update(&mut self, temp: f32) -> Option<&str> {
+        let raw = self.band_at(temp);
+        let next = match self.current {
+            None => raw,
+            Some(cur) if raw > cur => raw,
+            Some(cur) => self.band_at(temp + self.hysteresis).min(cur),
+        };
+        if self.current == Some(next) {
+            return None;
+        }
+        self.current = Some(next);
+        Some(&self.bands[next].curve)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::CurveBands;
+    use crate::config::CurveBandCfg;
+
+    fn bands() -> CurveBands {
+        let band = |from, curve: &str| CurveBandCfg {
+            from,
+            curve: curve.to_string(),
+        };
+        CurveBands::new(&[band(50.0, "performance"), band(0.0, "silent")], 2.0).unwrap()
+    }
+
+    #[test]
+    fn switches_once_per_crossing() {
+        let mut bands = bands();
+        let switches: Vec<_> = [40.0, 45.0, 50.0, 55.0, 49.0, 48.5, 47.9, 45.0, 51.0]
+            .into_iter()
+            .filter_map(|t| bands.update(t).map(str::to_string))
+            .collect();
+        assert_eq!(switches, ["silent", "performance", "silent", "performance"]);
+    }
+
+    #[test]
+    fn hovering_at_boundary_does_not_thrash() {
+        let mut bands = bands();
+        assert_eq!(bands.update(50.5), Some("performance"));
+        for t in [49.5, 50.2, 48.5, 50.0, 49.0] {
+            assert_eq!(bands.update(t), None, "switched at {t}");
+        }
+        assert_eq!(bands.update(47.0), Some("silent"));
+        assert_eq!(bands.update(49.9), None);
+    }
+
+    #[test]
+    fn empty_bands_are_disabled() {
+        assert!(CurveBands::new(&[], 2.0).is_none());
+    }
+}
@@ -0,0 +1,151 @@
+use std::collections::HashMap;
+
+use serde_json::{Value, json};
+
+use crate::{
+    config::{Config, ControllerCfg},
+    controller::Controllers,
+    mappings::{FanNames, FanRef},
+    services::TaskTable,
+};
+
+const REDACTED: &str = "<redacted>";
+
+/// One-shot JSON snapshot of the daemon for bug reports: config, mappings, latest sensor
+/// readings, fan state, controller firmware and service status. With `redact`, USB serial
+/// numbers are replaced so the dump can be shared publicly.
+pub async fn diagnostic_dump(
+    cfg: &Config,
+    controllers: &Controllers,
+    temperatures: &HashMap<String, f32>,
+    services: &TaskTable,
+    redact: bool,
+) -> Value {
+    let mut cfg = cfg.clone();
+    if redact {
+        for ctrl in &mut cfg.controllers {
+            let ControllerCfg::RiingQuad { usb, .. } = ctrl;
+            if let Some(serial) = usb.serial.as_mut() {
+                *serial = String::from(REDACTED);
+            }
+        }
+    }
+
+    let names = FanNames::from_cfg(&cfg);
+    let mut fans = vec![];
+    let mut firmware = vec![];
+    for controller in 1..=controllers.fan_counts().await.len() as u8 {
+        match controllers.read_all_fans(controller).await {
+            Ok(stats) => {
+                fans.extend(stats.into_iter().zip(1u8..).map(|((speed, rpm), channel)| {
+                    let fan = FanRef {
+                        controller_id: controller as usize,
+                        channel: channel as usize,
+                    };
+                    json!({
+                        "controller": controller,
+                        "channel": channel,
+                        "name": names.name(fan),
+                        "speed": speed,
+                        "rpm": rpm,
+                    })
+                }))
+            }
+            Err(e) => fans.push(json!({ "controller": controller, "error": e.to_string() })),
+        }
+        firmware.push(match controllers.get_firmware_version(controller).await {
+            Ok((major, minor, patch)) => json!({
+                "controller": controller,
+                "name": names.controller(controller),
+                "firmware": format!("{major}.{minor}.{patch}"),
+            }),
+            Err(e) => json!({
+                "controller": controller,
+                "name": names.controller(controller),
+                "error": e.to_string(),
+            }),
+        });
+    }
+
+    let tasks: Vec<Value> = services
+        .list_tasks()
+        .into_iter()
+        .map(|info| {
+            json!({
+                "name": info.name,
+                "status": info.status.to_string(),
+                "started": info.started,
+                "restarts": info.restarts,
+            })
+        })
+        .collect();
+
+    json!({
+        "version": env!("CARGO_PKG_VERSION"),
+        "mappings": {
+            "sensors": cfg.mappings,
+            "colors": cfg.color_mappings,
+            "fan_groups": cfg.fan_groups,
+        },
+        "config": cfg,
+        "sensors": temperatures,
+        "fans": fans,
+        "controllers": firmware,
+        "tasks": tasks,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashMap, future::pending};
+
+    use super::{REDACTED, diagnostic_dump};
+    use crate::{
+        config::{Config, ControllerCfg, MappingCfg},
+        controller::{Controllers, tests::MockController},
+        events::EventBus,
+        services::{CancelToken, Services},
+    };
+
+    #[tokio::test]
+    async fn dump_covers_the_whole_daemon() {
+        let cfg = Config::builder()
+            .profile("quiet")
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .serial("ABC123")
+                    .fan(1, "CPU left", &["Silent"]),
+            )
+            .mapping(MappingCfg::builder("cpu").target(1, 1).build())
+            .build();
+        let controllers = Controllers::from(vec![MockController::with_fans(1).boxed()]);
+        let temps = HashMap::from([(String::from("cpu"), 42.0)]);
+        let mut services = Services::new(EventBus::default(), CancelToken::default());
+        services.start("monitoring", tokio::spawn(pending()));
+
+        let dump = diagnostic_dump(&cfg, &controllers, &temps, &services.table(), true).await;
+        let keys: Vec<_> = dump.as_object().unwrap().keys().cloned().collect();
+        for key in [
+            "version",
+            "config",
+            "mappings",
+            "sensors",
+            "fans",
+            "controllers",
+            "tasks",
+        ] {
+            assert!(keys.iter().any(|k| k == key), "missing {key} in {keys:?}");
+        }
+        assert_eq!(dump["config"]["profile"], "quiet");
+        assert_eq!(dump["config"]["controllers"][0]["usb"]["serial"], REDACTED);
+        assert_eq!(dump["mappings"]["sensors"][0]["sensor"], "cpu");
+        assert_eq!(dump["sensors"]["cpu"], 42.0);
+        assert_eq!(dump["fans"][0]["name"], "CPU left");
+        assert_eq!(dump["controllers"][0]["firmware"], "1.0.0");
+        assert_eq!(dump["tasks"][0]["status"], "running");
+
+        let full = diagnostic_dump(&cfg, &controllers, &temps, &services.table(), false).await;
+        assert_eq!(full["config"]["controllers"][0]["usb"]["serial"], "ABC123");
+        services.shutdown().await;
+    }
+}
@@ -1,18 +1,39 @@
 use anyhow::{Result, anyhow};
-#[cfg(debug_assertions)]
-use log::info;
-use std::collections::HashMap;
+use log::{debug, info, warn};
+use std::{
+    collections::HashMap,
+    sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    time::Duration,
+};
 
-use crate::fan_curve::{FanCurve, Point};
+use crate::{
+    calibration,
+    config::{InitCfg, KickstartCfg, LedOrder},
+    events::{Event, EventBus},
+    fan_curve::{FanCurve, Point},
+    services::CancelToken,
+};
 
 use super::{
-    device_io::DeviceIO,
+    device_io::{DeviceIO, DeviceOpener, Direction},
     protocol::{Command, Response},
 };
 
 pub const READ_TIMEOUT: i32 = 250;
+pub const INIT_TIMEOUT: i32 = 1000;
+/// Consecutive HID failures after which the device handle is reopened.
+pub const RECONNECT_AFTER: u32 = 3;
+/// Addressable LEDs on a Riing Quad fan.
+pub const LED_COUNT: usize = 52;
+/// `SetRgb` mode that takes one color per LED.
+pub const RGB_MODE_PER_LED: u8 = 0x24;
 const MAX_ITERATIONS: usize = 100;
-const EPSILON: f32 = 1e-6;
+/// Bezier search tolerance, in °C.
+const EPSILON: f32 = 1e-3;
+/// Degrees below `off_below` a running zero-RPM fan must cool to before it stops.
+pub const ZERO_RPM_HYSTERESIS: f32 = 3.0;
+/// Kickstart pulses a fan gets in a row while it keeps reporting 0 RPM.
+pub const KICKSTART_ATTEMPTS: u8 = 3;
 
 #[derive(Debug)]
 pub struct Fan {
@@ -20,6 +41,127 @@ pub struct Fan {
     pub current_rpm: u16,
     pub active_curve: String,
     pub curve: HashMap<String, FanCurve>,
+    /// Used when `active_curve` is not in `curve`.
+    pub fallback: Option<FanCurve>,
+    pub fallback_warned: AtomicBool,
+    /// Last color written to the fan, before brightness scaling.
+    pub color: (u8, u8, u8),
+    pub kickstart: Option<KickstartCfg>,
+    /// Kickstart pulses since the fan last turned or was stopped.
+    pub kickstarts: u8,
+    /// Calibrated RPM at 100%, which [`FanCurve::RpmStepCurve`] targets are scaled by.
+    pub max_rpm: Option<u16>,
+}
+
+/// Curve speeds computed during the current tick, keyed by the curve's
+/// [`FanCurve::fingerprint`], whether the fan is running and the temperature rounded to
+/// `resolution`. The fingerprint rather than the curve id is used because
+/// `UpdateCurveData` can give two fans different data under the same id.
+#[derive(Debug, Default)]
+pub struct CurveCache {
+    /// Degrees temperatures are rounded to; 0 keeps them exact.
+    pub resolution: f32,
+    entries: HashMap<(Vec<u32>, bool, u32), u8>,
+    /// Lookups answered from the cache since the last [`Self::clear`].
+    pub hits: usize,
+}
+
+impl CurveCache {
+    pub fn new(resolution: f32) -> Self {
+        Self {
+            resolution,
+            ..Self::default()
+        }
+    }
+
+    /// `temp` rounded to the nearest multiple of `resolution`.
+    pub fn quantize(&self, temp: f32) -> f32 {
+        if self.resolution > 0.0 {
+            (temp / self.resolution).round() * self.resolution
+        } else {
+            temp
+        }
+    }
+
+    /// Speed of `curve` at `temp`, evaluated only on the first lookup for the same curve,
+    /// running state and rounded temperature this tick.
+    pub fn speed(&mut self, curve: &FanCurve, temp: f32, running: bool) -> Result<u8> {
+        let temp = self.quantize(temp);
+        let key = (curve.fingerprint(), running, temp.to_bits());
+        if let Some(&speed) = self.entries.get(&key) {
+            self.hits += 1;
+            return Ok(speed);
+        }
+        let speed = curve.speed_at(temp, running)?;
+        self.entries.insert(key, speed);
+        Ok(speed)
+    }
+
+    /// Forgets every speed, at the start of a tick.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.hits = 0;
+    }
+}
+
+/// Value of a step curve at `temp`, interpolated between the two points around it.
+// Windows are half-open, so a temperature on a shared point falls in the next one only; the
+// last window also takes its end. A window between two equal temperatures is a vertical step
+// and never matches, except as the last one.
+fn step_value<T: Copy + Into<f32>>(temps: &[f32], values: &[T], temp: f32) -> Result<f32> {
+    let last = temps.len().saturating_sub(2);
+    temps
+        .windows(2)
+        .zip(values.windows(2))
+        .enumerate()
+        .find_map(|(idx, (t, w))| {
+            let (t0, t1) = (t[0], t[1]);
+            let (v0, v1): (f32, f32) = (w[0].into(), w[1].into());
+            let inside = (t0..t1).contains(&temp) || (idx == last && (t0..=t1).contains(&temp));
+            if !inside {
+                return None;
+            }
+            let ratio = if t1 > t0 {
+                (temp - t0) / (t1 - t0)
+            } else {
+                1.0
+            };
+            Some(v0 * (1.0 - ratio) + v1 * ratio)
+        })
+        .ok_or(anyhow!("Temperature not found in curve"))
+}
+
+impl FanCurve {
+    /// Speed (percent) at `temp`. `running` tells a zero-RPM ramp whether the fan is
+    /// spinning, for its stop hysteresis.
+    pub fn speed_at(&self, temp: f32, running: bool) -> Result<u8> {
+        match self {
+            Self::Constant(speed) => Ok(*speed),
+            Self::StepCurve { temps, speeds } => {
+                step_value(temps, speeds, temp).map(|speed| speed.round().clamp(0.0, 100.0) as u8)
+            }
+            Self::RpmStepCurve { .. } => Err(anyhow!("RPM curve needs the fan's calibration")),
+            Self::BezierCurve { points } => {
+                if points.len() != 4 {
+                    Err(anyhow!("Bezier curve must have 4 points"))
+                } else {
+                    get_speed_for_temp(&points[0..4], temp).map(|speed| speed as u8)
+                }
+            }
+            Self::CatmullRom { points } => Ok(catmull_rom_speed_at(points, temp).round() as u8),
+            Self::ZeroRpmRamp {
+                off_below,
+                ramp_start_speed,
+                max_temp,
+                max_speed,
+            } => Ok(zero_rpm_speed_at(
+                (*off_below, *ramp_start_speed),
+                (*max_temp, *max_speed),
+                temp,
+                running,
+            )),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -28,23 +170,181 @@ pub struct Controller<Io: DeviceIO> {
     pub name: String,
     pub dev: Io,
     pub fans: Vec<Fan>,
+    pub brightness: f32,
+    pub led_order: LedOrder,
+    pub read_rpm: bool,
+    /// Minimum sum of speeds across all fans, see [`apply_airflow_floor`].
+    pub airflow_floor: Option<u16>,
+    pub init: InitCfg,
+    pub trace_hid: bool,
+    pub reopen: Option<Box<dyn DeviceOpener<Io>>>,
+    /// Consecutive failed reads/writes, reset by a successful read.
+    pub failures: AtomicU32,
+    pub curve_cache: CurveCache,
+    pub events: EventBus,
+    pub cancel: CancelToken,
 }
 
 impl<Io: DeviceIO> Controller<Io> {
+    pub fn fan_count(&self) -> usize {
+        self.fans.len()
+    }
+
+    /// Computes `(port, speed)` for every configured fan at `temp`, with the failures of the
+    /// fans left out, see [`Self::channel_targets`].
+    pub fn speed_targets(&mut self, temp: f32) -> (Vec<(u8, u8)>, Vec<String>) {
+        let channels: Vec<_> = (1..=self.fan_count() as u8)
+            .map(|port| (port, temp))
+            .collect();
+        self.port_speeds(&channels)
+    }
+
+    /// `(port, speed)` for each `(port, temp)` whose speed can be computed, and a message for
+    /// each that can't.
+    fn port_speeds(&mut self, channels: &[(u8, f32)]) -> (Vec<(u8, u8)>, Vec<String>) {
+        let mut targets = Vec::new();
+        let mut failed = Vec::new();
+        for &(port, temp) in channels {
+            match self.cached_speed(port, temp) {
+                Ok(speed) => targets.push((port, speed)),
+                Err(e) => failed.push(format!("fan {port}: {e}")),
+            }
+        }
+        (targets, failed)
+    }
+
+    /// Speed of fan `port` at `temp`, through the controller's [`CurveCache`].
+    pub fn cached_speed(&mut self, port: u8, temp: f32) -> Result<u8> {
+        // Borrows `fans` directly, since `Self::fan` would hold all of `self`.
+        let fan = self
+            .fans
+            .get((port as usize).wrapping_sub(1))
+            .ok_or(anyhow!("Fan {port} not found"))?;
+        fan.compute_speed(&mut self.curve_cache, temp)
+    }
+
+    /// Computes `(port, speed)` for the given `(port, temp)` pairs, then raises the slowest of
+    /// them if the controller's airflow floor would otherwise be violated. Fans outside
+    /// `channels` count towards the floor with their current speed. A fan whose speed can't
+    /// be computed is left out and reported in the returned failures, so it doesn't hold
+    /// back the others.
+    pub fn channel_targets(&mut self, channels: &[(u8, f32)]) -> (Vec<(u8, u8)>, Vec<String>) {
+        let (targets, failed) = self.port_speeds(channels);
+        (self.floor_targets(targets), failed)
+    }
+
+    /// Applies the airflow floor to already computed `(port, speed)` targets.
+    pub fn floor_targets(&self, mut targets: Vec<(u8, u8)>) -> Vec<(u8, u8)> {
+        if let Some(floor) = self.airflow_floor {
+            let others = (1..=self.fans.len() as u8)
+                .filter(|port| !targets.iter().any(|&(p, _)| p == *port))
+                .map(|port| self.fans[(port - 1) as usize].current_speed as u16)
+                .sum();
+            apply_airflow_floor(&mut targets, others, floor);
+        }
+        targets
+    }
+
+    pub fn fan(&self, port: u8) -> Result<&Fan> {
+        self.fans
+            .get((port as usize).wrapping_sub(1))
+            .ok_or(anyhow!("Fan {port} not found"))
+    }
+
+    fn write(&self, pkt: &[u8]) -> Result<usize> {
+        // Every exchange starts with a write, so at most the read in flight outlives shutdown.
+        if self.cancel.is_cancelled() {
+            return Err(anyhow!("{}: shutting down", self.name));
+        }
+        if self.trace_hid {
+            self.dev.trace(Direction::Write, pkt);
+        }
+        self.dev
+            .write(pkt)
+            .inspect_err(|_| _ = self.failures.fetch_add(1, Ordering::Relaxed))
+    }
+
+    fn read(&self, buf: &mut [u8], timeout: i32) -> Result<()> {
+        self.dev
+            .read(buf, timeout)
+            .inspect_err(|_| _ = self.failures.fetch_add(1, Ordering::Relaxed))?;
+        self.failures.store(0, Ordering::Relaxed);
+        if self.trace_hid {
+            self.dev.trace(Direction::Read, buf);
+        }
+        Ok(())
+    }
+
+    /// Reopens and re-initializes the device once [`RECONNECT_AFTER`] consecutive operations
+    /// have failed. Returns whether the controller is usable again.
+    pub fn reconnect(&mut self) -> bool {
+        if self.failures.load(Ordering::Relaxed) < RECONNECT_AFTER || self.cancel.is_cancelled() {
+            return false;
+        }
+        let Some(opener) = &self.reopen else {
+            return false;
+        };
+        match opener.open() {
+            Ok(dev) => self.dev = dev,
+            Err(e) => {
+                debug!("{}: reconnect failed: {e}", self.name);
+                return false;
+            }
+        }
+        self.failures.store(0, Ordering::Relaxed);
+        if let Err(e) = self.init() {
+            warn!("{}: reopened but init failed: {e}", self.name);
+            return false;
+        }
+        info!("{}: reconnected", self.name);
+        self.events.publish(Event::ControllerReconnected {
+            name: self.name.clone(),
+        });
+        true
+    }
+
+    /// Passes `res` through, trying to reconnect first if it is an error.
+    pub fn recovering<T>(&mut self, res: Result<T>) -> Result<T> {
+        if res.is_err() {
+            self.reconnect();
+        }
+        res
+    }
+
     fn request(&self, cmd: Command) -> Result<Response> {
         let pkt = cmd.to_bytes();
-        self.dev.write(&pkt)?;
+        self.write(&pkt)?;
         let mut buf = vec![0u8; cmd.expected_response_len()];
-        self.dev
-            .read(&mut buf, READ_TIMEOUT)
+        self.read(&mut buf, READ_TIMEOUT)
             .map_err(|e| anyhow!("{e}"))?;
         Response::parse(cmd, &buf)
     }
 
+    /// Sends the init packet and checks the status it is answered with, both as configured
+    /// in [`InitCfg`].
     pub fn init(&self) -> Result<()> {
-        match self.request(Command::Init)? {
-            Response::Status(0xFC) => Ok(()),
-            _ => Err(anyhow!("Invalid init response")),
+        let cmd = Command::Init;
+        let pkt = match &self.init.packet {
+            Some(packet) => [&[0x00], packet.as_slice()].concat(),
+            None => cmd.to_bytes(),
+        };
+        self.write(&pkt)
+            .map_err(|e| anyhow!("{}: init write failed: {e}", self.name))?;
+        let mut buf = vec![0u8; cmd.expected_response_len()];
+        self.read(&mut buf, INIT_TIMEOUT).map_err(|e| {
+            anyhow!(
+                "{}: no init response within {INIT_TIMEOUT} ms ({e}); check the USB cable and hidraw permissions",
+                self.name
+            )
+        })?;
+        let expected = self.init.status;
+        match Response::parse(cmd, &buf)? {
+            Response::Status(code) if code == expected => Ok(()),
+            Response::Status(code) => Err(anyhow!(
+                "{}: unexpected init status 0x{code:02X} (expected 0x{expected:02X}); firmware/protocol mismatch, see `init` in the controller's config",
+                self.name
+            )),
+            other => Err(anyhow!("{}: invalid init response {other:?}", self.name)),
         }
     }
 
@@ -73,6 +373,85 @@ impl<Io: DeviceIO> Controller<Io> {
         }
     }
 
+    /// Whether the fan on `port` reports RPM, read with [`Self::get_data`]. The controller
+    /// has no presence query, so this is the closest it gets: an empty port and a fan
+    /// stopped by a zero-RPM curve both read as not spinning.
+    pub fn port_spinning(&self, port: u8) -> Result<bool> {
+        self.get_data(port).map(|(_, rpm)| rpm > 0)
+    }
+
+    /// How long to run `port` at 100% before setting it to `speed`: the fan's kickstart when
+    /// `speed` is under its threshold and the fan goes from 0% to `speed`, or, when RPM is
+    /// read, still reports 0 RPM after fewer than [`KICKSTART_ATTEMPTS`] pulses.
+    pub fn kickstart(&self, port: u8, speed: u8) -> Option<Duration> {
+        let fan = self.fan(port).ok()?;
+        let kick = fan.kickstart?;
+        let stalled = self.read_rpm
+            && fan.current_rpm == 0
+            && (1..KICKSTART_ATTEMPTS).contains(&fan.kickstarts);
+        let starting = fan.current_speed == 0 || stalled;
+        (speed > 0 && speed < kick.below && starting).then(|| Duration::from_millis(kick.ms))
+    }
+
+    /// Sets `ports` to 100% for a kickstart, which the caller waits out before the real speed.
+    pub fn start_kickstart(&mut self, ports: &[u8]) -> Result<()> {
+        for &port in ports {
+            debug!("{}: kickstarting fan {port}", self.name);
+            self.set_speed(port, 100)?;
+            if let Some(fan) = self.fans.get_mut((port as usize).wrapping_sub(1)) {
+                fan.kickstarts = fan.kickstarts.saturating_add(1);
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets `port` to `speed` and reads back `(speed, rpm)`. With `read_rpm` disabled the
+    /// read is skipped and the commanded speed is returned with the fan's last known RPM.
+    pub fn apply_speed(&self, port: u8, speed: u8) -> Result<(u8, u16)> {
+        self.set_speed(port, speed)?;
+        if self.read_rpm {
+            return self.get_data(port);
+        }
+        let rpm = self
+            .fans
+            .get((port as usize).wrapping_sub(1))
+            .map_or(0, |fan| fan.current_rpm);
+        Ok((speed, rpm))
+    }
+
+    /// Sets several ports in one pass and reads back `(speed, rpm)` for each of them,
+    /// in the same order as `speeds`. A port that fails doesn't stop the ones after it.
+    pub fn set_speeds_batch(&self, speeds: &[(u8, u8)]) -> Vec<Result<(u8, u16)>> {
+        speeds
+            .iter()
+            .map(|&(port, speed)| self.apply_speed(port, speed))
+            .collect()
+    }
+
+    pub fn scale_color(&self, red: u8, green: u8, blue: u8) -> (u8, u8, u8) {
+        (
+            scale_channel(red, self.brightness),
+            scale_channel(green, self.brightness),
+            scale_channel(blue, self.brightness),
+        )
+    }
+
+    /// Sets `zones` of RGB colors on `port`, see [`color_package`].
+    pub fn set_rgb_zones(&self, port: u8, zones: &[[u8; 3]]) -> Result<()> {
+        let scaled: Vec<_> = zones
+            .iter()
+            .map(|&[r, g, b]| {
+                let (r, g, b) = self.scale_color(r, g, b);
+                [r, g, b]
+            })
+            .collect();
+        self.set_rgb(
+            port,
+            RGB_MODE_PER_LED,
+            color_package(&scaled, self.led_order)?,
+        )
+    }
+
     pub fn set_rgb(&self, port: u8, mode: u8, colors: Vec<(u8, u8, u8)>) -> Result<()> {
         match self.request(Command::SetRgb { port, mode, colors })? {
             Response::Status(0xFC) => Ok(()),
@@ -82,41 +461,46 @@ impl<Io: DeviceIO> Controller<Io> {
 }
 
 impl Fan {
-    pub fn compute_speed(&self, temp: f32) -> Result<u8> {
-        match self
-            .curve
-            .get(&self.active_curve)
-            .ok_or(anyhow!("Curve not found"))?
-        {
-            FanCurve::Constant(speed) => Ok(*speed),
-            FanCurve::StepCurve { temps, speeds } => temps
-                .windows(2)
-                .zip(speeds.windows(2))
-                .find_map(|(t, w)| {
-                    let (t0, t1) = (t[0], t[1]);
-                    let (s0, s1) = (w[0], w[1]);
-                    if (t0..=t1).contains(&temp) {
-                        let ratio = (temp - t0) / (t1 - t0);
-                        let speed = s0 as f32 * (1.0 - ratio) + s1 as f32 * ratio;
-                        Some(speed.round().clamp(0.0, 100.0) as u8)
-                    } else {
-                        None
-                    }
-                })
-                .ok_or(anyhow!("Temperature not found in curve")),
-            FanCurve::BezierCurve { points } => {
-                if points.len() != 4 {
-                    Err(anyhow!("Bezier curve must have 4 points"))
-                } else {
-                    Ok(get_speed_for_temp(&points[0..4], temp) as u8)
-                }
-            }
+    fn resolve_curve(&self) -> Result<&FanCurve> {
+        if let Some(curve) = self.curve.get(&self.active_curve) {
+            return Ok(curve);
         }
+        let fallback = self
+            .fallback
+            .as_ref()
+            .ok_or(anyhow!("Curve {} not found", self.active_curve))?;
+        if !self.fallback_warned.swap(true, Ordering::Relaxed) {
+            warn!(
+                "Curve {} not found, using the fallback curve",
+                self.active_curve
+            );
+        }
+        Ok(fallback)
+    }
+
+    /// Speed at `temp` from the active curve. RPM targets are turned into the nearest duty
+    /// here rather than through `cache`, since they depend on this fan's calibration.
+    pub fn compute_speed(&self, cache: &mut CurveCache, temp: f32) -> Result<u8> {
+        let curve = self.resolve_curve()?;
+        if let FanCurve::RpmStepCurve { temps, rpms } = curve {
+            let max_rpm = self.max_rpm.ok_or(anyhow!(
+                "Curve {} is in RPM but the fan is not calibrated",
+                self.active_curve
+            ))?;
+            return Ok(calibration::duty_for_rpm(
+                step_value(temps, rpms, temp)?,
+                max_rpm,
+            ));
+        }
+        cache.speed(curve, temp, self.current_speed > 0)
     }
 
     pub fn update_stats(&mut self, speed: u8, rpm: u16) {
         self.current_rpm = rpm;
         self.current_speed = speed;
+        if speed == 0 || rpm > 0 {
+            self.kickstarts = 0;
+        }
     }
 
     pub fn update_curve(&mut self, curve: &str) -> Result<()> {
@@ -124,6 +508,7 @@ impl Fan {
             .get(curve)
             .map(|_| {
                 self.active_curve = curve.to_string();
+                self.fallback_warned.store(false, Ordering::Relaxed);
                 Ok(())
             })
             .ok_or(anyhow!("Curve {curve} not found"))?
@@ -150,6 +535,65 @@ impl Fan {
     }
 }
 
+/// Raises the slowest targets one percent at a time until their sum plus `others` reaches
+/// `floor` or every target is at 100%.
+pub fn apply_airflow_floor(targets: &mut [(u8, u8)], others: u16, floor: u16) {
+    let mut total = others + targets.iter().map(|&(_, s)| s as u16).sum::<u16>();
+    while total < floor {
+        let Some(slowest) = targets
+            .iter_mut()
+            .filter(|(_, speed)| *speed < 100)
+            .min_by_key(|(_, speed)| *speed)
+        else {
+            break;
+        };
+        slowest.1 += 1;
+        total += 1;
+    }
+}
+
+/// Spreads `zones` of RGB colors evenly over the fan's [`LED_COUNT`] LEDs as the triples
+/// `SetRgb` expects, in the fan's `order`. One zone colors the whole fan, `LED_COUNT` zones
+/// address every LED.
+pub fn color_package(zones: &[[u8; 3]], order: LedOrder) -> Result<Vec<(u8, u8, u8)>> {
+    if zones.is_empty() || zones.len() > LED_COUNT {
+        return Err(anyhow!(
+            "Expected 1..={LED_COUNT} color zones, got {}",
+            zones.len()
+        ));
+    }
+    Ok((0..LED_COUNT)
+        .map(|led| order.arrange(zones[led * zones.len() / LED_COUNT]))
+        .collect())
+}
+
+fn scale_channel(value: u8, brightness: f32) -> u8 {
+    (value as f32 * brightness.clamp(0.0, 1.0))
+        .round()
+        .clamp(0.0, 255.0) as u8
+}
+
+/// Speed of a zero-RPM ramp at `temp`. A fan that is already `running` keeps spinning until
+/// the temperature drops [`ZERO_RPM_HYSTERESIS`] degrees below the threshold.
+pub fn zero_rpm_speed_at(start: (f32, u8), end: (f32, u8), temp: f32, running: bool) -> u8 {
+    let (off_below, start_speed) = start;
+    let (max_temp, max_speed) = end;
+    let threshold = if running {
+        off_below - ZERO_RPM_HYSTERESIS
+    } else {
+        off_below
+    };
+    if temp < threshold {
+        return 0;
+    }
+    if temp >= max_temp || max_temp <= off_below {
+        return max_speed.min(100);
+    }
+    let ratio = ((temp - off_below) / (max_temp - off_below)).clamp(0.0, 1.0);
+    let speed = start_speed as f32 + (max_speed as f32 - start_speed as f32) * ratio;
+    speed.round().clamp(0.0, 100.0) as u8
+}
+
 fn compute_bezier_at_t(pts: &[Point], t: f32) -> Point {
     let u = 1.0 - t;
     let tt = t * t;
@@ -164,17 +608,27 @@ fn compute_bezier_at_t(pts: &[Point], t: f32) -> Point {
     (x, y).into()
 }
 
-pub fn get_speed_for_temp(pts: &[Point], temp: f32) -> f32 {
+/// Finds the curve's `y` at `x == temp` by bisection over `t`, assuming `x` is monotonic
+/// (checked when the config is loaded). Temperatures outside the curve take the nearest
+/// endpoint's speed.
+pub fn get_speed_for_temp(pts: &[Point], temp: f32) -> Result<f32> {
+    let (first, last) = (&pts[0], &pts[pts.len() - 1]);
+    if temp <= first.x {
+        return Ok(first.y);
+    }
+    if temp >= last.x {
+        return Ok(last.y);
+    }
+
     let mut t_low = 0.0_f32;
     let mut t_high = 1.0_f32;
-    let mut t_mid = 0.0_f32;
 
     for _ in 0..MAX_ITERATIONS {
-        t_mid = (t_low + t_high) * 0.5;
+        let t_mid = (t_low + t_high) * 0.5;
         let p = compute_bezier_at_t(pts, t_mid);
 
         if (p.x - temp).abs() < EPSILON {
-            return p.y;
+            return Ok(p.y);
         }
         if p.x < temp {
             t_low = t_mid;
@@ -183,6 +637,561 @@ pub fn get_speed_for_temp(pts: &[Point], temp: f32) -> f32 {
         }
     }
 
-    let p = compute_bezier_at_t(pts, t_mid);
-    p.y
+    Err(anyhow!(
+        "Bezier search for {temp}°C did not converge in {MAX_ITERATIONS} iterations"
+    ))
+}
+
+/// Evaluates a Catmull-Rom spline through `pts`, whose `x` must be strictly increasing
+/// (checked when the config is loaded). Tangents are central differences, one-sided at the
+/// ends. Temperatures outside the points take the nearest endpoint's speed.
+pub fn catmull_rom_speed_at(pts: &[Point], temp: f32) -> f32 {
+    let (Some(first), Some(last)) = (pts.first(), pts.last()) else {
+        return 0.0;
+    };
+    if temp <= first.x || temp.is_nan() {
+        return first.y.clamp(0.0, 100.0);
+    }
+    if temp >= last.x {
+        return last.y.clamp(0.0, 100.0);
+    }
+    let i = pts
+        .windows(2)
+        .position(|w| temp < w[1].x)
+        .unwrap_or(pts.len() - 2);
+    let slope = |a: &Point, b: &Point| (b.y - a.y) / (b.x - a.x);
+    let tangent = |k: usize| slope(&pts[k.saturating_sub(1)], &pts[(k + 1).min(pts.len() - 1)]);
+    let (p0, p1) = (&pts[i], &pts[i + 1]);
+    let h = p1.x - p0.x;
+    let t = (temp - p0.x) / h;
+    let (t2, t3) = (t * t, t * t * t);
+    let y = (2.0 * t3 - 3.0 * t2 + 1.0) * p0.y
+        + (t3 - 2.0 * t2 + t) * h * tangent(i)
+        + (-2.0 * t3 + 3.0 * t2) * p1.y
+        + (t3 - t2) * h * tangent(i + 1);
+    y.clamp(0.0, 100.0)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::{
+        collections::HashMap,
+        sync::atomic::{AtomicBool, AtomicU32, Ordering},
+    };
+
+    use super::{
+        Controller, CurveCache, Fan, LED_COUNT, ZERO_RPM_HYSTERESIS, apply_airflow_floor,
+        catmull_rom_speed_at, color_package, get_speed_for_temp, scale_channel, zero_rpm_speed_at,
+    };
+    use crate::config::{InitCfg, LedOrder};
+    use crate::drivers::tt_riing_quad::device_io::Direction;
+    use crate::drivers::tt_riing_quad::device_io::tests::StubIo;
+    use crate::events::EventBus;
+    use crate::fan_curve::{FanCurve, Point};
+    use crate::services::CancelToken;
+
+    pub(crate) fn controller(responses: Vec<Vec<u8>>) -> Controller<StubIo> {
+        Controller {
+            name: String::from("test"),
+            dev: StubIo::new(responses),
+            fans: vec![],
+            brightness: 1.0,
+            led_order: LedOrder::Grb,
+            read_rpm: true,
+            airflow_floor: None,
+            init: InitCfg::default(),
+            trace_hid: false,
+            reopen: None,
+            failures: AtomicU32::new(0),
+            curve_cache: CurveCache::default(),
+            events: EventBus::default(),
+            cancel: CancelToken::default(),
+        }
+    }
+
+    pub(crate) fn fan(speed: u8, rpm: u16) -> Fan {
+        Fan {
+            current_speed: speed,
+            current_rpm: rpm,
+            active_curve: String::new(),
+            curve: HashMap::new(),
+            fallback: None,
+            fallback_warned: AtomicBool::new(false),
+            color: (0, 0, 0),
+            kickstart: None,
+            kickstarts: 0,
+            max_rpm: None,
+        }
+    }
+
+    pub(crate) fn status_ok() -> Vec<u8> {
+        vec![0x00, 0x00, 0xFC]
+    }
+
+    pub(crate) fn data(speed: u8, rpm: u16) -> Vec<u8> {
+        let [lo, hi] = rpm.to_le_bytes();
+        vec![0x00, 0x00, speed, lo, hi]
+    }
+
+    pub(crate) fn constant_fan(speed: u8) -> Fan {
+        Fan {
+            active_curve: String::from("Constant"),
+            curve: HashMap::from([(String::from("Constant"), FanCurve::Constant(speed))]),
+            ..fan(0, 0)
+        }
+    }
+
+    #[test]
+    fn missing_active_curve_uses_fallback_and_warns_once() {
+        let mut fan = fan(0, 0);
+        fan.active_curve = String::from("Removed");
+        assert!(fan.compute_speed(&mut CurveCache::default(), 40.0).is_err());
+
+        fan.fallback = Some(FanCurve::Constant(70));
+        assert_eq!(
+            fan.compute_speed(&mut CurveCache::default(), 40.0).unwrap(),
+            70
+        );
+        assert!(fan.fallback_warned.load(Ordering::Relaxed));
+        assert_eq!(
+            fan.compute_speed(&mut CurveCache::default(), 45.0).unwrap(),
+            70
+        );
+
+        fan.curve
+            .insert(String::from("Quiet"), FanCurve::Constant(20));
+        fan.update_curve("Quiet").unwrap();
+        assert!(!fan.fallback_warned.load(Ordering::Relaxed));
+        assert_eq!(
+            fan.compute_speed(&mut CurveCache::default(), 40.0).unwrap(),
+            20
+        );
+    }
+
+    #[test]
+    fn speed_targets_cover_configured_fans() {
+        for count in [3u8, 8] {
+            let mut ctrl = controller(vec![]);
+            ctrl.fans = (0..count).map(|i| constant_fan(10 + i)).collect();
+
+            assert_eq!(ctrl.fan_count(), count as usize);
+            let (targets, failed) = ctrl.speed_targets(40.0);
+            assert!(failed.is_empty());
+            let expected: Vec<_> = (1..=count).map(|port| (port, 9 + port)).collect();
+            assert_eq!(targets, expected);
+        }
+    }
+
+    #[test]
+    fn trace_hook_sees_exact_packets() {
+        let mut ctrl = controller(vec![status_ok()]);
+        ctrl.set_speed(2, 40).unwrap();
+        assert!(ctrl.dev.traced().is_empty());
+
+        ctrl.trace_hid = true;
+        ctrl.dev = StubIo::new(vec![status_ok()]);
+        ctrl.set_speed(2, 40).unwrap();
+
+        let traced = ctrl.dev.traced();
+        assert_eq!(
+            traced[0],
+            (Direction::Write, vec![0x00, 0x32, 0x51, 2, 0x01, 40])
+        );
+        assert_eq!(traced[1].0, Direction::Read);
+        assert_eq!(traced[1].1[..3], [0x00, 0x00, 0xFC]);
+    }
+
+    #[test]
+    fn init_distinguishes_timeout_from_bad_status() {
+        let ok = controller(vec![status_ok()]);
+        assert!(ok.init().is_ok());
+
+        let timeout = controller(vec![]).init().unwrap_err().to_string();
+        assert!(timeout.contains("no init response"), "{timeout}");
+
+        let mismatch = controller(vec![vec![0x00, 0x00, 0x01]])
+            .init()
+            .unwrap_err()
+            .to_string();
+        assert!(
+            mismatch.contains("unexpected init status 0x01"),
+            "{mismatch}"
+        );
+    }
+
+    #[test]
+    fn configured_init_status_and_packet_are_used() {
+        let mut variant = controller(vec![vec![0x00, 0x00, 0xFA]]);
+        variant.init = InitCfg {
+            status: 0xFA,
+            packet: Some(vec![0xFE, 0x34]),
+        };
+        assert!(variant.init().is_ok());
+        assert_eq!(variant.dev.written(), vec![vec![0x00, 0xFE, 0x34]]);
+
+        // The stock status is then a mismatch like any other.
+        variant.dev = StubIo::new(vec![status_ok()]);
+        let err = variant.init().unwrap_err().to_string();
+        assert!(err.contains("0xFC (expected 0xFA)"), "{err}");
+    }
+
+    #[test]
+    fn batched_speeds_match_per_fan_path() {
+        let targets = [(1, 30), (2, 55), (3, 100)];
+        let responses: Vec<_> = targets
+            .iter()
+            .flat_map(|&(port, speed)| [status_ok(), data(speed, 500 * port as u16)])
+            .collect();
+
+        let batched = controller(responses.clone());
+        let batched_stats: Vec<_> = batched
+            .set_speeds_batch(&targets)
+            .into_iter()
+            .map(Result::unwrap)
+            .collect();
+
+        let single = controller(responses);
+        let single_stats: Vec<_> = targets
+            .iter()
+            .map(|&(port, speed)| {
+                single.set_speed(port, speed).unwrap();
+                single.get_data(port).unwrap()
+            })
+            .collect();
+
+        assert_eq!(batched_stats, single_stats);
+        assert_eq!(batched_stats, vec![(30, 500), (55, 1000), (100, 1500)]);
+        assert_eq!(batched.dev.written(), single.dev.written());
+    }
+
+    #[test]
+    fn skipping_rpm_read_keeps_previous_rpm() {
+        let mut ctrl = controller(vec![status_ok()]);
+        ctrl.read_rpm = false;
+        ctrl.fans = vec![fan(20, 900)];
+
+        assert_eq!(ctrl.apply_speed(1, 60).unwrap(), (60, 900));
+        assert_eq!(
+            ctrl.dev.written(),
+            vec![vec![0x00, 0x32, 0x51, 1, 0x01, 60]]
+        );
+        assert_eq!(ctrl.dev.pending_responses(), 0);
+    }
+
+    #[test]
+    fn rpm_read_enabled_reads_back() {
+        let mut ctrl = controller(vec![status_ok(), data(60, 1200)]);
+        ctrl.fans = vec![fan(20, 900)];
+
+        assert_eq!(ctrl.apply_speed(1, 60).unwrap(), (60, 1200));
+        assert_eq!(ctrl.dev.written().len(), 2);
+    }
+
+    #[test]
+    fn port_spinning_follows_its_rpm() {
+        let ctrl = controller(vec![data(40, 1200), data(40, 0)]);
+
+        assert!(ctrl.port_spinning(1).unwrap());
+        assert!(!ctrl.port_spinning(2).unwrap());
+        assert_eq!(
+            ctrl.dev.written(),
+            vec![vec![0x00, 0x33, 0x51, 1], vec![0x00, 0x33, 0x51, 2]]
+        );
+    }
+
+    #[test]
+    fn brightness_scales_each_channel() {
+        assert_eq!(scale_channel(200, 0.5), 100);
+        assert_eq!(scale_channel(255, 0.5), 128);
+        assert_eq!(scale_channel(1, 0.5), 1);
+        assert_eq!(scale_channel(0, 0.5), 0);
+    }
+
+    #[test]
+    fn full_brightness_is_noop_and_out_of_range_is_clamped() {
+        for v in [0, 1, 127, 128, 255] {
+            assert_eq!(scale_channel(v, 1.0), v);
+            assert_eq!(scale_channel(v, 1.5), v);
+            assert_eq!(scale_channel(v, -1.0), 0);
+        }
+    }
+
+    const RAMP_START: (f32, u8) = (50.0, 30);
+    const RAMP_END: (f32, u8) = (80.0, 90);
+
+    #[test]
+    fn zero_rpm_ramp_is_off_below_threshold() {
+        assert_eq!(zero_rpm_speed_at(RAMP_START, RAMP_END, 20.0, false), 0);
+        assert_eq!(zero_rpm_speed_at(RAMP_START, RAMP_END, 49.9, false), 0);
+    }
+
+    #[test]
+    fn zero_rpm_ramp_jumps_then_ramps() {
+        assert_eq!(zero_rpm_speed_at(RAMP_START, RAMP_END, 50.0, false), 30);
+        assert_eq!(zero_rpm_speed_at(RAMP_START, RAMP_END, 65.0, true), 60);
+        assert_eq!(zero_rpm_speed_at(RAMP_START, RAMP_END, 80.0, true), 90);
+    }
+
+    #[test]
+    fn zero_rpm_ramp_clamps_above_max_temp() {
+        assert_eq!(zero_rpm_speed_at(RAMP_START, RAMP_END, 95.0, true), 90);
+        assert_eq!(zero_rpm_speed_at(RAMP_START, (80.0, 150), 95.0, true), 100);
+    }
+
+    #[test]
+    fn zero_rpm_ramp_has_hysteresis_around_threshold() {
+        let just_below = 50.0 - ZERO_RPM_HYSTERESIS / 2.0;
+        assert_eq!(
+            zero_rpm_speed_at(RAMP_START, RAMP_END, just_below, false),
+            0
+        );
+        assert_eq!(
+            zero_rpm_speed_at(RAMP_START, RAMP_END, just_below, true),
+            30
+        );
+        let cooled = 50.0 - ZERO_RPM_HYSTERESIS - 0.1;
+        assert_eq!(zero_rpm_speed_at(RAMP_START, RAMP_END, cooled, true), 0);
+
+        let mut fan = constant_fan(0);
+        fan.curve.insert(
+            String::from("Constant"),
+            FanCurve::ZeroRpmRamp {
+                off_below: 50.0,
+                ramp_start_speed: 30,
+                max_temp: 80.0,
+                max_speed: 90,
+            },
+        );
+        assert_eq!(
+            fan.compute_speed(&mut CurveCache::default(), just_below)
+                .unwrap(),
+            0
+        );
+        fan.update_stats(30, 600);
+        assert_eq!(
+            fan.compute_speed(&mut CurveCache::default(), just_below)
+                .unwrap(),
+            30
+        );
+    }
+
+    #[test]
+    fn airflow_floor_raises_slowest_fans() {
+        let mut targets = vec![(1, 20), (2, 20), (3, 30)];
+        apply_airflow_floor(&mut targets, 0, 120);
+        assert_eq!(targets.iter().map(|&(_, s)| s as u16).sum::<u16>(), 120);
+        assert_eq!(targets, vec![(1, 40), (2, 40), (3, 40)]);
+
+        let mut targets = vec![(1, 90), (2, 95)];
+        apply_airflow_floor(&mut targets, 0, 400);
+        assert_eq!(targets, vec![(1, 100), (2, 100)]);
+    }
+
+    #[test]
+    fn channel_targets_respect_airflow_floor() {
+        let mut ctrl = controller(vec![]);
+        ctrl.fans = vec![constant_fan(10), constant_fan(10), constant_fan(10)];
+        ctrl.fans[2].current_speed = 50;
+        assert_eq!(
+            ctrl.channel_targets(&[(1, 40.0), (2, 40.0)]).0,
+            vec![(1, 10), (2, 10)]
+        );
+
+        ctrl.airflow_floor = Some(100);
+        let (targets, failed) = ctrl.channel_targets(&[(1, 40.0), (2, 40.0)]);
+        assert_eq!(targets, vec![(1, 25), (2, 25)]);
+        assert!(failed.is_empty());
+
+        // A missing fan is reported without dropping the one next to it.
+        let (targets, failed) = ctrl.channel_targets(&[(4, 40.0), (1, 40.0)]);
+        assert_eq!(targets, vec![(1, 50)]);
+        assert_eq!(failed, ["fan 4: Fan 4 not found"]);
+    }
+
+    #[test]
+    fn curve_cache_reuses_speeds_until_cleared() {
+        let step = |low| FanCurve::StepCurve {
+            temps: vec![20.0, 60.0],
+            speeds: vec![low, 100],
+        };
+        let step_fan = |low| Fan {
+            active_curve: String::from("Step"),
+            curve: HashMap::from([(String::from("Step"), step(low))]),
+            ..fan(0, 0)
+        };
+        let mut ctrl = controller(vec![]);
+        ctrl.curve_cache = CurveCache::new(0.5);
+        ctrl.fans = vec![step_fan(20), step_fan(20), step_fan(0)];
+
+        assert_eq!(ctrl.cached_speed(1, 40.2).unwrap(), 60);
+        assert_eq!(ctrl.cached_speed(2, 40.1).unwrap(), 60);
+        assert_eq!(
+            (ctrl.curve_cache.entries.len(), ctrl.curve_cache.hits),
+            (1, 1)
+        );
+        // Same id, different data after an update: evaluated on its own.
+        assert_eq!(ctrl.cached_speed(3, 40.0).unwrap(), 50);
+        assert_eq!(ctrl.cached_speed(1, 40.3).unwrap(), 61);
+        assert_eq!(
+            (ctrl.curve_cache.entries.len(), ctrl.curve_cache.hits),
+            (3, 1)
+        );
+
+        ctrl.curve_cache.clear();
+        assert_eq!(
+            (ctrl.curve_cache.entries.len(), ctrl.curve_cache.hits),
+            (0, 0)
+        );
+        assert_eq!(ctrl.cached_speed(2, 40.1).unwrap(), 60);
+        assert_eq!(
+            (ctrl.curve_cache.entries.len(), ctrl.curve_cache.hits),
+            (1, 0)
+        );
+        assert!(ctrl.cached_speed(4, 40.0).is_err());
+    }
+
+    #[test]
+    fn rpm_curve_targets_map_to_calibrated_duty() {
+        let rpm_fan = |max_rpm| Fan {
+            active_curve: String::from("Quiet"),
+            curve: HashMap::from([(
+                String::from("Quiet"),
+                FanCurve::RpmStepCurve {
+                    temps: vec![30.0, 60.0, 80.0],
+                    rpms: vec![600, 1200, 2400],
+                },
+            )]),
+            max_rpm,
+            ..fan(0, 0)
+        };
+        let mut ctrl = controller(vec![]);
+        ctrl.fans = vec![rpm_fan(Some(2000)), rpm_fan(Some(1500)), rpm_fan(None)];
+
+        // 2000 RPM at 100%: 600 RPM is 30%, 900 RPM 45% and 1200 RPM 60%.
+        assert_eq!(ctrl.cached_speed(1, 30.0).unwrap(), 30);
+        assert_eq!(ctrl.cached_speed(1, 45.0).unwrap(), 45);
+        assert_eq!(ctrl.cached_speed(1, 60.0).unwrap(), 60);
+        // Targets above the fan's maximum run it at full speed.
+        assert_eq!(ctrl.cached_speed(1, 80.0).unwrap(), 100);
+        assert_eq!(ctrl.cached_speed(2, 60.0).unwrap(), 80);
+        assert_eq!(ctrl.curve_cache.entries.len(), 0);
+
+        let err = ctrl.cached_speed(3, 45.0).unwrap_err().to_string();
+        assert!(err.contains("not calibrated"), "{err}");
+    }
+
+    #[test]
+    fn color_package_spreads_zones_over_leds() {
+        let single = color_package(&[[1, 2, 3]], LedOrder::Grb).unwrap();
+        assert_eq!(single, vec![(2, 1, 3); LED_COUNT]);
+
+        let halves = color_package(&[[255, 0, 0], [0, 0, 255]], LedOrder::Grb).unwrap();
+        assert!(halves[..LED_COUNT / 2].iter().all(|&c| c == (0, 255, 0)));
+        assert!(halves[LED_COUNT / 2..].iter().all(|&c| c == (0, 0, 255)));
+
+        assert!(color_package(&[], LedOrder::Grb).is_err());
+        assert!(color_package(&[[0, 0, 0]; LED_COUNT + 1], LedOrder::Grb).is_err());
+    }
+
+    #[test]
+    fn pure_red_follows_the_led_order() {
+        for (order, bytes) in [
+            (LedOrder::Rgb, [255, 0, 0]),
+            (LedOrder::Grb, [0, 255, 0]),
+            (LedOrder::Bgr, [0, 0, 255]),
+        ] {
+            let mut ctrl = controller(vec![status_ok()]);
+            ctrl.led_order = order;
+            ctrl.set_rgb_zones(1, &[[255, 0, 0]]).unwrap();
+
+            let written = ctrl.dev.written();
+            for led in written[0][5..].chunks(3) {
+                assert_eq!(led, bytes, "{order:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn set_rgb_zones_writes_distinct_led_colors() {
+        let mut ctrl = controller(vec![status_ok()]);
+        ctrl.brightness = 0.5;
+        let zones: Vec<[u8; 3]> = (0..LED_COUNT as u8).map(|i| [i * 2, 0, 200]).collect();
+        ctrl.set_rgb_zones(2, &zones).unwrap();
+
+        let written = ctrl.dev.written();
+        let pkt = &written[0];
+        assert_eq!(pkt[..5], [0x00, 0x32, 0x52, 2, 0x24]);
+        for (led, grb) in pkt[5..].chunks(3).enumerate() {
+            assert_eq!(grb, [0, led as u8, 100]);
+        }
+    }
+
+    #[test]
+    fn step_curve_handles_shared_and_duplicate_points() {
+        let step = |temps: &[f32], speeds: &[u8]| FanCurve::StepCurve {
+            temps: temps.to_vec(),
+            speeds: speeds.to_vec(),
+        };
+        let curve = step(&[-10.0, 30.0, 50.0, 50.0, 70.0], &[0, 20, 40, 80, 100]);
+        let speed = |temp| curve.speed_at(temp, false).unwrap();
+
+        assert_eq!(speed(-10.0), 0);
+        assert_eq!(speed(30.0), 20);
+        assert_eq!(speed(40.0), 30);
+        assert_eq!(speed(49.9), 40);
+        assert_eq!(speed(50.0), 80);
+        assert_eq!(speed(60.0), 90);
+        assert_eq!(speed(70.0), 100);
+        assert!(curve.speed_at(70.5, false).is_err());
+        assert!(curve.speed_at(-10.5, false).is_err());
+
+        let flat_end = step(&[30.0, 60.0, 60.0], &[20, 50, 90]);
+        assert_eq!(flat_end.speed_at(60.0, false).unwrap(), 90);
+        assert_eq!(flat_end.speed_at(45.0, false).unwrap(), 35);
+    }
+
+    #[test]
+    fn bezier_clamps_outside_its_range() {
+        let pts: Vec<Point> = [(30.0, 20.0), (50.0, 20.0), (60.0, 100.0), (80.0, 100.0)]
+            .into_iter()
+            .map(Point::from)
+            .collect();
+
+        assert_eq!(get_speed_for_temp(&pts, 10.0).unwrap(), 20.0);
+        assert_eq!(get_speed_for_temp(&pts, 30.0).unwrap(), 20.0);
+        assert_eq!(get_speed_for_temp(&pts, 95.0).unwrap(), 100.0);
+
+        let near_end = get_speed_for_temp(&pts, 79.99).unwrap();
+        assert!((99.0..=100.0).contains(&near_end), "{near_end}");
+        let mid = get_speed_for_temp(&pts, 55.0).unwrap();
+        assert!((55.0..=65.0).contains(&mid), "{mid}");
+
+        assert!(get_speed_for_temp(&pts, f32::NAN).is_err());
+    }
+
+    #[test]
+    fn catmull_rom_passes_through_its_points() {
+        let pts: Vec<Point> = [(30.0, 20.0), (50.0, 35.0), (70.0, 80.0), (90.0, 100.0)]
+            .into_iter()
+            .map(Point::from)
+            .collect();
+
+        for p in &pts {
+            assert!(
+                (catmull_rom_speed_at(&pts, p.x) - p.y).abs() < 1e-3,
+                "{p:?}"
+            );
+        }
+        assert_eq!(catmull_rom_speed_at(&pts, 10.0), 20.0);
+        assert_eq!(catmull_rom_speed_at(&pts, 95.0), 100.0);
+        assert_eq!(catmull_rom_speed_at(&pts, f32::NAN), 20.0);
+
+        // Rising points give a rising curve that stays between its neighbours.
+        let samples: Vec<f32> = (300..=900)
+            .map(|t| catmull_rom_speed_at(&pts, t as f32 / 10.0))
+            .collect();
+        assert!(samples.windows(2).all(|w| w[0] <= w[1] + 1e-3));
+        let mid = catmull_rom_speed_at(&pts, 60.0);
+        assert!((35.0..=80.0).contains(&mid), "{mid}");
+    }
 }
@@ -1,9 +1,104 @@
 use anyhow::{Ok, Result, anyhow};
-use hidapi::{HidDevice, HidError};
+use hidapi::{HidApi, HidDevice, HidError};
+use log::{debug, info};
+use std::{fmt::Debug, io, thread, time::Duration};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Write,
+    Read,
+}
 
 pub trait DeviceIO: Send + 'static {
     fn write(&self, buf: &[u8]) -> Result<usize>;
     fn read(&self, buf: &mut [u8], timeout: i32) -> Result<()>;
+
+    /// Called with every raw packet when HID tracing is enabled.
+    fn trace(&self, direction: Direction, buf: &[u8]) {
+        debug!("HID {direction:?}: {}", hex(buf));
+    }
+}
+
+/// Opens a fresh handle for a device, used to reconnect after it was unplugged.
+pub trait DeviceOpener<Io>: Send + Sync + Debug {
+    fn open(&self) -> Result<Io>;
+}
+
+#[derive(Debug, Clone)]
+pub struct HidOpener {
+    pub vid: u16,
+    pub pid: u16,
+    pub serial: Option<String>,
+}
+
+impl DeviceOpener<HidDevice> for HidOpener {
+    fn open(&self) -> Result<HidDevice> {
+        // A new `HidApi` enumerates devices again, so a replugged controller is found.
+        let api = HidApi::new().map_err(|e| anyhow!("{e}"))?;
+        match &self.serial {
+            Some(serial) => api.open_serial(self.vid, self.pid, serial),
+            None => api.open(self.vid, self.pid),
+        }
+        .map_err(open_error)
+    }
+}
+
+/// Explains an open failure where the device exists but is busy or denied, which usually
+/// means another program holds it or the udev rule is missing.
+fn open_error(e: HidError) -> anyhow::Error {
+    let busy = match &e {
+        HidError::IoError { error } => matches!(
+            error.kind(),
+            io::ErrorKind::PermissionDenied | io::ErrorKind::ResourceBusy
+        ),
+        HidError::HidApiError { message } => {
+            let message = message.to_lowercase();
+            ["permission denied", "access denied", "busy"]
+                .iter()
+                .any(|hint| message.contains(hint))
+        }
+        _ => false,
+    };
+    if !busy {
+        return anyhow!("{e}");
+    }
+    anyhow!(
+        "{e}: the device is busy or not accessible. Another program (OpenRGB, liquidctl, \
+         TT RGB Plus) may be holding it; find it with `fuser -v /dev/hidraw*` and stop it. \
+         If nothing else uses it, install 99-tt-riingd.rules for non-root access"
+    )
+}
+
+/// Opens every device, retrying only the ones that failed up to `attempts` times in total
+/// with `delay` in between. Results are in the order of `openers`. The delays block the
+/// calling thread, so async callers run this on the blocking pool.
+pub fn open_with_retry<Io>(
+    openers: &[&dyn DeviceOpener<Io>],
+    attempts: u32,
+    delay: Duration,
+) -> Vec<Result<Io>> {
+    let mut opened: Vec<Result<Io>> = openers.iter().map(|o| o.open()).collect();
+    for attempt in 2..=attempts {
+        let missing = opened.iter().filter(|r| r.is_err()).count();
+        if missing == 0 {
+            break;
+        }
+        info!("Waiting for {missing} USB device(s) to appear (attempt {attempt}/{attempts})");
+        thread::sleep(delay);
+        for (opener, slot) in openers.iter().zip(opened.iter_mut()) {
+            if slot.is_err() {
+                *slot = opener.open();
+            }
+        }
+    }
+    opened
+}
+
+pub fn hex(buf: &[u8]) -> String {
+    buf.iter()
+        .map(|b| format!("{b:02X}"))
+        .collect::<Vec<_>>()
+        .join(" ")
 }
 
 impl DeviceIO for HidDevice {
@@ -24,41 +119,149 @@ impl DeviceIO for HidDevice {
 }
 
 #[cfg(test)]
-mod tests {
-    use crate::drivers::tt_riing_quad::controller::READ_TIMEOUT;
+pub(crate) mod tests {
+    use crate::{drivers::tt_riing_quad::controller::READ_TIMEOUT, services::CancelToken};
 
-    use super::DeviceIO;
-    use anyhow::Result;
-    use std::sync::Mutex;
+    use super::{DeviceIO, DeviceOpener, Direction, hex, open_error, open_with_retry};
+    use anyhow::{Result, anyhow};
+    use hidapi::HidError;
+    use std::{
+        collections::VecDeque,
+        io,
+        sync::{Mutex, PoisonError},
+        time::Duration,
+    };
 
-    struct StubIo {
+    #[derive(Debug)]
+    pub(crate) struct StubIo {
         written: Mutex<Vec<Vec<u8>>>,
         responses: Mutex<Vec<Vec<u8>>>,
+        traced: Mutex<Vec<(Direction, Vec<u8>)>>,
+        cancel_on_read: Option<CancelToken>,
     }
 
     impl StubIo {
-        fn new(resps: Vec<Vec<u8>>) -> Self {
+        pub(crate) fn new(resps: Vec<Vec<u8>>) -> Self {
             StubIo {
                 written: Mutex::new(vec![]),
                 responses: Mutex::new(resps),
+                traced: Mutex::new(vec![]),
+                cancel_on_read: None,
             }
         }
-        fn written(&self) -> Vec<Vec<u8>> {
-            self.written.lock().unwrap().clone()
+        /// Cancels `token` as each read starts, like a shutdown arriving while the device
+        /// is still answering.
+        pub(crate) fn with_cancel_on_read(mut self, token: CancelToken) -> Self {
+            self.cancel_on_read = Some(token);
+            self
+        }
+        pub(crate) fn written(&self) -> Vec<Vec<u8>> {
+            self.written
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
+        }
+        pub(crate) fn pending_responses(&self) -> usize {
+            self.responses
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .len()
+        }
+        pub(crate) fn traced(&self) -> Vec<(Direction, Vec<u8>)> {
+            self.traced
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone()
         }
     }
 
     impl DeviceIO for StubIo {
         fn write(&self, buf: &[u8]) -> Result<usize> {
-            self.written.lock().unwrap().push(buf.to_vec());
+            self.written
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(buf.to_vec());
             Ok(buf.len())
         }
         fn read(&self, buf: &mut [u8], _timeout: i32) -> Result<()> {
-            let mut resp = self.responses.lock().unwrap();
+            if let Some(token) = &self.cancel_on_read {
+                token.cancel();
+            }
+            let mut resp = self
+                .responses
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            if resp.is_empty() {
+                return Err(anyhow!("read timed out"));
+            }
             let next = resp.remove(0);
             buf[..next.len()].copy_from_slice(&next);
             Ok(())
         }
+        fn trace(&self, direction: Direction, buf: &[u8]) {
+            self.traced
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push((direction, buf.to_vec()));
+        }
+    }
+
+    /// Hands out a device per attempt; `None` means the device is not there (yet).
+    #[derive(Debug)]
+    pub(crate) struct StubOpener(Mutex<VecDeque<Option<Vec<Vec<u8>>>>>);
+
+    impl StubOpener {
+        pub(crate) fn new(attempts: impl IntoIterator<Item = Option<Vec<Vec<u8>>>>) -> Self {
+            StubOpener(Mutex::new(attempts.into_iter().collect()))
+        }
+        pub(crate) fn remaining(&self) -> usize {
+            self.0.lock().unwrap_or_else(PoisonError::into_inner).len()
+        }
+    }
+
+    impl DeviceOpener<StubIo> for StubOpener {
+        fn open(&self) -> Result<StubIo> {
+            self.0
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .pop_front()
+                .flatten()
+                .map(StubIo::new)
+                .ok_or(anyhow!("device not found"))
+        }
+    }
+
+    #[test]
+    fn late_device_is_opened_on_retry() {
+        let present = StubOpener::new([Some(vec![vec![0x01]]), Some(vec![])]);
+        let late = StubOpener::new([None, Some(vec![vec![0x02]])]);
+        let absent = StubOpener::new([None, None, None, None]);
+        let opened =
+            open_with_retry::<StubIo>(&[&present, &late, &absent], 3, Duration::from_millis(1));
+
+        assert_eq!(opened[0].as_ref().unwrap().pending_responses(), 1);
+        assert_eq!(opened[1].as_ref().unwrap().pending_responses(), 1);
+        assert!(opened[2].is_err());
+        // The already opened device was never reopened, the absent one tried once per attempt.
+        assert_eq!(present.remaining(), 1);
+        assert_eq!(absent.remaining(), 1);
+    }
+
+    #[test]
+    fn busy_open_names_the_likely_conflict() {
+        let busy = open_error(HidError::IoError {
+            error: io::Error::from(io::ErrorKind::ResourceBusy),
+        });
+        assert!(busy.to_string().contains("OpenRGB"), "{busy}");
+        let denied = open_error(HidError::HidApiError {
+            message: String::from("Failed to open /dev/hidraw3: Permission denied"),
+        });
+        assert!(denied.to_string().contains("fuser"), "{denied}");
+
+        let missing = open_error(HidError::HidApiError {
+            message: String::from("No HID devices with requested VID/PID found"),
+        });
+        assert!(!missing.to_string().contains("OpenRGB"), "{missing}");
     }
 
     #[test]
@@ -71,4 +274,10 @@ mod tests {
         assert_eq!(buf[0], 0xAA);
         assert_eq!(stub.written(), vec![vec![1, 2, 3]]);
     }
+
+    #[test]
+    fn hex_formats_bytes() {
+        assert_eq!(hex(&[0x00, 0xFE, 0x33]), "00 FE 33");
+        assert_eq!(hex(&[]), "");
+    }
 }
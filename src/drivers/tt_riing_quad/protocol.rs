@@ -14,6 +14,7 @@ pub enum Command {
     SetRgb {
         port: u8,
         mode: u8,
+        /// One triple per LED, already in the fan's LED byte order.
         colors: Vec<(u8, u8, u8)>,
     },
 }
@@ -32,8 +33,8 @@ impl Command {
             } => {
                 let mut buf = Vec::with_capacity(5 + 3 * colors.len());
                 buf.extend_from_slice(&[0x00, 0x32, 0x52, port, mode]);
-                for &(g, r, b) in colors {
-                    buf.extend_from_slice(&[g, r, b]);
+                for &(first, second, third) in colors {
+                    buf.extend_from_slice(&[first, second, third]);
                 }
                 buf
             }
@@ -1,6 +1,18 @@
 use crate::fan_curve::FanCurve;
-use crate::{config::ControllerCfg, fan_controller::FanController};
-use std::{collections::HashMap, sync::Arc};
+use crate::{
+    config::{Config, ControllerCfg, InitCfg, LedOrder},
+    events::EventBus,
+    fan_controller::FanController,
+    services::CancelToken,
+};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU32},
+    },
+    time::Duration,
+};
 
 use anyhow::{Ok, Result, anyhow};
 use async_trait::async_trait;
@@ -8,16 +20,21 @@ use hidapi::{HidApi, HidDevice};
 use log::info;
 use tokio::sync::{Mutex, MutexGuard};
 
-use super::controller::{Controller, Fan};
+use super::{
+    controller::{Controller, CurveCache, Fan, LED_COUNT, RGB_MODE_PER_LED},
+    device_io::{DeviceIO, DeviceOpener, HidOpener, open_with_retry},
+};
 
 pub const VID: u16 = 0x264A; // Thermaltake
 pub const DEFAULT_PERCENT: u8 = 50;
+/// Fan ports on a Riing Quad controller.
+pub const PORT_COUNT: usize = 5;
 
 #[derive(Debug)]
-pub struct TTRiingQuad(Arc<Mutex<Controller<HidDevice>>>);
+pub struct TTRiingQuad<Io: DeviceIO = HidDevice>(Arc<Mutex<Controller<Io>>>);
 
 #[async_trait]
-impl FanController for TTRiingQuad {
+impl<Io: DeviceIO + core::fmt::Debug> FanController for TTRiingQuad<Io> {
     async fn send_init(&self) -> Result<()> {
         #[cfg(debug_assertions)]
         {
@@ -31,20 +48,109 @@ impl FanController for TTRiingQuad {
         {
             info!("Updating speeds for TTRiingQuad controller");
         }
-        for idx in 0..5 {
-            self.process_fan(idx, temp).await?;
-        }
-        Ok(())
+        let (targets, failed) = self.0.lock().await.speed_targets(temp);
+        self.write_targets(targets, failed).await
+    }
+
+    async fn fan_count(&self) -> usize {
+        self.read().await.fan_count()
     }
 
     async fn update_channel(&self, channel: u8, temp: f32) -> Result<()> {
         self.process_fan((channel - 1) as usize, temp).await
     }
 
+    async fn update_channels(&self, channels: &[(u8, f32)]) -> Result<()> {
+        let (targets, failed) = self.0.lock().await.channel_targets(channels);
+        self.write_targets(targets, failed).await
+    }
+
+    async fn channel_speed(&self, channel: u8, temp: f32) -> Result<u8> {
+        self.0.lock().await.cached_speed(channel, temp)
+    }
+
+    async fn clear_curve_cache(&self) {
+        self.0.lock().await.curve_cache.clear();
+    }
+
+    async fn set_max_rpm(&self, channel: u8, rpm: u16) -> Result<()> {
+        self.read()
+            .await
+            .fans
+            .get_mut((channel as usize).wrapping_sub(1))
+            .map(|fan| fan.max_rpm = Some(rpm))
+            .ok_or(anyhow!("Fan {channel} not found"))
+    }
+
+    async fn set_channel_speeds(&self, speeds: &[(u8, u8)]) -> Result<()> {
+        let targets = {
+            let guard = self.read().await;
+            for &(port, _) in speeds {
+                guard.fan(port)?;
+            }
+            guard.floor_targets(speeds.to_vec())
+        };
+        self.write_targets(targets, Vec::new()).await
+    }
+
+    async fn set_speed_direct(&self, channel: u8, speed: u8) -> Result<()> {
+        if speed > 100 {
+            return Err(anyhow!("Speed {speed} is out of range 0-100"));
+        }
+        self.read().await.fan(channel)?;
+        self.write_targets(vec![(channel, speed)], Vec::new()).await
+    }
+
     async fn update_channel_color(&self, channel: u8, red: u8, green: u8, blue: u8) -> Result<()> {
-        self.process_fan_color((channel - 1) as usize, green, red, blue)
+        self.process_fan_color((channel - 1) as usize, red, green, blue)
             .await
     }
+
+    async fn update_channel_color_zones(&self, channel: u8, zones: &[[u8; 3]]) -> Result<()> {
+        let idx = (channel as usize)
+            .checked_sub(1)
+            .ok_or(anyhow!("Fan {channel} not found"))?;
+        self.process_fan_zones(idx, zones.to_vec()).await
+    }
+
+    async fn channel_rpm(&self, channel: u8) -> Result<u16> {
+        let controller = self.read().await;
+        controller.fan(channel)?;
+        controller.get_data(channel).map(|(_, rpm)| rpm)
+    }
+
+    async fn fan_stats(&self) -> Vec<(u8, u16)> {
+        self.read()
+            .await
+            .fans
+            .iter()
+            .map(|fan| (fan.current_speed, fan.current_rpm))
+            .collect()
+    }
+
+    /// Reads every port under a single lock and refreshes the cached stats with the result.
+    async fn read_all_fans(&self) -> Result<Vec<(u8, u16)>> {
+        let mut controller = self.read().await;
+        let stats = (1..=controller.fan_count() as u8)
+            .map(|port| controller.get_data(port))
+            .collect::<Result<Vec<_>>>()?;
+        controller
+            .fans
+            .iter_mut()
+            .zip(&stats)
+            .for_each(|(fan, &(speed, rpm))| fan.update_stats(speed, rpm));
+        Ok(stats)
+    }
+
+    async fn channel_color(&self, channel: u8) -> Result<(u8, u8, u8)> {
+        let controller = self.read().await;
+        (channel as usize)
+            .checked_sub(1)
+            .and_then(|idx| controller.fans.get(idx))
+            .map(|fan| fan.color)
+            .ok_or(anyhow!("Fan not found"))
+    }
+
     async fn switch_curve(&self, channel: u8, curve: &str) -> Result<()> {
         #[cfg(debug_assertions)]
         {
@@ -81,6 +187,30 @@ impl FanController for TTRiingQuad {
         self.read().await.get_firmware_version()
     }
 
+    /// Reads every port in one blocking pass; a port that fails is reported once the rest
+    /// are read.
+    async fn connected_ports(&self) -> Result<Vec<u8>> {
+        let ctrl = self.0.clone();
+        let (ports, failed) = tokio::task::spawn_blocking(move || {
+            let mut guard = ctrl.blocking_lock();
+            let (mut ports, mut failed) = (Vec::new(), Vec::new());
+            for port in 1..=PORT_COUNT as u8 {
+                let res = guard.port_spinning(port);
+                match guard.recovering(res) {
+                    Result::Ok(true) => ports.push(port),
+                    Result::Ok(false) => {}
+                    Err(e) => failed.push(format!("port {port}: {e}")),
+                }
+            }
+            (ports, failed)
+        })
+        .await?;
+        if failed.is_empty() {
+            return Ok(ports);
+        }
+        Err(anyhow!("{}", failed.join("; ")))
+    }
+
     async fn update_curve_data(
         &self,
         channel: u8,
@@ -115,70 +245,148 @@ impl TTRiingQuad {
                     Box::new(TTRiingQuad(Arc::new(Mutex::new(Controller {
                         name: format!("TTRiingQuad: {}", idx + 1),
                         dev: device,
-                        fans: (0..5)
+                        fans: (0..PORT_COUNT)
                             .map(|_| Fan {
                                 current_speed: speed,
                                 current_rpm: 0,
                                 active_curve: String::from("Constant"),
                                 curve: build_default_curves(),
+                                fallback: None,
+                                fallback_warned: AtomicBool::new(false),
+                                color: (0, 0, 0),
+                                kickstart: None,
+                                kickstarts: 0,
+                                max_rpm: None,
                             })
                             .collect(),
+                        brightness: 1.0,
+                        led_order: LedOrder::default(),
+                        read_rpm: true,
+                        airflow_floor: None,
+                        init: InitCfg::default(),
+                        trace_hid: false,
+                        reopen: Some(Box::new(HidOpener {
+                            vid: d.vendor_id(),
+                            pid: d.product_id(),
+                            serial: None,
+                        })),
+                        failures: AtomicU32::new(0),
+                        curve_cache: CurveCache::default(),
+                        events: EventBus::default(),
+                        cancel: CancelToken::default(),
                     })))) as Box<dyn FanController>
                 })
             })
             .collect())
     }
 
-    #[allow(irrefutable_let_patterns)]
+    /// Opens every configured controller, waiting for late USB enumeration as configured by
+    /// `usb_open_attempts` and `usb_open_delay_ms`.
     pub fn find_controllers(
-        api: &HidApi,
-        ctrl_cfg: &[ControllerCfg],
+        cfg: &Config,
         curve_map: &HashMap<String, FanCurve>,
+        events: &EventBus,
+        cancel: &CancelToken,
     ) -> Result<Vec<Box<dyn FanController>>> {
-        Ok(ctrl_cfg
+        let fallback = cfg.fallback_curve.as_ref().and_then(|id| curve_map.get(id));
+        let openers: Vec<HidOpener> = cfg
+            .controllers
             .iter()
-            .filter_map(|cfg| {
-                if let ControllerCfg::RiingQuad { id, usb, fans } = cfg {
-                    Some(Box::new(TTRiingQuad(Arc::new(Mutex::new(Controller {
-                        name: format!("TTRiingQuad{}", id),
-                        dev: api.open(usb.vid, usb.pid).unwrap(),
-                        fans: fans
-                            .iter()
-                            .map(|fan| Fan {
-                                current_speed: 0,
-                                current_rpm: 0,
-                                active_curve: fan.active_curve.clone(),
-                                curve: fan
-                                    .curve
-                                    .iter()
-                                    .filter_map(|curve_str| {
-                                        curve_map
-                                            .get(curve_str)
-                                            .map(|curve| (curve_str.clone(), curve.clone()))
-                                    })
-                                    .collect(),
-                            })
-                            .collect(),
-                    })))) as Box<dyn FanController>)
-                } else {
-                    None
+            .map(|ctrl| {
+                let ControllerCfg::RiingQuad { usb, .. } = ctrl;
+                HidOpener {
+                    vid: usb.vid,
+                    pid: usb.pid,
+                    serial: usb.serial.clone(),
                 }
             })
-            .collect())
+            .collect();
+        let refs: Vec<&dyn DeviceOpener<HidDevice>> = openers.iter().map(|o| o as _).collect();
+        let devices = open_with_retry(
+            &refs,
+            cfg.usb_open_attempts.max(1),
+            Duration::from_millis(cfg.usb_open_delay_ms),
+        );
+        cfg.controllers
+            .iter()
+            .zip(openers.iter())
+            .zip(devices)
+            .map(|((ctrl, opener), dev)| {
+                let ControllerCfg::RiingQuad {
+                    id,
+                    usb,
+                    fans,
+                    brightness,
+                    read_rpm,
+                    airflow_floor,
+                    led_order,
+                    init,
+                    ..
+                } = ctrl;
+                let dev = dev.map_err(|e| {
+                    anyhow!(
+                        "{id}: cannot open USB device {:04X}:{:04X} after {} attempt(s): {e}",
+                        usb.vid,
+                        usb.pid,
+                        cfg.usb_open_attempts.max(1)
+                    )
+                })?;
+                Ok(Box::new(TTRiingQuad(Arc::new(Mutex::new(Controller {
+                    name: ctrl.name(),
+                    dev,
+                    fans: fans
+                        .iter()
+                        .map(|fan| Fan {
+                            current_speed: 0,
+                            current_rpm: 0,
+                            active_curve: fan.active_curve.clone(),
+                            curve: fan
+                                .curve
+                                .iter()
+                                .filter_map(|curve_ref| {
+                                    let id = curve_ref.id();
+                                    curve_map.get(&id).map(|curve| (id, curve.clone()))
+                                })
+                                .collect(),
+                            fallback: fallback.cloned(),
+                            fallback_warned: AtomicBool::new(false),
+                            color: (0, 0, 0),
+                            kickstart: fan.kickstart,
+                            kickstarts: 0,
+                            max_rpm: None,
+                        })
+                        .collect(),
+                    brightness: *brightness,
+                    led_order: *led_order,
+                    read_rpm: *read_rpm,
+                    airflow_floor: *airflow_floor,
+                    init: init.clone(),
+                    trace_hid: cfg.trace_hid,
+                    reopen: Some(Box::new(opener.clone())),
+                    failures: AtomicU32::new(0),
+                    curve_cache: CurveCache::new(cfg.curve_resolution),
+                    events: events.clone(),
+                    cancel: cancel.clone(),
+                })))) as Box<dyn FanController>)
+            })
+            .collect()
     }
+}
 
+impl<Io: DeviceIO> TTRiingQuad<Io> {
+    // HID work runs on the blocking pool with the lock taken inside the closure. If the awaiting
+    // task is cancelled the blocking task still runs to completion, so the transaction is never
+    // cut in half and the guard is always dropped.
     async fn process_fan(&self, idx: usize, temp: f32) -> Result<()> {
-        let speed = {
-            let guard = self.0.lock().await;
-            guard.fans[idx].compute_speed(temp)?
-        };
+        let speed = self.0.lock().await.cached_speed(idx as u8 + 1, temp)?;
         #[cfg(debug_assertions)]
         {
             info!("Computed speed for fan {}: {}", idx + 1, speed);
         }
+        self.kickstart(&[(idx as u8 + 1, speed)]).await?;
         let ctrl = self.0.clone();
-        let (speed, rpm) = tokio::task::spawn_blocking(move || {
-            let guard = ctrl.blocking_lock();
+        tokio::task::spawn_blocking(move || {
+            let mut guard = ctrl.blocking_lock();
             #[cfg(debug_assertions)]
             {
                 info!(
@@ -188,49 +396,127 @@ impl TTRiingQuad {
                     temp
                 );
             }
-            Self::proccess_fan_inner(guard, idx, speed)
+            let res = Self::proccess_fan_inner(&mut guard, idx, speed);
+            guard.recovering(res)
         })
-        .await??;
-
-        self.0.lock().await.fans[idx].update_stats(speed, rpm);
-        Ok(())
+        .await?
     }
 
-    async fn process_fan_color(&self, idx: usize, green: u8, red: u8, blue: u8) -> Result<()> {
+    async fn process_fan_color(&self, idx: usize, red: u8, green: u8, blue: u8) -> Result<()> {
         let ctrl = self.0.clone();
         tokio::task::spawn_blocking(move || {
-            let guard = ctrl.blocking_lock();
+            let mut guard = ctrl.blocking_lock();
             #[cfg(debug_assertions)]
             {
                 info!("Setting color fan {} on controller {}", idx + 1, guard.name,);
             }
-            Self::proccess_fan_inner_color(guard, idx, green, red, blue)
+            let (r, g, b) = guard.scale_color(red, green, blue);
+            let res = Self::proccess_fan_inner_color(&guard, idx, [r, g, b]);
+            guard.recovering(res)?;
+            if let Some(fan) = guard.fans.get_mut(idx) {
+                fan.color = (red, green, blue);
+            }
+            Ok(())
+        })
+        .await?
+    }
+    // Writes `(port, speed)` targets in one blocking pass, see `process_fan` for why. A
+    // controller without fans, driving only LEDs, has none and never reaches the device.
+    // A fan that fails is reported along with the earlier `failed` ones once the others are
+    // written.
+    async fn write_targets(&self, targets: Vec<(u8, u8)>, mut failed: Vec<String>) -> Result<()> {
+        if !targets.is_empty() {
+            if let Err(e) = self.kickstart(&targets).await {
+                failed.push(format!("kickstart: {e}"));
+            }
+            let ctrl = self.0.clone();
+            let written = tokio::task::spawn_blocking(move || {
+                let mut guard = ctrl.blocking_lock();
+                let stats = guard.set_speeds_batch(&targets);
+                let mut failed = Vec::new();
+                for (&(port, _), stats) in targets.iter().zip(stats) {
+                    match stats {
+                        Result::Ok((speed, rpm)) => {
+                            if let Some(fan) = guard.fans.get_mut((port - 1) as usize) {
+                                fan.update_stats(speed, rpm);
+                            }
+                        }
+                        Err(e) => failed.push(format!("fan {port}: {e}")),
+                    }
+                }
+                if !failed.is_empty() {
+                    guard.reconnect();
+                }
+                failed
+            })
+            .await?;
+            failed.extend(written);
+        }
+        if failed.is_empty() {
+            return Ok(());
+        }
+        Err(anyhow!("{}", failed.join("; ")))
+    }
+
+    // Runs the standing fans among `(port, speed)` targets at 100% and waits out the longest
+    // of their pulses with the lock released, so other controllers' fans and this one's
+    // colors aren't held up for it. The targets are written by the caller afterwards.
+    async fn kickstart(&self, targets: &[(u8, u8)]) -> Result<()> {
+        let (ports, pulse): (Vec<u8>, Vec<Duration>) = {
+            let guard = self.0.lock().await;
+            targets
+                .iter()
+                .filter_map(|&(port, speed)| Some((port, guard.kickstart(port, speed)?)))
+                .unzip()
+        };
+        let Some(&pulse) = pulse.iter().max() else {
+            return Ok(());
+        };
+        let ctrl = self.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut guard = ctrl.blocking_lock();
+            let res = guard.start_kickstart(&ports);
+            guard.recovering(res)
+        })
+        .await??;
+        tokio::time::sleep(pulse).await;
+        Ok(())
+    }
+
+    async fn process_fan_zones(&self, idx: usize, zones: Vec<[u8; 3]>) -> Result<()> {
+        let ctrl = self.0.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut guard = ctrl.blocking_lock();
+            let port = (idx + 1) as u8;
+            guard.fan(port)?;
+            let res = guard.set_rgb_zones(port, &zones);
+            guard.recovering(res)?;
+            if let Some(&[r, g, b]) = zones.first() {
+                guard.fans[idx].color = (r, g, b);
+            }
+            Ok(())
         })
         .await?
     }
-    async fn read(&self) -> MutexGuard<'_, Controller<HidDevice>> {
+
+    async fn read(&self) -> MutexGuard<'_, Controller<Io>> {
         self.0.lock().await
     }
 
     #[inline(never)]
-    fn proccess_fan_inner(
-        guard: MutexGuard<'_, Controller<HidDevice>>,
-        idx: usize,
-        speed: u8,
-    ) -> Result<(u8, u16)> {
-        guard.set_speed((idx + 1) as u8, speed)?;
-        guard.get_data((idx + 1) as u8)
+    fn proccess_fan_inner(guard: &mut Controller<Io>, idx: usize, speed: u8) -> Result<()> {
+        let (speed, rpm) = guard.apply_speed((idx + 1) as u8, speed)?;
+        guard.fans[idx].update_stats(speed, rpm);
+        Ok(())
     }
 
     #[inline(never)]
-    fn proccess_fan_inner_color(
-        guard: MutexGuard<'_, Controller<HidDevice>>,
-        idx: usize,
-        green: u8,
-        red: u8,
-        blue: u8,
-    ) -> Result<()> {
-        guard.set_rgb((idx + 1) as u8, 0x24, vec![(green, red, blue); 52])
+    fn proccess_fan_inner_color(guard: &Controller<Io>, idx: usize, rgb: [u8; 3]) -> Result<()> {
+        guard.set_rgb(
+            (idx + 1) as u8,
+            RGB_MODE_PER_LED,
+            vec![guard.led_order.arrange(rgb); LED_COUNT],
+        )
     }
 }
 
@@ -258,3 +544,297 @@ fn build_default_curves() -> HashMap<String, FanCurve> {
         ),
     ])
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::sync::Mutex;
+
+    use super::TTRiingQuad;
+    use crate::config::KickstartCfg;
+    use crate::drivers::tt_riing_quad::controller::tests::{
+        constant_fan, controller, data, fan, status_ok,
+    };
+    use crate::drivers::tt_riing_quad::controller::{Fan, KICKSTART_ATTEMPTS};
+    use crate::drivers::tt_riing_quad::device_io::tests::StubOpener;
+    use crate::events::{Event, EventBus};
+    use crate::fan_controller::FanController;
+    use crate::services::CancelToken;
+
+    #[tokio::test]
+    async fn replugged_controller_is_reopened() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut ctrl = controller(vec![]);
+        ctrl.fans = vec![constant_fan(40)];
+        ctrl.events = bus;
+        ctrl.reopen = Some(Box::new(StubOpener::new([
+            None,
+            Some(vec![status_ok(), status_ok(), data(40, 900)]),
+        ])));
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        // Unplugged: three failures trigger a reopen attempt that finds nothing, the
+        // fourth reopens the replugged device.
+        for _ in 0..4 {
+            assert!(quad.update_channels(&[(1, 50.0)]).await.is_err());
+        }
+        assert_eq!(
+            events.try_recv().unwrap(),
+            Event::ControllerReconnected {
+                name: String::from("test")
+            }
+        );
+
+        quad.update_channels(&[(1, 50.0)]).await.unwrap();
+        let guard = quad.0.lock().await;
+        assert_eq!(guard.fans[0].current_rpm, 900);
+        assert_eq!(guard.dev.pending_responses(), 0);
+    }
+
+    #[tokio::test]
+    async fn failing_fans_do_not_stop_the_rest_of_the_batch() {
+        let rejected = vec![0x00, 0x00, 0x01];
+        let mut ctrl = controller(vec![rejected, status_ok(), data(55, 1100)]);
+        ctrl.fans = vec![constant_fan(40), constant_fan(55)];
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        let err = quad
+            .update_channels(&[(1, 50.0), (5, 50.0), (2, 50.0)])
+            .await
+            .unwrap_err()
+            .to_string();
+        assert_eq!(
+            err,
+            "fan 5: Fan 5 not found; fan 1: Invalid set speed responce"
+        );
+        let guard = quad.0.lock().await;
+        assert_eq!(guard.fans[1].current_rpm, 1100);
+        assert_eq!(guard.dev.pending_responses(), 0);
+    }
+
+    #[tokio::test]
+    async fn all_fans_read_in_one_pass() {
+        let mut ctrl = controller(vec![data(40, 900), data(55, 1100)]);
+        ctrl.fans = vec![constant_fan(40), constant_fan(55)];
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        assert_eq!(quad.read_all_fans().await.unwrap(), [(40, 900), (55, 1100)]);
+        assert_eq!(quad.fan_stats().await, [(40, 900), (55, 1100)]);
+        let guard = quad.0.lock().await;
+        assert_eq!(
+            guard.dev.written(),
+            [vec![0x00, 0x33, 0x51, 1], vec![0x00, 0x33, 0x51, 2]]
+        );
+    }
+
+    #[tokio::test]
+    async fn connected_ports_names_the_ports_that_failed() {
+        let ctrl = controller(vec![data(40, 1200), data(40, 0), data(40, 900)]);
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        let err = quad.connected_ports().await.unwrap_err().to_string();
+        assert_eq!(err, "port 4: read timed out; port 5: read timed out");
+        assert_eq!(quad.0.lock().await.dev.written().len(), 5);
+
+        let ctrl = controller(vec![
+            data(40, 1200),
+            data(40, 0),
+            data(40, 900),
+            data(0, 0),
+            data(0, 0),
+        ]);
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+        assert_eq!(quad.connected_ports().await.unwrap(), [1, 3]);
+    }
+
+    #[tokio::test]
+    async fn fanless_controller_writes_colors_only() {
+        let quad = TTRiingQuad(Arc::new(Mutex::new(controller(vec![status_ok()]))));
+
+        quad.update_speeds(50.0).await.unwrap();
+        quad.set_channel_speeds(&[]).await.unwrap();
+        assert!(quad.update_channels(&[(1, 50.0)]).await.is_err());
+        assert!(quad.0.lock().await.dev.written().is_empty());
+
+        quad.update_channel_color(2, 255, 0, 0).await.unwrap();
+        let written = quad.0.lock().await.dev.written();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0][..5], [0x00, 0x32, 0x52, 2, 0x24]);
+    }
+
+    #[tokio::test]
+    async fn color_zones_are_not_written_for_a_missing_fan() {
+        let mut ctrl = controller(vec![status_ok()]);
+        ctrl.fans = vec![constant_fan(40), constant_fan(40)];
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+        let zones = [[255, 0, 0], [0, 0, 255]];
+
+        for channel in [0, 3] {
+            let err = quad.update_channel_color_zones(channel, &zones).await;
+            assert_eq!(
+                err.unwrap_err().to_string(),
+                format!("Fan {channel} not found")
+            );
+        }
+        assert!(quad.0.lock().await.dev.written().is_empty());
+
+        quad.update_channel_color_zones(2, &zones).await.unwrap();
+        let guard = quad.0.lock().await;
+        assert_eq!(guard.dev.written()[0][..5], [0x00, 0x32, 0x52, 2, 0x24]);
+        assert_eq!(guard.fans[1].color, (255, 0, 0));
+    }
+
+    #[tokio::test]
+    async fn color_of_a_missing_fan_is_an_error() {
+        let mut ctrl = controller(vec![]);
+        ctrl.fans = vec![constant_fan(40)];
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        assert_eq!(quad.channel_color(1).await.unwrap(), (0, 0, 0));
+        for channel in [0, 2] {
+            assert!(quad.channel_color(channel).await.is_err());
+        }
+    }
+
+    #[tokio::test]
+    async fn direct_speed_skips_curve_and_floor() {
+        let mut ctrl = controller(vec![status_ok(), data(15, 400)]);
+        ctrl.fans = vec![constant_fan(40), constant_fan(40)];
+        ctrl.airflow_floor = Some(100);
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        quad.set_speed_direct(2, 15).await.unwrap();
+        assert!(quad.set_speed_direct(2, 101).await.is_err());
+        assert!(quad.set_speed_direct(3, 50).await.is_err());
+
+        let guard = quad.0.lock().await;
+        assert_eq!(guard.dev.written()[0], vec![0x00, 0x32, 0x51, 2, 0x01, 15]);
+        assert_eq!(guard.fans[1].current_speed, 15);
+        assert_eq!(guard.fans[1].current_rpm, 400);
+        assert_eq!(guard.dev.pending_responses(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn standing_fan_is_kickstarted_once() {
+        let responses = vec![
+            status_ok(),
+            status_ok(),
+            data(15, 450),
+            status_ok(),
+            data(15, 450),
+        ];
+        let mut ctrl = controller(responses);
+        ctrl.fans = vec![Fan {
+            kickstart: Some(KickstartCfg { below: 30, ms: 20 }),
+            ..constant_fan(15)
+        }];
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+        let set = |speed| vec![0x00, 0x32, 0x51, 1, 0x01, speed];
+        let get = vec![0x00, 0x33, 0x51, 1];
+
+        let start = tokio::time::Instant::now();
+        quad.process_fan(0, 50.0).await.unwrap();
+        assert_eq!(start.elapsed(), Duration::from_millis(20));
+        // Spinning at a low speed, the fan is left alone.
+        quad.process_fan(0, 50.0).await.unwrap();
+
+        let guard = quad.0.lock().await;
+        assert_eq!(
+            guard.dev.written(),
+            [set(100), set(15), get.clone(), set(15), get]
+        );
+        assert_eq!(guard.fans[0].current_rpm, 450);
+        assert_eq!(guard.dev.pending_responses(), 0);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_fan_gets_a_limited_number_of_kickstarts() {
+        let pulsed = [status_ok(), status_ok(), data(15, 0)];
+        let mut responses: Vec<_> = (0..KICKSTART_ATTEMPTS)
+            .flat_map(|_| pulsed.clone())
+            .collect();
+        responses.extend([status_ok(), data(15, 0), status_ok(), data(15, 0)]);
+        let mut ctrl = controller(responses);
+        let kickstart = Some(KickstartCfg { below: 30, ms: 20 });
+        ctrl.fans = vec![
+            Fan {
+                kickstart,
+                ..constant_fan(15)
+            },
+            // Already commanded to turn, so a 0 RPM reading alone doesn't pulse it.
+            Fan {
+                kickstart,
+                ..fan(20, 0)
+            },
+        ];
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        for _ in 0..=KICKSTART_ATTEMPTS {
+            quad.process_fan(0, 50.0).await.unwrap();
+        }
+        quad.set_speed_direct(2, 15).await.unwrap();
+
+        let guard = quad.0.lock().await;
+        let pulses = guard
+            .dev
+            .written()
+            .iter()
+            .filter(|pkt| pkt[1..3] == [0x32, 0x51] && pkt[5] == 100)
+            .count();
+        assert_eq!(pulses, KICKSTART_ATTEMPTS as usize);
+        assert_eq!(guard.fans[0].kickstarts, KICKSTART_ATTEMPTS);
+    }
+
+    #[tokio::test]
+    async fn cancel_stops_slow_batch_after_the_read_in_flight() {
+        let responses = (0..4).flat_map(|_| [status_ok(), data(50, 900)]).collect();
+        let mut ctrl = controller(responses);
+        let cancel = CancelToken::default();
+        ctrl.dev = ctrl.dev.with_cancel_on_read(cancel.clone());
+        ctrl.fans = (0..4).map(|_| constant_fan(40)).collect();
+        ctrl.cancel = cancel;
+        let quad = TTRiingQuad(Arc::new(Mutex::new(ctrl)));
+
+        // Shutdown arrives during the first read: it finishes, the other seven never start.
+        assert!(
+            quad.set_channel_speeds(&[(1, 50), (2, 50), (3, 50), (4, 50)])
+                .await
+                .is_err()
+        );
+        let guard = quad.0.lock().await;
+        assert_eq!(guard.dev.written().len(), 1);
+        assert_eq!(guard.dev.pending_responses(), 7);
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn cancelled_fan_updates_release_the_lock() {
+        let responses = (0..64).flat_map(|_| [status_ok(), data(40, 900)]).collect();
+        let mut ctrl = controller(responses);
+        ctrl.fans = vec![constant_fan(40), constant_fan(40)];
+        let quad = Arc::new(TTRiingQuad(Arc::new(Mutex::new(ctrl))));
+
+        let handles: Vec<_> = (0..32)
+            .map(|i| {
+                let quad = quad.clone();
+                tokio::spawn(async move { quad.process_fan(i % 2, 50.0).await })
+            })
+            .collect();
+        for handle in handles.iter().step_by(2) {
+            handle.abort();
+        }
+        for handle in handles {
+            if let Ok(res) = handle.await {
+                res.unwrap();
+            }
+        }
+
+        let guard = tokio::time::timeout(Duration::from_secs(1), quad.0.lock())
+            .await
+            .expect("controller mutex still held after cancellation");
+        assert_eq!(guard.fans[1].current_rpm, 900);
+        drop(guard);
+        quad.process_fan(0, 50.0).await.unwrap();
+    }
+}
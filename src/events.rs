@@ -0,0 +1,177 @@
+use std::sync::{
+    Arc,
+    atomic::{AtomicU64, Ordering},
+};
+
+use log::warn;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use crate::trend::Trend;
+
+pub const EVENT_CAPACITY: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Event {
+    ServiceStarted {
+        name: String,
+    },
+    ServiceStopped {
+        name: String,
+    },
+    ControllerReconnected {
+        name: String,
+    },
+    ThresholdCrossed {
+        sensor: String,
+        value: f32,
+        alert: String,
+    },
+    ThermalTrend {
+        sensor: String,
+        trend: Trend,
+    },
+    /// `sensor` reached its critical limit (`active`), or dropped back below it.
+    CriticalTemperature {
+        sensor: String,
+        temperature: f32,
+        active: bool,
+    },
+    /// Static colors were written; `failed` counts fans whose write errored.
+    ColorChanged {
+        applied: usize,
+        failed: usize,
+    },
+    /// The config file changed on disk: it was reloaded, rejected or went missing.
+    ConfigChanged,
+}
+
+/// Fan-out channel for daemon lifecycle and state events.
+#[derive(Debug, Clone)]
+pub struct EventBus {
+    tx: broadcast::Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new(EVENT_CAPACITY)
+    }
+}
+
+impl EventBus {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            tx: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Publishes `event`; having no subscribers is not an error.
+    pub fn publish(&self, event: Event) {
+        let _ = self.tx.send(event);
+    }
+
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn subscribe(&self) -> broadcast::Receiver<Event> {
+        self.tx.subscribe()
+    }
+
+    /// A subscription for long-lived consumers that must not stop on lag.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn subscriber(&self) -> Subscriber {
+        self.subscriber_counting(Arc::default())
+    }
+
+    /// Like [`Self::subscriber`], adding missed events to `lagged`, so a consumer that is
+    /// restarted with a fresh subscription keeps one count.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn subscriber_counting(&self, lagged: Arc<AtomicU64>) -> Subscriber {
+        Subscriber {
+            rx: self.subscribe(),
+            bus: self.clone(),
+            lagged,
+        }
+    }
+}
+
+/// Receives from an [`EventBus`], counting events missed through lag instead of failing and
+/// subscribing again if its receiver was closed.
+#[derive(Debug)]
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+pub struct Subscriber {
+    bus: EventBus,
+    rx: broadcast::Receiver<Event>,
+    lagged: Arc<AtomicU64>,
+}
+
+#[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+impl Subscriber {
+    /// Shared count of events this subscriber missed because it fell behind.
+    pub fn lagged(&self) -> Arc<AtomicU64> {
+        self.lagged.clone()
+    }
+
+    pub async fn recv(&mut self) -> Event {
+        loop {
+            match self.rx.recv().await {
+                Ok(event) => return event,
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("Event subscriber lagged, {missed} event(s) dropped");
+                    self.lagged.fetch_add(missed, Ordering::Relaxed);
+                }
+                Err(RecvError::Closed) => {
+                    warn!("Event subscription closed, subscribing again");
+                    self.rx = self.bus.subscribe();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::Ordering;
+
+    use super::{Event, EventBus};
+
+    fn started(name: &str) -> Event {
+        Event::ServiceStarted {
+            name: name.to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn subscriber_survives_lag() {
+        let bus = EventBus::new(2);
+        let mut subscriber = bus.subscriber();
+        for name in ["a", "b", "c", "d"] {
+            bus.publish(started(name));
+        }
+
+        assert_eq!(subscriber.recv().await, started("c"));
+        assert_eq!(subscriber.recv().await, started("d"));
+        assert_eq!(subscriber.lagged().load(Ordering::Relaxed), 2);
+
+        // A replacement subscription only sees new events and keeps counting where the
+        // first one left off.
+        let mut replacement = bus.subscriber_counting(subscriber.lagged());
+        for name in ["e", "f", "g"] {
+            bus.publish(started(name));
+        }
+        assert_eq!(replacement.recv().await, started("f"));
+        assert_eq!(subscriber.lagged().load(Ordering::Relaxed), 3);
+    }
+
+    #[tokio::test]
+    async fn subscriber_resubscribes_when_closed() {
+        let bus = EventBus::default();
+        let mut subscriber = bus.subscriber();
+        // Swap in a receiver whose channel is gone, as if the sender side had been dropped.
+        subscriber.rx = EventBus::default().subscribe();
+
+        let publisher = bus.clone();
+        tokio::spawn(async move {
+            tokio::task::yield_now().await;
+            publisher.publish(started("after"));
+        });
+        assert_eq!(subscriber.recv().await, started("after"));
+    }
+}
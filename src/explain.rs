@@ -0,0 +1,206 @@
+use std::collections::HashMap;
+
+use anyhow::{Result, anyhow};
+use serde::Serialize;
+
+use crate::{
+    config::Config,
+    controller::Controllers,
+    fan_curve::FanCurve,
+    mappings::{FanNames, FanRef},
+    overrides::Overrides,
+};
+
+/// Why a fan runs at its speed: what drives it and what its curve asks for.
+#[derive(Debug, Serialize)]
+pub struct FanExplanation {
+    pub controller: u8,
+    pub channel: u8,
+    pub name: String,
+    pub active_curve: String,
+    /// Sensors mapped to the fan, in name order; on each tick the one whose curve asks for
+    /// the highest speed sets it.
+    pub sensors: Vec<SensorInput>,
+    /// Last speed written to the fan, after floors, groups and quiet hours.
+    pub speed: u8,
+    pub rpm: u16,
+    pub auto_control: bool,
+    /// Speed set by hand, which holds while automatic control is off.
+    pub manual_speed: Option<u8>,
+}
+
+/// A sensor driving a fan, with its latest reading and the speed its curve gives for it:
+/// the mapping's `curve` when set, otherwise the fan's active curve. Reading and speed are
+/// `None` until the sensor has been read.
+#[derive(Debug, Serialize)]
+pub struct SensorInput {
+    pub sensor: String,
+    pub curve: Option<String>,
+    pub temperature: Option<f32>,
+    pub curve_speed: Option<u8>,
+}
+
+/// Assembles the [`FanExplanation`] of `fan` from the mappings in `cfg`, the latest
+/// `temperatures` and the fan's state on the controller.
+pub async fn explain_fan(
+    cfg: &Config,
+    controllers: &Controllers,
+    temperatures: &HashMap<String, f32>,
+    names: &FanNames,
+    auto_control: bool,
+    overrides: &Overrides,
+    fan: FanRef,
+) -> Result<FanExplanation> {
+    let (controller, channel) = (fan.controller_id as u8, fan.channel as u8);
+    let (_, _, speed, rpm) = controllers
+        .fan_stats()
+        .await
+        .into_iter()
+        .find(|&(c, ch, _, _)| (c, ch) == (controller, channel))
+        .ok_or_else(|| anyhow!("fan {fan} not found"))?;
+    let active_curve = controllers.get_active_curve(controller, channel).await?;
+
+    let mut mapped: Vec<(&str, Option<&String>)> = cfg
+        .mappings
+        .iter()
+        .filter(|m| {
+            m.targets
+                .iter()
+                .any(|t| (t.controller, t.fan_idx) == (controller, channel))
+        })
+        .map(|m| (m.sensor.as_str(), m.curve.as_ref()))
+        .collect();
+    // A sensor mapped twice keeps the mapping that sets a curve, as fan control does.
+    mapped.sort_unstable_by(|a, b| a.0.cmp(b.0).then(b.1.cmp(&a.1)));
+    mapped.dedup_by_key(|(sensor, _)| *sensor);
+    let mut sensors = Vec::with_capacity(mapped.len());
+    for (sensor, curve) in mapped {
+        let temperature = temperatures.get(sensor).copied();
+        let curve_speed = match temperature {
+            Some(t) => controllers
+                .channel_speed(controller, channel, t)
+                .await
+                .ok()
+                .map(|speed| {
+                    cfg.curves
+                        .iter()
+                        .find(|c| Some(&c.get_id()) == curve)
+                        .and_then(|c| FanCurve::from(c).speed_at(t, speed > 0).ok())
+                        .unwrap_or(speed)
+                }),
+            None => None,
+        };
+        sensors.push(SensorInput {
+            sensor: sensor.to_string(),
+            curve: curve.cloned(),
+            temperature,
+            curve_speed,
+        });
+    }
+
+    Ok(FanExplanation {
+        controller,
+        channel,
+        name: names.name(fan),
+        active_curve,
+        sensors,
+        speed,
+        rpm,
+        auto_control,
+        manual_speed: overrides.speed(fan).filter(|_| !auto_control),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use serde_json::json;
+
+    use super::explain_fan;
+    use crate::{
+        config::{Config, ControllerCfg, CurveCfg, MappingCfg},
+        controller::{Controllers, tests::MockController},
+        mappings::{FanNames, FanRef},
+        overrides::Overrides,
+    };
+
+    #[tokio::test]
+    async fn explanation_follows_mapping_curve_and_state() {
+        let cfg = Config::builder()
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "CPU left", &["Silent"])
+                    .fan(2, "Rear", &["Silent"]),
+            )
+            .curve(CurveCfg::constant("Loud", 90))
+            .mapping(
+                MappingCfg::builder("gpu")
+                    .target(1, 1)
+                    .curve("Loud")
+                    .build(),
+            )
+            .mapping(MappingCfg::builder("cpu").target(1, 1).target(1, 2).build())
+            .build();
+        let mock = MockController::with_fans(2);
+        *mock.speeds.lock().unwrap() = vec![45, 30];
+        mock.curves
+            .lock()
+            .unwrap()
+            .insert(1, String::from("Silent"));
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let mut temps = HashMap::from([(String::from("cpu"), 45.5)]);
+        let names = FanNames::from_cfg(&cfg);
+        let mut overrides = Overrides::default();
+        let fan = |channel| FanRef {
+            controller_id: 1,
+            channel,
+        };
+        overrides.set_speed(fan(1), 80);
+
+        let explained = explain_fan(&cfg, &controllers, &temps, &names, true, &overrides, fan(1))
+            .await
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(&explained).unwrap(),
+            json!({
+                "controller": 1,
+                "channel": 1,
+                "name": "CPU left",
+                "active_curve": "Silent",
+                "sensors": [
+                    { "sensor": "cpu", "curve": null, "temperature": 45.5, "curve_speed": 45 },
+                    { "sensor": "gpu", "curve": "Loud", "temperature": null, "curve_speed": null },
+                ],
+                "speed": 45,
+                "rpm": 900,
+                "auto_control": true,
+                "manual_speed": null,
+            })
+        );
+
+        let manual = explain_fan(
+            &cfg,
+            &controllers,
+            &temps,
+            &names,
+            false,
+            &overrides,
+            fan(1),
+        )
+        .await
+        .unwrap();
+        assert_eq!(manual.manual_speed, Some(80));
+
+        temps.insert(String::from("gpu"), 60.0);
+        let explained = explain_fan(&cfg, &controllers, &temps, &names, true, &overrides, fan(1))
+            .await
+            .unwrap();
+        assert_eq!(explained.sensors[1].curve_speed, Some(90));
+
+        let err = explain_fan(&cfg, &controllers, &temps, &names, true, &overrides, fan(3))
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "fan 1:3 not found");
+    }
+}
@@ -0,0 +1,1326 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use futures::future::join_all;
+use log::{error, info};
+use tokio::{
+    sync::{RwLock, watch},
+    time::{interval, sleep},
+};
+use tokio_stream::{StreamExt, wrappers::IntervalStream};
+
+use crate::{
+    config::{Config, ControllerCfg, CurveRef},
+    controller::Controllers,
+    curve_bands::CurveBands,
+    fan_curve::FanCurve,
+    fan_groups::FanGroups,
+    history::{TemperatureHistory, unix_now},
+    log_throttle::LogThrottle,
+    mappings::{FanNames, FanRef, Mapping},
+    schedules::Schedules,
+    sensor_snapshot::{SENSOR_TIMEOUT, SensorSnapshot},
+    sensors::{TemperatureSensor, sensor_limits},
+    thresholds::{CriticalAlerts, Thresholds},
+    trend::Trends,
+    watchdog::Heartbeat,
+};
+
+/// Speed of every fan driven by a sensor at its critical limit.
+pub const CRITICAL_SPEED: u8 = 100;
+
+/// A fan's `floor`: the fan never runs slower than `curve` asks for at `sensor`.
+#[derive(Debug, Clone)]
+pub struct CurveFloor {
+    sensor: String,
+    curve: FanCurve,
+}
+
+impl CurveFloor {
+    /// Builds the floor of every configured fan whose curve is defined.
+    fn from_cfg(cfg: &Config) -> HashMap<FanRef, CurveFloor> {
+        let mut floors = HashMap::new();
+        for (idx, ctrl) in cfg.controllers.iter().enumerate() {
+            let ControllerCfg::RiingQuad { fans, .. } = ctrl;
+            for fan in fans {
+                let Some(floor) = &fan.floor else {
+                    continue;
+                };
+                let Some(curve) = cfg.curves.iter().find(|c| c.get_id() == floor.curve) else {
+                    continue;
+                };
+                let fan_ref = FanRef {
+                    controller_id: idx + 1,
+                    channel: fan.idx as usize,
+                };
+                floors.insert(
+                    fan_ref,
+                    CurveFloor {
+                        sensor: floor.sensor.clone(),
+                        curve: FanCurve::from(curve),
+                    },
+                );
+            }
+        }
+        floors
+    }
+
+    /// Speed the floor asks for, if its sensor has a reading the curve covers.
+    fn speed(&self, temperatures: &BTreeMap<String, f32>, running: bool) -> Option<u8> {
+        let temp = *temperatures.get(&self.sensor)?;
+        self.curve.speed_at(temp, running).ok()
+    }
+}
+
+/// Curves set on sensor mappings, per fan and sensor, that replace the fan's active curve
+/// while that sensor drives it.
+fn sensor_curves(cfg: &Config) -> HashMap<FanRef, HashMap<String, FanCurve>> {
+    let mut curves: HashMap<FanRef, HashMap<String, FanCurve>> = HashMap::new();
+    for mapping in &cfg.mappings {
+        let Some(id) = &mapping.curve else {
+            continue;
+        };
+        let Some(curve) = cfg.curves.iter().find(|c| &c.get_id() == id) else {
+            continue;
+        };
+        for target in &mapping.targets {
+            let fan = FanRef {
+                controller_id: target.controller as usize,
+                channel: target.fan_idx as usize,
+            };
+            curves
+                .entry(fan)
+                .or_default()
+                .insert(mapping.sensor.clone(), FanCurve::from(curve));
+        }
+    }
+    curves
+}
+
+/// Asks the control loops for an extra tick right away, so a change made over D-Bus shows
+/// without waiting for the interval. Each loop runs it between its own ticks, never during
+/// one, and a trigger sent mid-tick runs once that tick is done.
+#[derive(Debug, Clone)]
+pub struct ApplyNow(Arc<watch::Sender<()>>);
+
+impl Default for ApplyNow {
+    fn default() -> Self {
+        Self(Arc::new(watch::channel(()).0))
+    }
+}
+
+impl ApplyNow {
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn trigger(&self) {
+        self.0.send_replace(());
+    }
+
+    fn subscribe(&self) -> watch::Receiver<()> {
+        self.0.subscribe()
+    }
+}
+
+/// Rules applied on top of each fan's own curve every tick.
+#[derive(Debug)]
+pub struct FanRules {
+    pub curve_bands: HashMap<FanRef, CurveBands>,
+    pub floors: HashMap<FanRef, CurveFloor>,
+    /// Critical limit by sensor, see [`critical_temperatures`].
+    pub critical: HashMap<String, f32>,
+    /// Mapping curves by fan, then by the sensor they apply to.
+    pub sensor_curves: HashMap<FanRef, HashMap<String, FanCurve>>,
+    pub groups: FanGroups,
+    pub schedules: Schedules,
+    /// Cleared for manual mode: temperatures are still read, but fan speeds are left alone.
+    pub auto_control: Arc<AtomicBool>,
+    /// Master switch, see `SetEnabled`: cleared, nothing is written to the fans, whatever
+    /// `auto_control` says.
+    pub enabled: Arc<AtomicBool>,
+    pub apply_now: ApplyNow,
+    /// Controllers (1-based) these rules drive; `None` for all of them.
+    pub controllers: Option<HashSet<usize>>,
+    pub names: FanNames,
+}
+
+impl FanRules {
+    pub fn from_cfg(cfg: &Config) -> Self {
+        Self {
+            curve_bands: CurveBands::from_cfg(cfg),
+            floors: CurveFloor::from_cfg(cfg),
+            critical: HashMap::new(),
+            sensor_curves: sensor_curves(cfg),
+            groups: FanGroups::from_cfg(&cfg.fan_groups),
+            schedules: Schedules::from_cfg(cfg),
+            auto_control: Arc::new(AtomicBool::new(true)),
+            enabled: Arc::new(AtomicBool::new(true)),
+            apply_now: ApplyNow::default(),
+            controllers: None,
+            names: FanNames::from_cfg(cfg),
+        }
+    }
+
+    /// Splits the rules per controller tick override, keyed by seconds. The global
+    /// `tick_seconds` is always present, possibly driving no controllers.
+    pub fn split_by_tick(self, cfg: &Config) -> BTreeMap<u16, FanRules> {
+        let mut ticks = BTreeMap::from([(cfg.tick_seconds, HashSet::new())]);
+        for (idx, ctrl) in cfg.controllers.iter().enumerate() {
+            ticks
+                .entry(ctrl.tick_seconds(cfg.tick_seconds))
+                .or_default()
+                .insert(idx + 1);
+        }
+        if ticks.len() == 1 {
+            return BTreeMap::from([(cfg.tick_seconds, self)]);
+        }
+        ticks
+            .into_iter()
+            .map(|(tick, ids)| {
+                let mut rules = FanRules {
+                    curve_bands: self.curve_bands.clone(),
+                    floors: self.floors.clone(),
+                    critical: self.critical.clone(),
+                    sensor_curves: self.sensor_curves.clone(),
+                    groups: self.groups.clone(),
+                    schedules: self.schedules.clone(),
+                    auto_control: self.auto_control.clone(),
+                    enabled: self.enabled.clone(),
+                    apply_now: self.apply_now.clone(),
+                    controllers: Some(ids),
+                    names: self.names.clone(),
+                };
+                rules.retain_driven();
+                (tick, rules)
+            })
+            .collect()
+    }
+
+    /// Takes the curves, mappings, groups and schedules of a reloaded config. The switches,
+    /// critical limits, driven controllers and fan names stay, as they don't come from
+    /// sections a reload can change.
+    pub fn reload(&mut self, cfg: &Config) {
+        self.curve_bands = CurveBands::from_cfg(cfg);
+        self.floors = CurveFloor::from_cfg(cfg);
+        self.sensor_curves = sensor_curves(cfg);
+        self.groups = FanGroups::from_cfg(&cfg.fan_groups);
+        self.schedules = self.schedules.reloaded(cfg);
+        self.retain_driven();
+    }
+
+    /// Drops the per-fan rules of fans on controllers these rules don't drive.
+    fn retain_driven(&mut self) {
+        let Some(ids) = &self.controllers else {
+            return;
+        };
+        self.curve_bands
+            .retain(|fan, _| ids.contains(&fan.controller_id));
+        self.floors
+            .retain(|fan, _| ids.contains(&fan.controller_id));
+        self.sensor_curves
+            .retain(|fan, _| ids.contains(&fan.controller_id));
+    }
+
+    fn drives(&self, fan: &FanRef) -> bool {
+        self.controllers
+            .as_ref()
+            .is_none_or(|ids| ids.contains(&fan.controller_id))
+    }
+}
+
+/// `crit` limit of every sensor whose hardware reports one. A sensor at or above it runs
+/// every fan it drives at [`CRITICAL_SPEED`].
+pub async fn critical_temperatures(sensors: &[Box<dyn TemperatureSensor>]) -> HashMap<String, f32> {
+    sensor_limits(sensors)
+        .await
+        .into_iter()
+        .filter_map(|(name, limits)| Some((name, limits.crit?)))
+        .collect()
+}
+
+/// Waits `startup_delay_ms` for the USB bus to settle, then initializes every controller.
+/// All of them are tried; the first failure is returned and later ones are logged, each
+/// naming the controller's id and USB ids.
+pub async fn initialize(cfg: &Config, controllers: &Controllers) -> Result<()> {
+    if cfg.startup_delay_ms > 0 {
+        info!(
+            "Waiting {} ms before initializing controllers",
+            cfg.startup_delay_ms
+        );
+        sleep(Duration::from_millis(cfg.startup_delay_ms)).await;
+    }
+    let mut result = Ok(());
+    for controller in 1..=controllers.count() as u8 {
+        let Err(e) = controllers.send_init(controller).await else {
+            continue;
+        };
+        let label = cfg.controllers.get(controller as usize - 1).map_or_else(
+            || format!("controller {controller}"),
+            ControllerCfg::describe,
+        );
+        let e = e.context(format!("{label} failed to initialize"));
+        if result.is_ok() {
+            result = Err(e);
+        } else {
+            error!("{e:#}");
+        }
+    }
+    result
+}
+
+/// Sets every fan right after `send_init` so none sits at the firmware default until the
+/// first tick: to `startup_speed` when configured, otherwise from the temperatures now.
+/// Does nothing while fan control is disabled.
+pub async fn apply_startup(
+    cfg: &Config,
+    controllers: &Controllers,
+    sensors: &[Box<dyn TemperatureSensor>],
+    mapping: &Mapping,
+    rules: &mut FanRules,
+) {
+    if !rules.enabled.load(Ordering::Relaxed) {
+        info!("Fan control is disabled, leaving fans at their firmware speed");
+        return;
+    }
+    let mut throttle = LogThrottle::default();
+    let Some(speed) = cfg.startup_speed else {
+        let snapshot = SensorSnapshot::read(sensors, SENSOR_TIMEOUT).await;
+        apply_temperatures(
+            controllers,
+            mapping,
+            rules,
+            &snapshot.temperatures,
+            &mut throttle,
+        )
+        .await;
+        return;
+    };
+    for (idx, ctrl) in cfg.controllers.iter().enumerate() {
+        let ControllerCfg::RiingQuad { fans, .. } = ctrl;
+        if fans.is_empty() {
+            continue;
+        }
+        let targets: Vec<_> = fans.iter().map(|fan| (fan.idx, speed)).collect();
+        if let Err(e) = controllers
+            .set_channel_speeds((idx + 1) as u8, &targets)
+            .await
+        {
+            throttle.error(
+                &rules.names.controller_label((idx + 1) as u8),
+                format_args!("startup speed error: {e}"),
+            );
+        }
+    }
+}
+
+/// Where each tick's temperatures go besides fan control.
+pub struct Readings {
+    pub latest: Arc<RwLock<HashMap<String, f32>>>,
+    pub history: Arc<RwLock<TemperatureHistory>>,
+    pub thresholds: Thresholds,
+    pub critical: CriticalAlerts,
+    pub trends: Option<Trends>,
+}
+
+impl Readings {
+    async fn record(&mut self, name: &str, temp: f32) {
+        self.latest.write().await.insert(name.to_string(), temp);
+        {
+            let mut history = self.history.write().await;
+            history.push(name, unix_now(), temp);
+            if let Some(trends) = self.trends.as_mut() {
+                trends.update(name, &history.get(name));
+            }
+        }
+        self.thresholds.update(name, temp);
+        self.critical.update(name, temp);
+        #[cfg(debug_assertions)]
+        {
+            info!("Temperature of {name}: {temp}°C");
+        }
+    }
+}
+
+/// Reads the sensors every `tick`, and whenever [`ApplyNow`] is triggered, and updates the
+/// fans `rules` drive. Only the loop given
+/// `readings` records temperatures and logs sensor errors, so loops for other ticks don't
+/// repeat that work. `heartbeat` is refreshed after every tick for the watchdog.
+///
+/// Fans are mapped to sensors as `config` says; each config sent through it after a reload
+/// replaces the mappings and rules, pushes changed curves to the controllers and runs a
+/// tick right away.
+pub async fn control_loop(
+    tick: Duration,
+    controllers: Controllers,
+    sensors: Arc<Vec<Box<dyn TemperatureSensor>>>,
+    mut config: watch::Receiver<Arc<Config>>,
+    mut rules: FanRules,
+    mut readings: Option<Readings>,
+    heartbeat: Heartbeat,
+) {
+    let mut interval_stream = IntervalStream::new(interval(tick));
+    let mut apply_now = rules.apply_now.subscribe();
+    let mut throttle = LogThrottle::default();
+    let mut current = config.borrow_and_update().clone();
+    let mut mapping = Mapping::load_mappings(&current.mappings);
+    loop {
+        tokio::select! {
+            _ = interval_stream.next() => {}
+            Ok(()) = apply_now.changed() => {}
+            Ok(()) = config.changed() => {
+                let reloaded = config.borrow_and_update().clone();
+                push_curves(&controllers, &current, &reloaded, &rules).await;
+                mapping = Mapping::load_mappings(&reloaded.mappings);
+                rules.reload(&reloaded);
+                current = reloaded;
+            }
+        }
+        run_tick(
+            &controllers,
+            &sensors,
+            &mapping,
+            &mut rules,
+            readings.as_mut(),
+            &mut throttle,
+        )
+        .await;
+        heartbeat.beat();
+        #[cfg(debug_assertions)]
+        {
+            info!("[timer] tick");
+        }
+    }
+}
+
+/// Gives the fans `rules` drive the new data of every `curves` entry that differs between
+/// `old` and `new`. A curve whose kind changed is refused by the controller and keeps its
+/// old data until the daemon restarts.
+async fn push_curves(controllers: &Controllers, old: &Config, new: &Config, rules: &FanRules) {
+    let changed: HashMap<String, FanCurve> = new
+        .curves
+        .iter()
+        .map(|curve| (curve.get_id(), FanCurve::from(curve)))
+        .filter(|(id, curve)| {
+            old.curves
+                .iter()
+                .find(|c| &c.get_id() == id)
+                .is_none_or(|c| FanCurve::from(c).fingerprint() != curve.fingerprint())
+        })
+        .collect();
+    if changed.is_empty() {
+        return;
+    }
+    for (idx, ctrl) in new.controllers.iter().enumerate() {
+        let ControllerCfg::RiingQuad { fans, .. } = ctrl;
+        for fan in fans {
+            let fan_ref = FanRef {
+                controller_id: idx + 1,
+                channel: fan.idx as usize,
+            };
+            if !rules.drives(&fan_ref) {
+                continue;
+            }
+            for curve_ref in &fan.curve {
+                let CurveRef::Id(id) = curve_ref else {
+                    continue;
+                };
+                let Some(curve) = changed.get(id) else {
+                    continue;
+                };
+                if let Err(e) = controllers
+                    .update_curve_data(idx as u8 + 1, fan.idx, id, curve)
+                    .await
+                {
+                    error!(
+                        "Fan {} keeps the old data of curve {id}: {e}",
+                        rules.names.label(fan_ref)
+                    );
+                }
+            }
+        }
+    }
+}
+
+/// One tick of [`control_loop`]: reads the sensors, records them into `readings` when
+/// given, and updates the fans.
+async fn run_tick(
+    controllers: &Controllers,
+    sensors: &[Box<dyn TemperatureSensor>],
+    mapping: &Mapping,
+    rules: &mut FanRules,
+    readings: Option<&mut Readings>,
+    throttle: &mut LogThrottle,
+) {
+    let snapshot = SensorSnapshot::read(sensors, SENSOR_TIMEOUT).await;
+    if let Some(readings) = readings {
+        for (name, e) in &snapshot.errors {
+            throttle.error(&format!("sensor {name}"), format_args!("read error: {e}"));
+        }
+        for (name, &t) in &snapshot.temperatures {
+            throttle.clear(&format!("sensor {name}"));
+            readings.record(name, t).await;
+        }
+    }
+    apply_temperatures(
+        controllers,
+        mapping,
+        rules,
+        &snapshot.temperatures,
+        throttle,
+    )
+    .await;
+}
+
+/// Computes and writes fan speeds for one tick of sensor readings.
+pub async fn apply_temperatures(
+    controllers: &Controllers,
+    mapping: &Mapping,
+    rules: &mut FanRules,
+    temperatures: &BTreeMap<String, f32>,
+    throttle: &mut LogThrottle,
+) {
+    controllers.clear_curve_caches().await;
+    if !rules.auto_control.load(Ordering::Relaxed) || !rules.enabled.load(Ordering::Relaxed) {
+        return;
+    }
+    let window = rules.schedules.active();
+    let mut speeds: BTreeMap<FanRef, u8> = BTreeMap::new();
+    for (name, &t) in temperatures {
+        for fan in mapping.fans_for_sensor(name) {
+            if !rules.drives(&fan) {
+                continue;
+            }
+            let (ctrl, channel) = (fan.controller_id as u8, fan.channel as u8);
+            if let Some(curve) = rules.curve_bands.get_mut(&fan).and_then(|b| b.update(t)) {
+                let label = rules.names.label(fan);
+                info!("Fan {label} switches to curve {curve} at {t}°C");
+                if let Err(e) = controllers.switch_curve(ctrl, channel, curve).await {
+                    throttle.error(
+                        &format!("fan {label}"),
+                        format_args!("switch_curve error: {e}"),
+                    );
+                }
+            }
+            if rules.groups.is_follower(&fan) {
+                continue;
+            }
+            match controllers.channel_speed(ctrl, channel, t).await {
+                Ok(speed) => {
+                    // `Config::validate` keeps RPM curves, the only ones that fail here, out
+                    // of mappings, schedules and floors.
+                    let speed = rules
+                        .sensor_curves
+                        .get(&fan)
+                        .and_then(|curves| curves.get(name))
+                        .and_then(|curve| curve.speed_at(t, speed > 0).ok())
+                        .unwrap_or(speed);
+                    let speed = window
+                        .and_then(|w| w.curve.as_ref())
+                        .and_then(|curve| curve.speed_at(t, speed > 0).ok())
+                        .unwrap_or(speed);
+                    let floor = rules
+                        .floors
+                        .get(&fan)
+                        .and_then(|floor| floor.speed(temperatures, speed > 0));
+                    let speed = floor.map_or(speed, |floor| speed.max(floor));
+                    // With several sensors on one fan, the one asking for most wins.
+                    speeds
+                        .entry(fan)
+                        .and_modify(|s| *s = (*s).max(speed))
+                        .or_insert(speed);
+                }
+                Err(e) => throttle.error(
+                    &format!("fan {}", rules.names.label(fan)),
+                    format_args!("speed error: {e}"),
+                ),
+            }
+        }
+    }
+    rules.groups.apply(&mut speeds);
+    // A quiet window's cap has the last word, over floors and groups alike.
+    if let Some(cap) = window.and_then(|w| w.max_speed) {
+        speeds
+            .values_mut()
+            .for_each(|speed| *speed = (*speed).min(cap));
+    }
+    // A sensor at its critical limit overrides everything, quiet hours included.
+    for (name, &t) in temperatures {
+        let Some(&crit) = rules.critical.get(name) else {
+            continue;
+        };
+        let source = format!("critical {name}");
+        if t < crit {
+            throttle.clear(&source);
+            continue;
+        }
+        throttle.error(
+            &source,
+            format_args!(
+                "{t}°C is at or above its critical {crit}°C, running its fans at full speed"
+            ),
+        );
+        for fan in mapping
+            .fans_for_sensor(name)
+            .filter(|fan| rules.drives(fan))
+        {
+            speeds.insert(fan, CRITICAL_SPEED);
+        }
+    }
+    // Send each controller's fans together so cross-fan rules such as the
+    // airflow floor see the whole tick, and write to the controllers concurrently
+    // so one sensor driving fans on several of them doesn't queue up HID writes.
+    let mut pending: BTreeMap<u8, Vec<(u8, u8)>> = BTreeMap::new();
+    for (fan, speed) in speeds {
+        pending
+            .entry(fan.controller_id as u8)
+            .or_default()
+            .push((fan.channel as u8, speed));
+    }
+    let writes = pending.iter().map(|(&ctrl, targets)| async move {
+        (ctrl, controllers.set_channel_speeds(ctrl, targets).await)
+    });
+    for (ctrl, result) in join_all(writes).await {
+        let source = rules.names.controller_label(ctrl);
+        match result {
+            Ok(()) => throttle.clear(&source),
+            Err(e) => throttle.error(&source, format_args!("update error: {e}")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{BTreeMap, HashMap},
+        sync::{Arc, atomic::Ordering},
+        time::Duration,
+    };
+
+    use tokio::sync::{RwLock, watch};
+
+    use super::{
+        CRITICAL_SPEED, FanRules, Readings, apply_startup, apply_temperatures, control_loop,
+        initialize, run_tick,
+    };
+    use crate::{
+        config::{Config, ControllerCfg, CurveCfg, FanTarget, MappingCfg, ScheduleCfg, SensorCfg},
+        config_watcher::ConfigReloader,
+        controller::{Controllers, tests::MockController},
+        events::EventBus,
+        fan_curve::FanCurve,
+        history::TemperatureHistory,
+        log_throttle::LogThrottle,
+        mappings::Mapping,
+        schedules::FixedClock,
+        sensors::{SensorRegistry, TemperatureSensor},
+        services::{CancelToken, Services, spawn_dedicated},
+        temperature_sensors::external::{ExternalFactory, ExternalTemperatures},
+        thresholds::{CriticalAlerts, Thresholds},
+        watchdog::Heartbeat,
+    };
+
+    fn external_sensors(
+        external: &ExternalTemperatures,
+        cfg: &Config,
+    ) -> Vec<Box<dyn TemperatureSensor>> {
+        let mut registry = SensorRegistry::default();
+        registry.register("external", ExternalFactory(external.clone()));
+        registry.build_all(&cfg.sensors).unwrap()
+    }
+
+    #[tokio::test]
+    async fn manual_mode_leaves_speeds_alone() {
+        let mock = MockController::with_fans(2);
+        let speeds = mock.speeds.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let mapping =
+            Mapping::load_mappings(&[MappingCfg::builder("cpu").target(1, 1).target(1, 2).build()]);
+        let mut rules = FanRules::from_cfg(&Config::default());
+        let mut throttle = LogThrottle::default();
+        let temps = |t| BTreeMap::from([(String::from("cpu"), t)]);
+
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(40.0),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [40, 40]);
+
+        rules.auto_control.store(false, Ordering::Relaxed);
+        controllers.set_speed_direct(1, 1, 90).await.unwrap();
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(55.0),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [90, 40]);
+
+        rules.auto_control.store(true, Ordering::Relaxed);
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(55.0),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [55, 55]);
+    }
+
+    #[tokio::test]
+    async fn disabled_control_writes_nothing() {
+        let mock = MockController::with_fans(2);
+        let writes = mock.writes.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let cfg = Config::builder()
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "Front", &["Silent"])
+                    .fan(2, "Rear", &["Silent"]),
+            )
+            .sensor(SensorCfg::External {
+                id: String::from("cpu"),
+                max_age: 30,
+            })
+            .build();
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        external.set("cpu", 45.0).unwrap();
+        let sensors = external_sensors(&external, &cfg);
+        let mapping =
+            Mapping::load_mappings(&[MappingCfg::builder("cpu").target(1, 1).target(1, 2).build()]);
+        let mut rules = FanRules::from_cfg(&cfg);
+        rules.enabled.store(false, Ordering::Relaxed);
+        let mut readings = Readings {
+            latest: Arc::default(),
+            history: Arc::new(RwLock::new(TemperatureHistory::new(10))),
+            thresholds: Thresholds::new(&[], EventBus::default()),
+            critical: CriticalAlerts::new(HashMap::new(), EventBus::default()),
+            trends: None,
+        };
+
+        apply_startup(&cfg, &controllers, &sensors, &mapping, &mut rules).await;
+        run_tick(
+            &controllers,
+            &sensors,
+            &mapping,
+            &mut rules,
+            Some(&mut readings),
+            &mut LogThrottle::default(),
+        )
+        .await;
+        assert_eq!(writes.load(Ordering::Relaxed), 0);
+        assert_eq!(readings.latest.read().await.get("cpu"), Some(&45.0));
+
+        rules.enabled.store(true, Ordering::Relaxed);
+        run_tick(
+            &controllers,
+            &sensors,
+            &mapping,
+            &mut rules,
+            None,
+            &mut LogThrottle::default(),
+        )
+        .await;
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+    }
+
+    #[tokio::test]
+    async fn mapping_curve_follows_the_driving_sensor() {
+        let mock = MockController::with_fans(2);
+        let speeds = mock.speeds.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let cfg = Config::builder()
+            .curve(CurveCfg::constant("Gentle", 50))
+            .curve(CurveCfg::step("Aggressive", &[30.0, 80.0], &[40, 100]))
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "CPU", &["Silent"])
+                    .fan(2, "Case", &["Silent"]),
+            )
+            .mapping(MappingCfg::builder("zone").target(1, 1).curve("Gentle"))
+            .mapping(MappingCfg::builder("zone").target(1, 2))
+            .mapping(MappingCfg::builder("cpu").target(1, 1).curve("Aggressive"))
+            .build();
+        cfg.validate().unwrap();
+        let mapping = Mapping::load_mappings(&cfg.mappings);
+        let mut rules = FanRules::from_cfg(&cfg);
+        let mut throttle = LogThrottle::default();
+        let temps = |cpu: Option<f32>| {
+            let mut temps = BTreeMap::from([(String::from("zone"), 30.0)]);
+            temps.extend(cpu.map(|t| (String::from("cpu"), t)));
+            temps
+        };
+
+        // `cpu` sorts before `zone` but asks for more, so it drives fan 1.
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(Some(55.0)),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [70, 30]);
+
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(Some(30.0)),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [50, 30]);
+
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(None),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [50, 30]);
+
+        let mut broken = cfg.clone();
+        broken.mappings[0].curve = Some(String::from("Missing"));
+        let msg = broken.validate().unwrap_err().to_string();
+        assert_eq!(
+            msg,
+            "sensor `zone` mapping: curve `Missing` is not defined in `curves`"
+        );
+    }
+
+    #[tokio::test]
+    async fn critical_sensor_runs_its_fans_at_full_speed() {
+        let mock = MockController::with_fans(2);
+        let speeds = mock.speeds.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let mapping = Mapping::load_mappings(&[
+            MappingCfg::builder("cpu").target(1, 1).build(),
+            MappingCfg::builder("gpu").target(1, 2).build(),
+        ]);
+        let mut rules = FanRules::from_cfg(&Config::builder().build());
+        rules.critical.insert(String::from("cpu"), 90.0);
+        let mut throttle = LogThrottle::default();
+        let temps =
+            |cpu, gpu| BTreeMap::from([(String::from("cpu"), cpu), (String::from("gpu"), gpu)]);
+
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(90.0, 95.0),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [CRITICAL_SPEED, 95]);
+
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(60.0, 95.0),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [60, 95]);
+    }
+
+    #[tokio::test]
+    async fn floor_curve_wins_when_it_asks_for_more() {
+        let mock = MockController::with_fans(2);
+        let speeds = mock.speeds.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let cfg = Config::builder()
+            .curve(CurveCfg::step("CPU", &[30.0, 80.0], &[20, 90]))
+            .sensor(SensorCfg::External {
+                id: String::from("cpu"),
+                max_age: 30,
+            })
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "GPU front", &["Silent"])
+                    .floor("cpu", "CPU")
+                    .fan(2, "GPU rear", &["Silent"]),
+            )
+            .build();
+        cfg.validate().unwrap();
+        let mapping =
+            Mapping::load_mappings(&[MappingCfg::builder("gpu").target(1, 1).target(1, 2).build()]);
+        let mut rules = FanRules::from_cfg(&cfg);
+        let mut throttle = LogThrottle::default();
+        let temps =
+            |gpu, cpu| BTreeMap::from([(String::from("gpu"), gpu), (String::from("cpu"), cpu)]);
+
+        // The mock's own curve runs each fan at the GPU temperature.
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(40.0, 70.0),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [76, 40]);
+
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &temps(60.0, 30.0),
+            &mut throttle,
+        )
+        .await;
+        assert_eq!(*speeds.lock().unwrap(), [60, 60]);
+
+        let mut broken = cfg.clone();
+        let ControllerCfg::RiingQuad { fans, .. } = &mut broken.controllers[0];
+        fans[0].floor.as_mut().unwrap().curve = String::from("Missing");
+        let msg = broken.validate().unwrap_err().to_string();
+        assert!(msg.contains("floor curve `Missing`"), "{msg}");
+    }
+
+    #[tokio::test]
+    async fn quiet_hours_cap_speeds_only_inside_the_window() {
+        let mock = MockController::with_fans(2);
+        let speeds = mock.speeds.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let cfg = Config::builder()
+            .curve(CurveCfg::constant("Night", 25))
+            .schedule(ScheduleCfg {
+                from: String::from("22:00"),
+                to: String::from("07:00"),
+                max_speed: Some(35),
+                curve: None,
+            })
+            .schedule(ScheduleCfg {
+                from: String::from("12:00"),
+                to: String::from("13:00"),
+                max_speed: None,
+                curve: Some(String::from("Night")),
+            })
+            .build();
+        cfg.validate().unwrap();
+        let mapping =
+            Mapping::load_mappings(&[MappingCfg::builder("cpu").target(1, 1).target(1, 2).build()]);
+        let temps = BTreeMap::from([(String::from("cpu"), 60.0)]);
+        let mut throttle = LogThrottle::default();
+        let mut speeds_at = async |minute: u16| {
+            let mut rules = FanRules::from_cfg(&cfg);
+            rules.schedules = rules.schedules.with_clock(FixedClock(minute));
+            apply_temperatures(&controllers, &mapping, &mut rules, &temps, &mut throttle).await;
+            speeds.lock().unwrap().clone()
+        };
+
+        // The mock's own curve runs each fan at the temperature.
+        assert_eq!(speeds_at(23 * 60 + 30).await, [35, 35]);
+        assert_eq!(speeds_at(6 * 60).await, [35, 35]);
+        assert_eq!(speeds_at(7 * 60).await, [60, 60]);
+        assert_eq!(speeds_at(12 * 60 + 15).await, [25, 25]);
+        assert_eq!(speeds_at(18 * 60).await, [60, 60]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn one_sensor_updates_controllers_concurrently() {
+        let (first, second) = (MockController::with_fans(2), MockController::with_fans(2));
+        let (first_speeds, second_speeds) = (first.speeds.clone(), second.speeds.clone());
+        let controllers = Controllers::from(vec![
+            first.write_delay(Duration::from_millis(100)).boxed(),
+            second.write_delay(Duration::from_millis(100)).boxed(),
+        ]);
+        let mapping = Mapping::load_mappings(&[MappingCfg::builder("cpu")
+            .target(1, 1)
+            .target(1, 2)
+            .target(2, 1)
+            .target(2, 2)
+            .build()]);
+        let mut rules = FanRules::from_cfg(&Config::default());
+        let start = tokio::time::Instant::now();
+
+        apply_temperatures(
+            &controllers,
+            &mapping,
+            &mut rules,
+            &BTreeMap::from([(String::from("cpu"), 45.0)]),
+            &mut LogThrottle::default(),
+        )
+        .await;
+        assert_eq!(*first_speeds.lock().unwrap(), [45, 45]);
+        assert_eq!(*second_speeds.lock().unwrap(), [45, 45]);
+        assert_eq!(start.elapsed(), Duration::from_millis(100));
+    }
+
+    /// Stress harness for the monitoring loop: `SENSORS` sensors, each recorded and driving
+    /// two of the fans on `CONTROLLERS` controllers that take `WRITE_DELAY` per HID write.
+    /// Time is paused, so a tick costs exactly the write delays it waits on: one when the
+    /// controllers are written concurrently, one per controller when they are not.
+    #[tokio::test(start_paused = true)]
+    async fn busy_tick_stays_within_bound() {
+        const SENSORS: usize = 64;
+        const CONTROLLERS: usize = 16;
+        const TICKS: u32 = 20;
+        const WRITE_DELAY: Duration = Duration::from_millis(5);
+
+        let mocks: Vec<_> = (0..CONTROLLERS)
+            .map(|_| MockController::with_fans(5).write_delay(WRITE_DELAY))
+            .collect();
+        let speeds: Vec<_> = mocks.iter().map(|mock| mock.speeds.clone()).collect();
+        let controllers =
+            Controllers::from(mocks.into_iter().map(|m| m.boxed()).collect::<Vec<_>>());
+        let mut cfg = Config::builder();
+        for i in 0..SENSORS {
+            cfg = cfg.sensor(SensorCfg::External {
+                id: format!("s{i}"),
+                max_age: 3600,
+            });
+        }
+        let cfg = cfg.build();
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        for i in 0..SENSORS {
+            external
+                .set(&format!("s{i}"), 30.0 + (i % 40) as f32)
+                .unwrap();
+        }
+        let sensors = external_sensors(&external, &cfg);
+        let mappings: Vec<_> = (0..SENSORS)
+            .map(|i| {
+                let controller = (i % CONTROLLERS + 1) as u8;
+                MappingCfg::builder(format!("s{i}"))
+                    .target(controller, (i / CONTROLLERS + 1) as u8)
+                    .target(controller, 5)
+                    .build()
+            })
+            .collect();
+        let mapping = Mapping::load_mappings(&mappings);
+        let mut rules = FanRules::from_cfg(&cfg);
+        let bus = EventBus::new(16);
+        let mut readings = Readings {
+            latest: Arc::default(),
+            history: Arc::new(RwLock::new(TemperatureHistory::new(TICKS as usize))),
+            thresholds: Thresholds::new(&[], bus.clone()),
+            critical: CriticalAlerts::new(HashMap::new(), bus),
+            trends: None,
+        };
+        let mut throttle = LogThrottle::default();
+
+        let start = tokio::time::Instant::now();
+        for _ in 0..TICKS {
+            run_tick(
+                &controllers,
+                &sensors,
+                &mapping,
+                &mut rules,
+                Some(&mut readings),
+                &mut throttle,
+            )
+            .await;
+        }
+        let per_tick = start.elapsed() / TICKS;
+
+        assert_eq!(readings.latest.read().await.len(), SENSORS);
+        // Fan 5 is shared by every sensor on the controller; the hottest one drives it.
+        assert_eq!(*speeds[0].lock().unwrap(), [30, 46, 62, 38, 62]);
+        assert_eq!(
+            per_tick,
+            WRITE_DELAY,
+            "a tick over {SENSORS} sensors and {} fans waited on more than one write",
+            CONTROLLERS * 5
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn controllers_update_at_their_own_tick() {
+        let (fast, slow) = (MockController::with_fans(1), MockController::with_fans(1));
+        let (fast_writes, slow_writes) = (fast.writes.clone(), slow.writes.clone());
+        let controllers = Controllers::from(vec![fast.boxed(), slow.boxed()]);
+        let cfg = Config::builder()
+            .tick_seconds(5)
+            .controller(
+                ControllerCfg::riing_quad("loop", 0x264A, 0x232B)
+                    .fan(1, "Radiator", &["Silent"])
+                    .tick_seconds(1),
+            )
+            .controller(ControllerCfg::riing_quad("case", 0x264A, 0x232C).fan(
+                1,
+                "Rear",
+                &["Silent"],
+            ))
+            .sensor(SensorCfg::External {
+                id: String::from("cpu"),
+                max_age: 3600,
+            })
+            .mapping(MappingCfg::builder("cpu").target(1, 1).target(2, 1))
+            .build();
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        external.set("cpu", 40.0).unwrap();
+        let sensors = Arc::new(external_sensors(&external, &cfg));
+        let (_reloads, config) = watch::channel(Arc::new(cfg.clone()));
+
+        let loops: Vec<_> = FanRules::from_cfg(&cfg)
+            .split_by_tick(&cfg)
+            .into_iter()
+            .map(|(tick, rules)| {
+                tokio::spawn(control_loop(
+                    Duration::from_secs(tick as u64),
+                    controllers.clone(),
+                    sensors.clone(),
+                    config.clone(),
+                    rules,
+                    None,
+                    Heartbeat::default(),
+                ))
+            })
+            .collect();
+        tokio::time::sleep(Duration::from_millis(9500)).await;
+        loops.iter().for_each(|handle| handle.abort());
+
+        // Ticks at 0..=9s for the fast controller, at 0s and 5s for the slow one.
+        assert_eq!(fast_writes.load(Ordering::Relaxed), 10);
+        assert_eq!(slow_writes.load(Ordering::Relaxed), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn apply_now_runs_a_pass_before_the_next_tick() {
+        let mock = MockController::with_fans(1);
+        let speeds = mock.speeds.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let cfg = Config::builder()
+            .controller(ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B).fan(
+                1,
+                "Front",
+                &["Silent"],
+            ))
+            .sensor(SensorCfg::External {
+                id: String::from("cpu"),
+                max_age: 30,
+            })
+            .mapping(MappingCfg::builder("cpu").target(1, 1))
+            .build();
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        external.set("cpu", 40.0).unwrap();
+        let sensors = Arc::new(external_sensors(&external, &cfg));
+        let (_reloads, config) = watch::channel(Arc::new(cfg.clone()));
+        let rules = FanRules::from_cfg(&cfg);
+        let apply_now = rules.apply_now.clone();
+
+        let task = tokio::spawn(control_loop(
+            Duration::from_secs(60),
+            controllers,
+            sensors,
+            config,
+            rules,
+            None,
+            Heartbeat::default(),
+        ));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(*speeds.lock().unwrap(), [40]);
+
+        external.set("cpu", 55.0).unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert_eq!(*speeds.lock().unwrap(), [40]);
+        apply_now.trigger();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(*speeds.lock().unwrap(), [55]);
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reloaded_config_changes_the_written_speed() {
+        let mock = MockController::with_fans(2);
+        let (speeds, curve_data) = (mock.speeds.clone(), mock.curve_data.clone());
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let config = |curve: &str, silent: u8| {
+            Config::builder()
+                .curve(CurveCfg::constant("Silent", silent))
+                .curve(CurveCfg::constant("Low", 30))
+                .curve(CurveCfg::constant("High", 70))
+                .controller(
+                    ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                        .fan(1, "Front", &["Silent"])
+                        .fan(2, "Rear", &["Silent"]),
+                )
+                .sensor(SensorCfg::External {
+                    id: String::from("cpu"),
+                    max_age: 3600,
+                })
+                .mapping(MappingCfg::builder("cpu").target(1, 1).curve(curve))
+                .build()
+        };
+        let cfg = config("Low", 20);
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        external.set("cpu", 40.0).unwrap();
+        let sensors = Arc::new(external_sensors(&external, &cfg));
+        let (mut reloader, source) = ConfigReloader::in_memory(cfg.clone());
+
+        let task = tokio::spawn(control_loop(
+            Duration::from_secs(60),
+            controllers,
+            sensors,
+            reloader.subscribe(),
+            FanRules::from_cfg(&cfg),
+            None,
+            Heartbeat::default(),
+        ));
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(*speeds.lock().unwrap(), [30, 0]);
+
+        let mut reloaded = config("High", 25);
+        reloaded.mappings[0].targets.push(FanTarget {
+            controller: 1,
+            fan_idx: 2,
+        });
+        source.set(&serde_yaml::to_string(&reloaded).unwrap());
+        reloader.reload().unwrap();
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert_eq!(*speeds.lock().unwrap(), [70, 70]);
+        let pushed = curve_data.lock().unwrap()[&(2, String::from("Silent"))].clone();
+        assert_eq!(pushed.fingerprint(), FanCurve::Constant(25).fingerprint());
+        task.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn startup_sets_speeds_before_the_first_tick() {
+        let mock = MockController::with_fans(2);
+        let speeds = mock.speeds.clone();
+        let leds = MockController::with_fans(0);
+        let led_writes = leds.writes.clone();
+        let controllers = Controllers::from(vec![mock.boxed(), leds.boxed()]);
+        let mut cfg = Config::builder()
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "Front", &["Silent"])
+                    .fan(2, "Rear", &["Silent"]),
+            )
+            .controller(ControllerCfg::riing_quad("leds", 0x264A, 0x232C))
+            .sensor(SensorCfg::External {
+                id: String::from("cpu"),
+                max_age: 30,
+            })
+            .build();
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        external.set("cpu", 45.0).unwrap();
+        let sensors = external_sensors(&external, &cfg);
+        let mapping = Mapping::load_mappings(&[MappingCfg::builder("cpu").target(1, 2).build()]);
+        let mut rules = FanRules::from_cfg(&cfg);
+        let start = tokio::time::Instant::now();
+
+        apply_startup(&cfg, &controllers, &sensors, &mapping, &mut rules).await;
+        assert_eq!(*speeds.lock().unwrap(), [0, 45]);
+
+        cfg.startup_speed = Some(70);
+        apply_startup(&cfg, &controllers, &sensors, &mapping, &mut rules).await;
+        assert_eq!(*speeds.lock().unwrap(), [70, 70]);
+        assert_eq!(led_writes.load(Ordering::Relaxed), 0);
+        assert_eq!(start.elapsed(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn initialization_waits_for_the_startup_delay() {
+        let mock = MockController::with_fans(1);
+        let initialized_at = mock.initialized_at.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let mut cfg = Config::builder().build();
+        cfg.startup_delay_ms = 50;
+
+        let start = tokio::time::Instant::now();
+        initialize(&cfg, &controllers).await.unwrap();
+        let waited = initialized_at.lock().unwrap().unwrap() - start;
+        assert!(waited >= Duration::from_millis(50), "{waited:?}");
+    }
+
+    #[tokio::test]
+    async fn initialization_names_the_failing_controller() {
+        let first = MockController::with_fans(1);
+        let last = MockController::with_fans(1);
+        let initialized = [first.initialized_at.clone(), last.initialized_at.clone()];
+        let controllers = Controllers::from(vec![
+            first.boxed(),
+            MockController::with_fans(1)
+                .failing_init("no init response")
+                .boxed(),
+            last.boxed(),
+        ]);
+        let cfg = Config::builder()
+            .controller(ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B))
+            .controller(ControllerCfg::riing_quad("quad-2", 0x264A, 0x232C))
+            .build();
+
+        let err = initialize(&cfg, &controllers).await.unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "controller `quad-2` (264A:232C) failed to initialize: no init response"
+        );
+        assert!(initialized.iter().all(|at| at.lock().unwrap().is_some()));
+
+        let unlisted = Controllers::from(vec![
+            MockController::with_fans(1).failing_init("gone").boxed(),
+        ]);
+        let err = initialize(&Config::builder().build(), &unlisted)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            format!("{err:#}"),
+            "controller 1 failed to initialize: gone"
+        );
+    }
+
+    #[tokio::test]
+    async fn dedicated_thread_runs_and_stops_the_loop() {
+        let mock = MockController::with_fans(1);
+        let writes = mock.writes.clone();
+        let controllers = Controllers::from(vec![mock.boxed()]);
+        let cfg = Config::builder()
+            .controller(ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B).fan(
+                1,
+                "Front",
+                &["Silent"],
+            ))
+            .sensor(SensorCfg::External {
+                id: String::from("cpu"),
+                max_age: 30,
+            })
+            .mapping(MappingCfg::builder("cpu").target(1, 1))
+            .build();
+        let external = ExternalTemperatures::from_cfg(&cfg.sensors);
+        external.set("cpu", 40.0).unwrap();
+        let sensors = Arc::new(external_sensors(&external, &cfg));
+        let (_reloads, config) = watch::channel(Arc::new(cfg.clone()));
+        let mut services = Services::new(EventBus::default(), CancelToken::default());
+
+        let task = control_loop(
+            Duration::from_millis(10),
+            controllers,
+            sensors,
+            config,
+            FanRules::from_cfg(&cfg),
+            None,
+            Heartbeat::default(),
+        );
+        services.start("monitoring", spawn_dedicated("monitoring", task).unwrap());
+        for _ in 0..200 {
+            if writes.load(Ordering::Relaxed) >= 3 {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        assert!(writes.load(Ordering::Relaxed) >= 3);
+
+        services.shutdown().await;
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        let stopped = writes.load(Ordering::Relaxed);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        assert_eq!(writes.load(Ordering::Relaxed), stopped);
+    }
+}
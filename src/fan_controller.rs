@@ -6,15 +6,60 @@ use async_trait::async_trait;
 #[async_trait]
 pub trait FanController: Send + Sync + core::fmt::Debug {
     async fn send_init(&self) -> Result<()>;
+    async fn fan_count(&self) -> usize;
 
     async fn update_speeds(&self, temp: f32) -> Result<()>;
     async fn update_channel(&self, _channel: u8, temp: f32) -> Result<()> {
         self.update_speeds(temp).await
     }
+    /// Updates several channels of this controller in one pass, `(channel, temp)` each.
+    async fn update_channels(&self, channels: &[(u8, f32)]) -> Result<()> {
+        for &(channel, temp) in channels {
+            self.update_channel(channel, temp).await?;
+        }
+        Ok(())
+    }
+    /// Speed `channel`'s active curve asks for at `temp`, without touching the hardware.
+    async fn channel_speed(&self, channel: u8, temp: f32) -> Result<u8>;
+    /// Forgets curve speeds cached during the previous tick; drivers without a cache do
+    /// nothing.
+    async fn clear_curve_cache(&self) {}
+    /// Gives `channel` its calibrated RPM at 100%, which RPM curves are scaled by; drivers
+    /// without RPM curves ignore it.
+    async fn set_max_rpm(&self, _channel: u8, _rpm: u16) -> Result<()> {
+        Ok(())
+    }
+    /// Writes precomputed `(channel, speed)` targets in one pass.
+    async fn set_channel_speeds(&self, speeds: &[(u8, u8)]) -> Result<()>;
+    /// Writes `speed` percent to `channel` as is, bypassing curves and controller-wide rules.
+    async fn set_speed_direct(&self, channel: u8, speed: u8) -> Result<()>;
     async fn update_channel_color(&self, _channel: u8, red: u8, green: u8, blue: u8) -> Result<()>;
+    /// Sets per-segment RGB colors on `channel`. Drivers without addressable LEDs use the
+    /// first zone for the whole fan.
+    async fn update_channel_color_zones(&self, channel: u8, zones: &[[u8; 3]]) -> Result<()> {
+        let [red, green, blue] = zones.first().copied().unwrap_or_default();
+        self.update_channel_color(channel, red, green, blue).await
+    }
+    /// Reads the fan's RPM from the device now.
+    async fn channel_rpm(&self, channel: u8) -> Result<u16>;
+    /// `(speed, rpm)` of every fan as of its last write, in channel order.
+    async fn fan_stats(&self) -> Vec<(u8, u16)>;
+    /// `(speed, rpm)` of every fan read from the device now, in channel order. The default
+    /// asks for each channel's RPM in turn; drivers that can should read them in one pass.
+    async fn read_all_fans(&self) -> Result<Vec<(u8, u16)>> {
+        let mut fans = self.fan_stats().await;
+        for (idx, (_, rpm)) in fans.iter_mut().enumerate() {
+            *rpm = self.channel_rpm(idx as u8 + 1).await?;
+        }
+        Ok(fans)
+    }
+    /// Last color set on `channel` as `(red, green, blue)`.
+    async fn channel_color(&self, channel: u8) -> Result<(u8, u8, u8)>;
     async fn switch_curve(&self, channel: u8, curve: &str) -> Result<()>;
     async fn get_active_curve(&self, channel: u8) -> Result<String>;
     async fn firmware_version(&self) -> Result<(u8, u8, u8)>;
+    /// Ports whose fan currently reports RPM.
+    async fn connected_ports(&self) -> Result<Vec<u8>>;
     async fn update_curve_data(
         &self,
         channel: u8,
@@ -12,8 +12,29 @@ pub struct Point {
 #[serde(tag = "t", content = "c")]
 pub enum FanCurve {
     Constant(u8),
-    StepCurve { temps: Vec<f32>, speeds: Vec<u8> },
-    BezierCurve { points: Vec<Point> },
+    StepCurve {
+        temps: Vec<f32>,
+        speeds: Vec<u8>,
+    },
+    /// Step curve to target RPM, turned into duty through the fan's calibrated maximum.
+    RpmStepCurve {
+        temps: Vec<f32>,
+        rpms: Vec<u16>,
+    },
+    BezierCurve {
+        points: Vec<Point>,
+    },
+    /// Smooth curve through every point, clamped to the first and last point outside them.
+    CatmullRom {
+        points: Vec<Point>,
+    },
+    /// Off below `off_below`, then `ramp_start_speed` rising linearly to `max_speed` at `max_temp`.
+    ZeroRpmRamp {
+        off_below: f32,
+        ramp_start_speed: u8,
+        max_temp: f32,
+        max_speed: u8,
+    },
 }
 
 impl PartialEq for FanCurve {
@@ -22,11 +43,54 @@ impl PartialEq for FanCurve {
             (self, other),
             (Self::Constant(_), Self::Constant(_))
                 | (Self::BezierCurve { .. }, Self::BezierCurve { .. })
+                | (Self::CatmullRom { .. }, Self::CatmullRom { .. })
                 | (Self::StepCurve { .. }, Self::StepCurve { .. })
+                | (Self::RpmStepCurve { .. }, Self::RpmStepCurve { .. })
+                | (Self::ZeroRpmRamp { .. }, Self::ZeroRpmRamp { .. })
         )
     }
 }
 
+impl FanCurve {
+    /// The curve's kind and every parameter as bits, equal exactly when two curves give the
+    /// same speeds. Unlike `==`, which only compares kinds.
+    pub fn fingerprint(&self) -> Vec<u32> {
+        let points = |points: &[Point]| {
+            points
+                .iter()
+                .flat_map(|p| [p.x.to_bits(), p.y.to_bits()])
+                .collect::<Vec<_>>()
+        };
+        match self {
+            Self::Constant(speed) => vec![0, *speed as u32],
+            Self::StepCurve { temps, speeds } => [1, temps.len() as u32]
+                .into_iter()
+                .chain(temps.iter().map(|t| t.to_bits()))
+                .chain(speeds.iter().map(|&s| s as u32))
+                .collect(),
+            Self::RpmStepCurve { temps, rpms } => [5, temps.len() as u32]
+                .into_iter()
+                .chain(temps.iter().map(|t| t.to_bits()))
+                .chain(rpms.iter().map(|&r| r as u32))
+                .collect(),
+            Self::BezierCurve { points: p } => [vec![2], points(p)].concat(),
+            Self::CatmullRom { points: p } => [vec![3], points(p)].concat(),
+            Self::ZeroRpmRamp {
+                off_below,
+                ramp_start_speed,
+                max_temp,
+                max_speed,
+            } => vec![
+                4,
+                off_below.to_bits(),
+                *ramp_start_speed as u32,
+                max_temp.to_bits(),
+                *max_speed as u32,
+            ],
+        }
+    }
+}
+
 impl From<(f32, f32)> for Point {
     fn from(value: (f32, f32)) -> Self {
         Self {
@@ -40,13 +104,34 @@ impl From<&CurveCfg> for FanCurve {
     fn from(curve_cfg: &CurveCfg) -> Self {
         match curve_cfg {
             CurveCfg::Constant { id: _, speed } => FanCurve::Constant(*speed),
-            CurveCfg::StepCurve { id: _, tmps, spds } => FanCurve::StepCurve {
+            CurveCfg::StepCurve {
+                tmps, spds, rpm, ..
+            } if *rpm => FanCurve::RpmStepCurve {
                 temps: tmps.clone(),
-                speeds: spds.clone(),
+                rpms: spds.clone(),
+            },
+            CurveCfg::StepCurve { tmps, spds, .. } => FanCurve::StepCurve {
+                temps: tmps.clone(),
+                speeds: spds.iter().map(|&s| s.min(100) as u8).collect(),
             },
             CurveCfg::Bezier { id: _, points } => FanCurve::BezierCurve {
                 points: points.clone(),
             },
+            CurveCfg::CatmullRom { id: _, points } => FanCurve::CatmullRom {
+                points: points.clone(),
+            },
+            CurveCfg::ZeroRpmRamp {
+                id: _,
+                off_below,
+                ramp_start_speed,
+                max_temp,
+                max_speed,
+            } => FanCurve::ZeroRpmRamp {
+                off_below: *off_below,
+                ramp_start_speed: *ramp_start_speed,
+                max_temp: *max_temp,
+                max_speed: *max_speed,
+            },
         }
     }
 }
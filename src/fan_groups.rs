@@ -0,0 +1,114 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::{
+    config::{FanGroupCfg, FanTarget},
+    mappings::FanRef,
+};
+
+/// Followers mirroring a leader fan, keyed by follower.
+#[derive(Debug, Clone, Default)]
+pub struct FanGroups {
+    leaders: HashMap<FanRef, FanRef>,
+}
+
+fn fan_ref(target: &FanTarget) -> FanRef {
+    FanRef {
+        controller_id: target.controller as usize,
+        channel: target.fan_idx as usize,
+    }
+}
+
+impl FanGroups {
+    pub fn from_cfg(groups: &[FanGroupCfg]) -> Self {
+        Self {
+            leaders: groups
+                .iter()
+                .flat_map(|group| {
+                    let leader = fan_ref(&group.leader);
+                    group
+                        .members
+                        .iter()
+                        .map(fan_ref)
+                        .filter(move |member| *member != leader)
+                        .map(move |member| (member, leader))
+                })
+                .collect(),
+        }
+    }
+
+    /// Whether `fan` follows a leader instead of its own curve.
+    pub fn is_follower(&self, fan: &FanRef) -> bool {
+        self.leaders.contains_key(fan)
+    }
+
+    /// Gives every follower its leader's speed. Followers whose leader has no speed this
+    /// tick are left out, so they keep running at their last speed.
+    pub fn apply(&self, speeds: &mut BTreeMap<FanRef, u8>) {
+        for (member, leader) in &self.leaders {
+            match speeds.get(leader).copied() {
+                Some(speed) => speeds.insert(*member, speed),
+                None => speeds.remove(member),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use super::FanGroups;
+    use crate::{
+        config::{FanGroupCfg, FanTarget},
+        mappings::FanRef,
+    };
+
+    fn fan(controller: usize, channel: usize) -> FanRef {
+        FanRef {
+            controller_id: controller,
+            channel,
+        }
+    }
+
+    fn target(controller: u8, fan_idx: u8) -> FanTarget {
+        FanTarget {
+            controller,
+            fan_idx,
+        }
+    }
+
+    #[test]
+    fn members_mirror_leader_speed() {
+        let groups = FanGroups::from_cfg(&[FanGroupCfg {
+            leader: target(1, 1),
+            members: vec![target(1, 2), target(1, 3), target(2, 1)],
+        }]);
+        assert!(groups.is_follower(&fan(1, 2)));
+        assert!(!groups.is_follower(&fan(1, 1)));
+
+        let mut speeds = BTreeMap::from([(fan(1, 1), 65), (fan(1, 2), 20), (fan(1, 4), 30)]);
+        groups.apply(&mut speeds);
+
+        assert_eq!(
+            speeds,
+            BTreeMap::from([
+                (fan(1, 1), 65),
+                (fan(1, 2), 65),
+                (fan(1, 3), 65),
+                (fan(1, 4), 30),
+                (fan(2, 1), 65),
+            ])
+        );
+    }
+
+    #[test]
+    fn followers_wait_for_their_leader() {
+        let groups = FanGroups::from_cfg(&[FanGroupCfg {
+            leader: target(1, 1),
+            members: vec![target(1, 2)],
+        }]);
+        let mut speeds = BTreeMap::from([(fan(1, 2), 40), (fan(1, 3), 50)]);
+        groups.apply(&mut speeds);
+        assert_eq!(speeds, BTreeMap::from([(fan(1, 3), 50)]));
+    }
+}
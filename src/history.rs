@@ -0,0 +1,71 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+pub fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// Fixed-capacity per-sensor ring buffer of `(unix timestamp, temperature)` samples.
+#[derive(Debug, Default)]
+pub struct TemperatureHistory {
+    capacity: usize,
+    samples: HashMap<String, VecDeque<(u64, f32)>>,
+}
+
+impl TemperatureHistory {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            samples: HashMap::new(),
+        }
+    }
+
+    pub fn push(&mut self, sensor: &str, timestamp: u64, temp: f32) {
+        if self.capacity == 0 {
+            return;
+        }
+        let buf = self
+            .samples
+            .entry(sensor.to_string())
+            .or_insert_with(|| VecDeque::with_capacity(self.capacity));
+        if buf.len() == self.capacity {
+            buf.pop_front();
+        }
+        buf.push_back((timestamp, temp));
+    }
+
+    /// Samples for `sensor`, oldest first.
+    pub fn get(&self, sensor: &str) -> Vec<(u64, f32)> {
+        self.samples
+            .get(sensor)
+            .map(|buf| buf.iter().copied().collect())
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TemperatureHistory;
+
+    #[test]
+    fn evicts_oldest_and_keeps_order() {
+        let mut history = TemperatureHistory::new(3);
+        for (ts, temp) in [(1, 40.0), (2, 41.0), (3, 42.0), (4, 43.0)] {
+            history.push("cpu", ts, temp);
+        }
+        assert_eq!(history.get("cpu"), vec![(2, 41.0), (3, 42.0), (4, 43.0)]);
+        assert!(history.get("gpu").is_empty());
+    }
+
+    #[test]
+    fn zero_capacity_keeps_nothing() {
+        let mut history = TemperatureHistory::new(0);
+        history.push("cpu", 1, 40.0);
+        assert!(history.get("cpu").is_empty());
+    }
+}
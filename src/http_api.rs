@@ -0,0 +1,454 @@
+//! Minimal HTTP/JSON control API, for scripts that would rather not speak D-Bus.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use anyhow::Result;
+use log::{debug, warn};
+use serde::Deserialize;
+use serde_json::{Value, json};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::RwLock,
+    time::timeout,
+};
+
+use crate::{
+    config::Config,
+    controller::Controllers,
+    mappings::{FanNames, FanRef},
+    overrides::Overrides,
+};
+
+/// Largest request body accepted; every valid body is a few bytes.
+const MAX_BODY: usize = 4096;
+/// Longest request or header line accepted, in bytes.
+const MAX_LINE: usize = 8192;
+/// Most header lines accepted in one request.
+const MAX_HEADERS: usize = 64;
+/// How long a client has to send its whole request.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What the API reads and drives, shared with the rest of the daemon.
+pub struct HttpApi {
+    pub controllers: Controllers,
+    /// Latest reading of each sensor, written by the monitoring loop.
+    pub latest: Arc<RwLock<HashMap<String, f32>>>,
+    pub auto_control: Arc<AtomicBool>,
+    /// Fan control master switch; speeds can't be set while it is off.
+    pub enabled: Arc<AtomicBool>,
+    pub overrides: Arc<RwLock<Overrides>>,
+    pub names: FanNames,
+    /// Running config, replaced by the config watcher when a reload is accepted.
+    pub cfg: Arc<RwLock<Config>>,
+}
+
+#[derive(Deserialize)]
+struct SpeedRequest {
+    speed: u8,
+}
+
+/// A status code with a JSON body.
+type Reply = (u16, Value);
+
+/// A request as read off the connection.
+struct Request {
+    method: String,
+    path: String,
+    body: Vec<u8>,
+}
+
+fn error(status: u16, message: impl Into<String>) -> Reply {
+    (status, json!({ "error": message.into() }))
+}
+
+impl HttpApi {
+    /// `GET /status`: profile, auto control, latest temperatures, controller names and
+    /// every fan's last known speed and RPM.
+    async fn status(&self) -> Reply {
+        let controllers: Vec<Value> = (1..=self.controllers.fan_counts().await.len() as u8)
+            .map(|controller| {
+                json!({ "controller": controller, "name": self.names.controller(controller) })
+            })
+            .collect();
+        let fans: Vec<Value> = self
+            .controllers
+            .fan_stats()
+            .await
+            .into_iter()
+            .map(|(controller, channel, speed, rpm)| {
+                let fan = FanRef {
+                    controller_id: controller as usize,
+                    channel: channel as usize,
+                };
+                json!({
+                    "controller": controller,
+                    "channel": channel,
+                    "name": self.names.name(fan),
+                    "speed": speed,
+                    "rpm": rpm,
+                })
+            })
+            .collect();
+        let profile = self.cfg.read().await.profile.clone();
+        let status = json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "profile": profile,
+            "auto_control": self.auto_control.load(Ordering::Relaxed),
+            "enabled": self.enabled.load(Ordering::Relaxed),
+            "temperatures": *self.latest.read().await,
+            "controllers": controllers,
+            "fans": fans,
+        });
+        (200, status)
+    }
+
+    /// `POST /fan/{controller}/{channel}/speed` with `{"speed": 0-100}`: the same as
+    /// `SetFanSpeed` over D-Bus.
+    async fn set_fan_speed(&self, controller: &str, channel: &str, body: &[u8]) -> Reply {
+        let (Ok(controller), Ok(channel)) = (controller.parse::<u8>(), channel.parse::<u8>())
+        else {
+            return error(400, "controller and channel must be numbers");
+        };
+        let speed = match serde_json::from_slice::<SpeedRequest>(body) {
+            Ok(SpeedRequest { speed }) if speed <= 100 => speed,
+            Ok(SpeedRequest { speed }) => {
+                return error(400, format!("speed `{speed}` is out of range 0-100"));
+            }
+            Err(e) => return error(400, format!("invalid body: {e}")),
+        };
+        if !self.enabled.load(Ordering::Relaxed) {
+            return error(409, "fan control is disabled");
+        }
+        let fans = self.controllers.fan_count(controller).await.unwrap_or(0);
+        if channel == 0 || channel as usize > fans {
+            return error(
+                404,
+                format!("fan `{channel}` not found on controller `{controller}`"),
+            );
+        }
+        if let Err(e) = self
+            .controllers
+            .set_speed_direct(controller, channel, speed)
+            .await
+        {
+            return error(500, format!("failed to set fan speed: {e}"));
+        }
+        let fan = FanRef {
+            controller_id: controller as usize,
+            channel: channel as usize,
+        };
+        self.overrides.write().await.set_speed(fan, speed);
+        (
+            200,
+            json!({ "controller": controller, "channel": channel, "speed": speed }),
+        )
+    }
+
+    async fn route(&self, method: &str, path: &str, body: &[u8]) -> Reply {
+        let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+        match (method, segments.as_slice()) {
+            ("GET", ["status"]) => self.status().await,
+            ("GET", ["temps"]) => (200, json!(*self.latest.read().await)),
+            ("POST", ["fan", controller, channel, "speed"]) => {
+                self.set_fan_speed(controller, channel, body).await
+            }
+            (_, ["status"] | ["temps"] | ["fan", _, _, "speed"]) => {
+                error(405, format!("{method} is not allowed on {path}"))
+            }
+            _ => error(404, format!("no route for {path}")),
+        }
+    }
+
+    async fn handle(&self, stream: TcpStream) -> Result<()> {
+        let mut stream = BufReader::new(stream);
+        let (status, body) = match timeout(READ_TIMEOUT, read_request(&mut stream)).await {
+            Ok(Ok(Request { method, path, body })) => self.route(&method, &path, &body).await,
+            Ok(Err(reply)) => reply,
+            Err(_) => error(408, format!("request not received within {READ_TIMEOUT:?}")),
+        };
+        let body = body.to_string();
+        let response = format!(
+            "HTTP/1.1 {status} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+            reason(status),
+            body.len()
+        );
+        stream.get_mut().write_all(response.as_bytes()).await?;
+        stream.get_mut().shutdown().await?;
+        Ok(())
+    }
+}
+
+/// Reads the request line, the headers and a body of `Content-Length` bytes; a request
+/// that can't be read gets the error reply to send back instead.
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Request, Reply> {
+    let line = read_line(stream, 414).await?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = parts
+        .next()
+        .zip(parts.next())
+        .ok_or_else(|| error(400, format!("malformed request line `{}`", line.trim())))?;
+    let (method, path) = (method.to_string(), path.to_string());
+
+    let mut length = 0;
+    let mut headers = 0;
+    loop {
+        let header = read_line(stream, 431).await?;
+        if header.trim().is_empty() {
+            break;
+        }
+        headers += 1;
+        if headers > MAX_HEADERS {
+            return Err(error(431, format!("more than {MAX_HEADERS} headers")));
+        }
+        if let Some((name, value)) = header.split_once(':')
+            && name.trim().eq_ignore_ascii_case("content-length")
+        {
+            length = value
+                .trim()
+                .parse()
+                .map_err(|_| error(400, format!("invalid Content-Length `{}`", value.trim())))?;
+        }
+    }
+
+    if length > MAX_BODY {
+        return Err(error(413, format!("body over {MAX_BODY} bytes")));
+    }
+    let mut body = vec![0; length];
+    stream
+        .read_exact(&mut body)
+        .await
+        .map_err(|e| error(400, format!("reading body: {e}")))?;
+    Ok(Request { method, path, body })
+}
+
+/// Reads one line of at most [`MAX_LINE`] bytes, answering `too_long` past that. The end
+/// of the stream reads as an empty line.
+async fn read_line(stream: &mut BufReader<TcpStream>, too_long: u16) -> Result<String, Reply> {
+    let mut line = Vec::new();
+    (&mut *stream)
+        .take(MAX_LINE as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .await
+        .map_err(|e| error(400, format!("reading request: {e}")))?;
+    if line.len() > MAX_LINE {
+        return Err(error(too_long, format!("line over {MAX_LINE} bytes")));
+    }
+    String::from_utf8(line).map_err(|_| error(400, "request is not UTF-8"))
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        409 => "Conflict",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Answers requests on `listener` until the task is aborted, one connection per request.
+pub async fn serve(listener: TcpListener, api: Arc<HttpApi>) {
+    loop {
+        let stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                warn!("HTTP API accept error: {e}");
+                continue;
+            }
+        };
+        let api = api.clone();
+        tokio::spawn(async move {
+            if let Err(e) = api.handle(stream).await {
+                debug!("HTTP API request error: {e:#}");
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        net::SocketAddr,
+        sync::{Arc, atomic::AtomicBool},
+    };
+
+    use serde_json::{Value, json};
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::{TcpListener, TcpStream},
+        sync::RwLock,
+    };
+
+    use super::{HttpApi, MAX_HEADERS, MAX_LINE, serve};
+    use crate::{
+        config::{Config, ControllerCfg},
+        controller::{Controllers, tests::MockController},
+        mappings::FanNames,
+    };
+
+    async fn request(addr: SocketAddr, method: &str, path: &str, body: &str) -> (u16, Value) {
+        let request = format!(
+            "{method} {path} HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{body}",
+            body.len()
+        );
+        send(addr, &request).await
+    }
+
+    /// Sends `raw` as is and reads the reply.
+    async fn send(addr: SocketAddr, raw: &str) -> (u16, Value) {
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream.write_all(raw.as_bytes()).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.split_whitespace().nth(1).unwrap().parse().unwrap();
+        (status, serde_json::from_str(body).unwrap())
+    }
+
+    #[tokio::test]
+    async fn serves_status_and_sets_fan_speed() {
+        let mock = MockController::with_fans(2);
+        *mock.speeds.lock().unwrap() = vec![40, 55];
+        let speeds = mock.speeds.clone();
+        let cfg = Config::builder()
+            .profile("quiet")
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .name("Desk")
+                    .fan(1, "Front", &["Silent"])
+                    .fan(2, "Rear", &["Silent"]),
+            )
+            .build();
+        let latest = HashMap::from([(String::from("cpu"), 41.5)]);
+        let api = HttpApi {
+            controllers: Controllers::from(vec![mock.boxed()]),
+            latest: Arc::new(RwLock::new(latest)),
+            auto_control: Arc::new(AtomicBool::new(true)),
+            enabled: Arc::new(AtomicBool::new(true)),
+            overrides: Arc::default(),
+            names: FanNames::from_cfg(&cfg),
+            cfg: Arc::new(RwLock::new(cfg)),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, Arc::new(api)));
+
+        let (status, body) = request(addr, "GET", "/status", "").await;
+        assert_eq!(status, 200);
+        assert_eq!(body["profile"], "quiet");
+        assert_eq!(body["auto_control"], true);
+        assert_eq!(body["temperatures"], json!({ "cpu": 41.5 }));
+        assert_eq!(
+            body["controllers"],
+            json!([{ "controller": 1, "name": "Desk" }])
+        );
+        assert_eq!(
+            body["fans"][1],
+            json!({ "controller": 1, "channel": 2, "name": "Rear", "speed": 55, "rpm": 1100 })
+        );
+        assert_eq!(
+            request(addr, "GET", "/temps", "").await,
+            (200, json!({ "cpu": 41.5 }))
+        );
+
+        let (status, _) = request(addr, "POST", "/fan/1/2/speed", r#"{"speed": 70}"#).await;
+        assert_eq!(status, 200);
+        assert_eq!(*speeds.lock().unwrap(), [40, 70]);
+
+        let (status, body) = request(addr, "POST", "/fan/1/2/speed", r#"{"speed": 170}"#).await;
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().contains("out of range"));
+        assert_eq!(request(addr, "POST", "/fan/1/2/speed", "{").await.0, 400);
+        assert_eq!(
+            request(addr, "POST", "/fan/1/3/speed", r#"{"speed": 70}"#)
+                .await
+                .0,
+            404
+        );
+        assert_eq!(request(addr, "GET", "/fan/1/2/speed", "").await.0, 405);
+        assert_eq!(request(addr, "GET", "/nope", "").await.0, 404);
+        assert_eq!(*speeds.lock().unwrap(), [40, 70]);
+
+        server.abort();
+    }
+
+    fn empty_api() -> Arc<HttpApi> {
+        Arc::new(HttpApi {
+            controllers: Controllers::from(vec![]),
+            latest: Arc::default(),
+            auto_control: Arc::new(AtomicBool::new(true)),
+            enabled: Arc::new(AtomicBool::new(true)),
+            overrides: Arc::default(),
+            names: FanNames::default(),
+            cfg: Arc::default(),
+        })
+    }
+
+    #[tokio::test]
+    async fn broken_requests_get_an_error_reply() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, empty_api()));
+
+        // Each request stops where the server gives up on it, so no unread bytes are left
+        // to reset the connection before the reply is read.
+        let (status, body) = send(addr, "garbage\r\n").await;
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().contains("malformed"));
+        let (status, body) = send(addr, "GET /status HTTP/1.1\r\nContent-Length: x\r\n").await;
+        assert_eq!(status, 400);
+        assert!(body["error"].as_str().unwrap().contains("Content-Length"));
+        let long = "a".repeat(MAX_LINE);
+        assert_eq!(
+            send(addr, &format!("GET /{long}")[..=MAX_LINE]).await.0,
+            414
+        );
+        let header = format!("GET /status HTTP/1.1\r\nX-Long: {long}");
+        assert_eq!(
+            send(addr, &header[..header.find('X').unwrap() + MAX_LINE + 1])
+                .await
+                .0,
+            431
+        );
+        let headers = "X: 1\r\n".repeat(MAX_HEADERS + 1);
+        assert_eq!(
+            send(addr, &format!("GET /status HTTP/1.1\r\n{headers}"))
+                .await
+                .0,
+            431
+        );
+        assert_eq!(
+            send(addr, "GET /temps HTTP/1.1\r\n\r\n").await,
+            (200, json!({}))
+        );
+
+        server.abort();
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn silent_client_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let server = tokio::spawn(serve(listener, empty_api()));
+
+        let (status, body) = send(addr, "GET /status HTTP/1.1\r\n").await;
+        assert_eq!(status, 408);
+        assert!(body["error"].as_str().unwrap().contains("5s"));
+
+        server.abort();
+    }
+}
@@ -1,15 +1,64 @@
-use std::collections::HashMap;
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicU64, Ordering},
+    },
+    time::Duration,
+};
 
 use event_listener::Event;
 use log::error;
 use serde_json::from_str;
+use tokio::sync::RwLock;
 use zbus::{interface, object_server::SignalEmitter};
 
+use crate::calibration::{self, Calibration};
+use crate::config::{self, Config, ControllerCfg};
 use crate::controller::Controllers;
+use crate::diagnostics;
+use crate::explain;
+use crate::fan_control::ApplyNow;
 use crate::fan_curve::FanCurve;
+use crate::history::TemperatureHistory;
+use crate::mappings::{FanNames, FanRef};
+use crate::overrides::Overrides;
+use crate::sensor_snapshot::{SENSOR_TIMEOUT, SensorSnapshot};
+use crate::sensors::{TemperatureSensor, sensor_limits};
+use crate::services::TaskTable;
+use crate::state::DaemonState;
+use crate::temperature_sensors::external::ExternalTemperatures;
+
+/// Longest trial `TryCurve` accepts.
+const MAX_TRY_SECONDS: u32 = 600;
 
 pub struct DBusInterface {
     pub controllers: Controllers,
+    /// Running config, replaced by the config watcher when a reload is accepted.
+    pub cfg: Arc<RwLock<Config>>,
+    pub history: Arc<RwLock<TemperatureHistory>>,
+    /// Latest reading of each sensor, written by the monitoring loop.
+    pub latest: Arc<RwLock<HashMap<String, f32>>>,
+    pub config_missing: Arc<AtomicBool>,
+    /// Why the last reload of the config file failed, cleared by the next good one.
+    pub reload_error: Arc<RwLock<Option<String>>>,
+    pub external: ExternalTemperatures,
+    pub sensors: Arc<Vec<Box<dyn TemperatureSensor>>>,
+    /// Shared with the monitoring task; cleared while fans are under manual control.
+    pub auto_control: Arc<AtomicBool>,
+    /// Events the D-Bus signal forwarder missed because it fell behind.
+    pub events_lagged: Arc<AtomicU64>,
+    pub services: TaskTable,
+    pub names: FanNames,
+    pub calibration: Arc<RwLock<Calibration>>,
+    pub overrides: Arc<RwLock<Overrides>>,
+    /// Fans running a `TryCurve` trial, until their curve is switched back.
+    pub trials: Arc<RwLock<HashSet<FanRef>>>,
+    /// Fan control master switch, shared with the monitoring tasks and saved in `state`.
+    pub enabled: Arc<AtomicBool>,
+    pub state: Arc<RwLock<DaemonState>>,
+    /// Wakes the control loops for an extra tick.
+    pub apply_now: ApplyNow,
 
     // Events
     pub stop: Event,
@@ -27,6 +76,48 @@ impl DBusInterface {
         sensor_data: HashMap<String, f32>,
     ) -> zbus::Result<()>;
 
+    #[zbus(signal)]
+    pub async fn service_started(emitter: &SignalEmitter<'_>, name: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    pub async fn service_stopped(emitter: &SignalEmitter<'_>, name: String) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    pub async fn threshold_crossed(
+        emitter: &SignalEmitter<'_>,
+        sensor: String,
+        value: f64,
+        alert: String,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    pub async fn thermal_trend(
+        emitter: &SignalEmitter<'_>,
+        sensor: String,
+        trend: String,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    pub async fn color_changed(
+        emitter: &SignalEmitter<'_>,
+        applied: u32,
+        failed: u32,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    pub async fn critical_temperature(
+        emitter: &SignalEmitter<'_>,
+        sensor: String,
+        temperature: f64,
+        active: bool,
+    ) -> zbus::Result<()>;
+
+    #[zbus(signal)]
+    pub async fn controller_reconnected(
+        emitter: &SignalEmitter<'_>,
+        name: String,
+    ) -> zbus::Result<()>;
+
     async fn stop(
         &self,
         #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
@@ -42,16 +133,106 @@ impl DBusInterface {
         self.version.clone()
     }
 
+    #[zbus(property)]
+    async fn config_missing(&self) -> bool {
+        self.config_missing.load(Ordering::Relaxed)
+    }
+
+    /// Empty when the last reload succeeded.
+    #[zbus(property)]
+    async fn last_reload_error(&self) -> String {
+        self.reload_error.read().await.clone().unwrap_or_default()
+    }
+
+    #[zbus(property)]
+    async fn auto_control(&self) -> bool {
+        self.auto_control.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property)]
+    async fn enabled(&self) -> bool {
+        self.enabled.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property)]
+    async fn events_lagged(&self) -> u64 {
+        self.events_lagged.load(Ordering::Relaxed)
+    }
+
+    #[zbus(property)]
+    async fn active_profile(&self) -> String {
+        self.cfg.read().await.profile.clone()
+    }
+
+    #[zbus(property)]
+    async fn controller_count(&self) -> u32 {
+        self.cfg.read().await.controller_count() as u32
+    }
+
+    #[zbus(property)]
+    async fn fan_count(&self) -> u32 {
+        self.cfg.read().await.fan_count() as u32
+    }
+
     async fn switch_active_curve(&self, controller: u8, channel: u8, curve: String) {
-        if let Err(e) = self
+        match self
             .controllers
             .switch_curve(controller, channel, &curve)
             .await
         {
-            error!("{e}")
+            Ok(()) => self.overrides.write().await.set_curve(
+                fan_ref(controller, channel),
+                &curve,
+                self.cfg.read().await.active_curve(controller, channel),
+            ),
+            Err(e) => error!("{e}"),
         }
     }
 
+    /// Switches every fan of `controller` to `curve` and reports `(channel, switched, error)`
+    /// per fan. Nothing is switched unless every fan lists `curve` among its curves.
+    async fn set_controller_curve(
+        &self,
+        controller: u8,
+        curve: String,
+    ) -> zbus::fdo::Result<Vec<(u8, bool, String)>> {
+        let fans = self
+            .controllers
+            .fan_count(controller)
+            .await
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e}")))? as u8;
+        let cfg = self.cfg.read().await;
+        let missing: Vec<String> = (1..=fans)
+            .filter(|&channel| !cfg.fan_has_curve(controller, channel, &curve))
+            .map(|channel| self.names.label(fan_ref(controller, channel)))
+            .collect();
+        if !missing.is_empty() {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "Curve `{curve}` is not available on fan(s) {}",
+                missing.join(", ")
+            )));
+        }
+        let mut results = Vec::with_capacity(fans as usize);
+        for channel in 1..=fans {
+            match self
+                .controllers
+                .switch_curve(controller, channel, &curve)
+                .await
+            {
+                Ok(()) => {
+                    self.overrides.write().await.set_curve(
+                        fan_ref(controller, channel),
+                        &curve,
+                        cfg.active_curve(controller, channel),
+                    );
+                    results.push((channel, true, String::new()));
+                }
+                Err(e) => results.push((channel, false, e.to_string())),
+            }
+        }
+        Ok(results)
+    }
+
     async fn get_active_curve(&self, controller: u8, channel: u8) -> zbus::fdo::Result<String> {
         self.controllers
             .get_active_curve(controller, channel)
@@ -59,13 +240,197 @@ impl DBusInterface {
             .map_err(|e| zbus::fdo::Error::Failed(format!("Curve not found: {e}")))
     }
 
+    /// Switches a fan to `curve` for `seconds`, then back to the curve it was on unless it
+    /// was switched again meanwhile. The trial is not recorded as an override.
+    async fn try_curve(
+        &self,
+        controller: u8,
+        channel: u8,
+        curve: String,
+        seconds: u32,
+    ) -> zbus::fdo::Result<()> {
+        if !(1..=MAX_TRY_SECONDS).contains(&seconds) {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "Trial of {seconds}s is out of range 1-{MAX_TRY_SECONDS}"
+            )));
+        }
+        let fan = fan_ref(controller, channel);
+        if !self
+            .cfg
+            .read()
+            .await
+            .fan_has_curve(controller, channel, &curve)
+        {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "Curve `{curve}` is not available on fan {}",
+                self.names.label(fan)
+            )));
+        }
+        if !self.trials.write().await.insert(fan) {
+            return Err(zbus::fdo::Error::Failed(format!(
+                "Fan {} is already trying a curve",
+                self.names.label(fan)
+            )));
+        }
+        let revert = match self
+            .controllers
+            .try_curve(
+                controller,
+                channel,
+                &curve,
+                Duration::from_secs(seconds.into()),
+            )
+            .await
+        {
+            Ok(revert) => revert,
+            Err(e) => {
+                self.trials.write().await.remove(&fan);
+                return Err(zbus::fdo::Error::Failed(format!(
+                    "Failed to switch curve: {e}"
+                )));
+            }
+        };
+        let trials = self.trials.clone();
+        tokio::spawn(async move {
+            if let Err(e) = revert.await {
+                error!("try_curve error: {e}");
+            }
+            trials.write().await.remove(&fan);
+        });
+        Ok(())
+    }
+
     async fn get_firmware_version(&self, controller: u8) -> zbus::fdo::Result<String> {
         self.controllers
             .get_firmware_version(controller)
             .await
-            .map_err(|e| zbus::fdo::Error::Failed(format!("Curve not found: {e}")))
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Firmware not available: {e}")))
             .map(|(mj, mi, pa)| format!("{mj}.{mi}.{pa}"))
     }
+
+    /// Every temperature and every fan's `(controller, channel, rpm, speed)` in one call, for
+    /// monitors polling at a high rate. Both are empty when nothing is configured.
+    async fn poll(&self) -> PollReply {
+        let temps = self.latest.read().await.clone();
+        poll_payload(&temps, self.controllers.fan_stats().await)
+    }
+
+    /// `(controller, name)` of every controller, with the configured `name` or the generated
+    /// `TTRiingQuad<id>`.
+    async fn list_controllers(&self) -> Vec<(u8, String)> {
+        (1..=self.cfg.read().await.controller_count() as u8)
+            .map(|controller| (controller, self.names.controller(controller)))
+            .collect()
+    }
+
+    /// Why a fan runs at its speed, as JSON: the sensors mapped to it with their latest
+    /// temperature and the speed its active curve gives for each, its current speed and RPM,
+    /// and whether automatic control or a manual speed is in charge.
+    async fn explain_fan(&self, controller: u8, channel: u8) -> zbus::fdo::Result<String> {
+        let temps = self.latest.read().await.clone();
+        let explanation = explain::explain_fan(
+            &*self.cfg.read().await,
+            &self.controllers,
+            &temps,
+            &self.names,
+            self.auto_control.load(Ordering::Relaxed),
+            &*self.overrides.read().await,
+            fan_ref(controller, channel),
+        )
+        .await
+        .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e}")))?;
+        serde_json::to_string_pretty(&explanation)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to serialize explanation: {e}")))
+    }
+
+    /// Configured name of a fan, or `controller:channel` when it has none.
+    async fn get_fan_name(&self, controller: u8, channel: u8) -> String {
+        self.names.name(fan_ref(controller, channel))
+    }
+
+    /// Runs the fan at 100% for a few seconds and saves the RPM it reaches as its baseline.
+    /// Automatic control is paused meanwhile.
+    async fn calibrate_fan(&self, controller: u8, channel: u8) -> zbus::fdo::Result<u16> {
+        if !self.enabled.load(Ordering::Relaxed) {
+            return Err(zbus::fdo::Error::Failed(String::from(
+                "Fan control is disabled, see SetEnabled",
+            )));
+        }
+        let fan = fan_ref(controller, channel);
+        let rpm = calibration::calibrate(&self.controllers, &self.auto_control, fan)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Calibration failed: {e}")))?;
+        self.calibration
+            .write()
+            .await
+            .record(fan, rpm)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to save calibration: {e}")))?;
+        self.controllers
+            .set_max_rpm(controller, channel, rpm)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{e}")))?;
+        Ok(rpm)
+    }
+
+    /// Current RPM as a percentage of what the calibrated fan should reach at its speed.
+    async fn fan_health(&self, controller: u8, channel: u8) -> zbus::fdo::Result<f64> {
+        let fan = fan_ref(controller, channel);
+        let (_, _, speed, rpm) = self
+            .controllers
+            .fan_stats()
+            .await
+            .into_iter()
+            .find(|&(c, ch, _, _)| (c, ch) == (controller, channel))
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("Fan {fan} not found")))?;
+        self.calibration
+            .read()
+            .await
+            .health(fan, speed, rpm)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("{e}")))
+    }
+
+    /// Ports of `controller` whose fan reports RPM; a fan stopped by a zero-RPM curve is
+    /// left out.
+    async fn get_connected_ports(&self, controller: u8) -> zbus::fdo::Result<Vec<u8>> {
+        self.controllers
+            .connected_ports(controller)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to query ports: {e}")))
+    }
+    /// Likely mistakes in the loaded config, one sentence each; empty when there are none.
+    async fn lint(&self) -> Vec<String> {
+        self.cfg.read().await.lint()
+    }
+
+    /// Parses and validates `yaml` and compares it with the running config without applying
+    /// anything: whether a reload would apply every change without a restart, and the
+    /// top-level sections it changes.
+    async fn analyze_pending_changes(&self, yaml: &str) -> zbus::fdo::Result<(bool, Vec<String>)> {
+        let pending = config::parse(yaml)
+            .and_then(config::check)
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("Invalid config: {e:#}")))?;
+        let changed = self
+            .cfg
+            .read()
+            .await
+            .analyze_changes(&pending)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to compare configs: {e}")))?;
+        Ok((config::restart_sections(&changed).is_empty(), changed))
+    }
+
+    /// Curve `curve` as defined in the config, as JSON in the format `UpdateCurveData`
+    /// takes.
+    async fn get_curve(&self, curve: &str) -> zbus::fdo::Result<String> {
+        let cfg = self.cfg.read().await;
+        let found = cfg
+            .curves
+            .iter()
+            .find(|c| c.get_id() == curve)
+            .ok_or_else(|| zbus::fdo::Error::InvalidArgs(format!("Curve `{curve}` not found")))?;
+        serde_json::to_string(&FanCurve::from(found))
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to serialize curve: {e}")))
+    }
+
     async fn update_curve_data(
         &self,
         controller: u8,
@@ -80,4 +445,737 @@ impl DBusInterface {
             .await
             .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to update curve data: {e}")))
     }
+
+    /// Pauses (`false`) or resumes (`true`) curve-driven speed updates. Temperatures keep
+    /// being read and broadcast while paused.
+    async fn set_auto_control(
+        &self,
+        enabled: bool,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.auto_control.store(enabled, Ordering::Relaxed);
+        if enabled {
+            self.overrides.write().await.clear_speeds();
+        }
+        self.auto_control_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Turns all fan control off (`false`), leaving the fans at whatever speed the firmware
+    /// keeps, or back on. Temperatures keep being read and broadcast while off, and the
+    /// setting is saved so it holds across restarts.
+    async fn set_enabled(
+        &self,
+        enabled: bool,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        self.state
+            .write()
+            .await
+            .set_enabled(enabled)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to save state: {e:#}")))?;
+        self.enabled.store(enabled, Ordering::Relaxed);
+        self.enabled_changed(&emitter).await?;
+        Ok(())
+    }
+
+    /// Runs the control loops once now instead of at their next tick, so a curve, mapping or
+    /// temperature change shows right away. Returns without waiting for the pass.
+    async fn apply_now(&self) {
+        self.apply_now.trigger();
+    }
+
+    /// `(kind, fan, value)` for every runtime override in effect: `auto_control` while it is
+    /// off, `speed` for fans set by hand while it is off, and `curve` for fans switched away
+    /// from their configured `active_curve`.
+    async fn list_overrides(&self) -> Vec<(String, String, String)> {
+        self.overrides
+            .read()
+            .await
+            .list(self.auto_control.load(Ordering::Relaxed), &self.names)
+    }
+
+    /// Drops every runtime override: switches fans back to their configured curve and turns
+    /// automatic control back on, so the next tick sets speeds from the config again.
+    async fn clear_overrides(
+        &self,
+        #[zbus(signal_emitter)] emitter: SignalEmitter<'_>,
+    ) -> zbus::fdo::Result<()> {
+        let was_auto = self.auto_control.load(Ordering::Relaxed);
+        let cleared = self.drop_overrides().await;
+        if !was_auto {
+            self.auto_control_changed(&emitter).await?;
+        }
+        cleared
+    }
+
+    /// Sets a fan to `speed` percent, bypassing its curve. Overwritten on the next tick
+    /// unless auto control is off.
+    async fn set_fan_speed(&self, controller: u8, channel: u8, speed: u8) -> zbus::fdo::Result<()> {
+        if speed > 100 {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "Speed `{speed}` is out of range 0-100"
+            )));
+        }
+        if !self.enabled.load(Ordering::Relaxed) {
+            return Err(zbus::fdo::Error::Failed(String::from(
+                "Fan control is disabled, see SetEnabled",
+            )));
+        }
+        self.controllers
+            .set_speed_direct(controller, channel, speed)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to set fan speed: {e}")))?;
+        self.overrides
+            .write()
+            .await
+            .set_speed(fan_ref(controller, channel), speed);
+        Ok(())
+    }
+
+    /// Reads every sensor now and returns `{"temperatures": {..}, "errors": {..},
+    /// "limits": {..}}` as JSON, `limits` holding the `max` and `crit` of sensors that
+    /// report them.
+    async fn read_sensors(&self) -> zbus::fdo::Result<String> {
+        let snapshot = SensorSnapshot::read(&self.sensors, SENSOR_TIMEOUT).await;
+        let limits = sensor_limits(&self.sensors).await;
+        let mut json = serde_json::to_value(&snapshot)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to serialize sensors: {e}")))?;
+        json["limits"] = serde_json::json!(limits);
+        Ok(json.to_string())
+    }
+
+    /// Config, mappings, latest readings, fan state, firmware and service status as one JSON
+    /// document for bug reports. `redact` hides USB serial numbers.
+    async fn diagnostic_dump(&self, redact: bool) -> zbus::fdo::Result<String> {
+        let temps = self.latest.read().await.clone();
+        let dump = diagnostics::diagnostic_dump(
+            &*self.cfg.read().await,
+            &self.controllers,
+            &temps,
+            &self.services,
+            redact,
+        )
+        .await;
+        serde_json::to_string_pretty(&dump)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to serialize dump: {e}")))
+    }
+
+    /// Sets `zones` of `(red, green, blue)` spread evenly over the fan's LEDs.
+    async fn set_color_zones(
+        &self,
+        controller: u8,
+        channel: u8,
+        zones: Vec<(u8, u8, u8)>,
+    ) -> zbus::fdo::Result<()> {
+        let fans = self
+            .controllers
+            .fan_count(controller)
+            .await
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e}")))?;
+        if channel == 0 || channel as usize > fans {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "Fan `{channel}` not found on controller `{controller}`"
+            )));
+        }
+        let zones: Vec<_> = zones.into_iter().map(|(r, g, b)| [r, g, b]).collect();
+        self.controllers
+            .update_channel_color_zones(controller, channel, &zones)
+            .await
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to set color zones: {e}")))
+    }
+
+    /// Flashes a fan's LED so it can be found physically; returns before the blinking ends.
+    async fn identify_fan(&self, controller: u8, channel: u8) -> zbus::fdo::Result<()> {
+        let fans = self
+            .controllers
+            .fan_count(controller)
+            .await
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e}")))?;
+        if channel == 0 || channel as usize > fans {
+            return Err(zbus::fdo::Error::InvalidArgs(format!(
+                "Fan `{channel}` not found on controller `{controller}`"
+            )));
+        }
+        let controllers = self.controllers.clone();
+        tokio::spawn(async move {
+            if let Err(e) = controllers.identify_fan(controller, channel).await {
+                error!("identify_fan error: {e}");
+            }
+        });
+        Ok(())
+    }
+
+    async fn export_config(&self) -> zbus::fdo::Result<String> {
+        let mut cfg = self.cfg.read().await.clone();
+        for (idx, ctrl) in cfg.controllers.iter_mut().enumerate() {
+            let ControllerCfg::RiingQuad { fans, .. } = ctrl;
+            for fan in fans {
+                if let Ok(curve) = self
+                    .controllers
+                    .get_active_curve((idx + 1) as u8, fan.idx)
+                    .await
+                {
+                    fan.active_curve = curve;
+                }
+            }
+        }
+        config::to_yaml(&cfg)
+            .map_err(|e| zbus::fdo::Error::Failed(format!("Failed to export config: {e}")))
+    }
+
+    async fn get_history(&self, sensor: String) -> Vec<(u64, f64)> {
+        self.history
+            .read()
+            .await
+            .get(&sensor)
+            .into_iter()
+            .map(|(ts, temp)| (ts, temp as f64))
+            .collect()
+    }
+
+    /// `(name, status, started)` for every service, with the start time as unix seconds.
+    async fn list_services(&self) -> Vec<(String, String, u64)> {
+        self.services
+            .list_tasks()
+            .into_iter()
+            .map(|info| (info.name, info.status.to_string(), info.started))
+            .collect()
+    }
+
+    /// Stops the named service and starts a fresh task for it, e.g. to bounce the color
+    /// service without restarting the daemon.
+    async fn restart_service(&self, name: String) -> zbus::fdo::Result<()> {
+        self.services
+            .restart_service(&name)
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e}")))
+    }
+
+    async fn set_external_temperature(&self, id: String, value: f64) -> zbus::fdo::Result<()> {
+        self.external
+            .set(&id, value as f32)
+            .map_err(|e| zbus::fdo::Error::InvalidArgs(format!("{e}")))
+    }
+}
+
+impl DBusInterface {
+    /// Emits `PropertiesChanged` for every property read from the config or its file,
+    /// after [`Event::ConfigChanged`](crate::events::Event::ConfigChanged).
+    pub async fn config_changed(&self, emitter: &SignalEmitter<'_>) -> zbus::Result<()> {
+        self.active_profile_changed(emitter).await?;
+        self.controller_count_changed(emitter).await?;
+        self.fan_count_changed(emitter).await?;
+        self.config_missing_changed(emitter).await?;
+        self.last_reload_error_changed(emitter).await
+    }
+
+    /// `ClearOverrides` without the property signal.
+    async fn drop_overrides(&self) -> zbus::fdo::Result<()> {
+        self.auto_control.store(true, Ordering::Relaxed);
+        let curves = self.overrides.write().await.take_curves();
+        let cfg = self.cfg.read().await;
+        let mut failed = vec![];
+        for fan in curves.into_keys() {
+            let (controller, channel) = (fan.controller_id as u8, fan.channel as u8);
+            let Some(curve) = cfg.active_curve(controller, channel) else {
+                continue;
+            };
+            if let Err(e) = self
+                .controllers
+                .switch_curve(controller, channel, curve)
+                .await
+            {
+                failed.push(format!("{fan}: {e}"));
+            }
+        }
+        if !failed.is_empty() {
+            return Err(zbus::fdo::Error::Failed(format!(
+                "Failed to restore curves: {}",
+                failed.join(", ")
+            )));
+        }
+        Ok(())
+    }
+}
+
+fn fan_ref(controller: u8, channel: u8) -> FanRef {
+    FanRef {
+        controller_id: controller as usize,
+        channel: channel as usize,
+    }
+}
+
+/// `Poll()` reply: temperatures by sensor, and `(controller, channel, rpm, speed)` per fan.
+type PollReply = (HashMap<String, f64>, Vec<(u8, u8, u16, u8)>);
+
+/// Builds the `Poll()` reply from the latest temperatures and `(controller, channel, speed,
+/// rpm)` fan stats.
+fn poll_payload(temps: &HashMap<String, f32>, fans: Vec<(u8, u8, u8, u16)>) -> PollReply {
+    let temps = temps
+        .iter()
+        .map(|(sensor, &temp)| (sensor.clone(), temp as f64))
+        .collect();
+    let fans = fans
+        .into_iter()
+        .map(|(controller, channel, speed, rpm)| (controller, channel, rpm, speed))
+        .collect();
+    (temps, fans)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        sync::{
+            Arc,
+            atomic::{AtomicBool, AtomicU64, Ordering},
+        },
+        time::Duration,
+    };
+
+    use event_listener::Event;
+    use futures::StreamExt;
+    use tokio::sync::RwLock;
+
+    use super::{DBusInterface, PollReply, poll_payload};
+    use crate::{
+        calibration::Calibration,
+        config::{self, Config, ControllerCfg, CurveCfg},
+        controller::{Controllers, tests::MockController},
+        fan_control::ApplyNow,
+        history::TemperatureHistory,
+        mappings::FanNames,
+        services::TaskTable,
+        temperature_sensors::external::ExternalTemperatures,
+    };
+
+    /// A daemon started with no controllers, sensors or services.
+    fn empty_interface() -> DBusInterface {
+        interface(Config::default(), Controllers::from(vec![]))
+    }
+
+    fn interface(cfg: Config, controllers: Controllers) -> DBusInterface {
+        DBusInterface {
+            controllers,
+            history: Arc::new(RwLock::new(TemperatureHistory::new(cfg.history_length))),
+            latest: Arc::default(),
+            config_missing: Arc::new(AtomicBool::new(false)),
+            reload_error: Arc::default(),
+            external: ExternalTemperatures::default(),
+            sensors: Arc::new(vec![]),
+            auto_control: Arc::new(AtomicBool::new(true)),
+            events_lagged: Arc::new(AtomicU64::new(0)),
+            services: TaskTable::default(),
+            names: FanNames::from_cfg(&cfg),
+            calibration: Arc::new(RwLock::new(Calibration::default())),
+            overrides: Arc::default(),
+            trials: Arc::default(),
+            enabled: Arc::new(AtomicBool::new(true)),
+            state: Arc::default(),
+            apply_now: ApplyNow::default(),
+            stop: Event::new(),
+            version: cfg.version.to_string(),
+            cfg: Arc::new(RwLock::new(cfg)),
+        }
+    }
+
+    #[tokio::test]
+    async fn status_methods_are_empty_without_hardware() {
+        let iface = empty_interface();
+
+        assert_eq!(iface.controller_count().await, 0);
+        assert_eq!(iface.fan_count().await, 0);
+        assert_eq!(iface.poll().await, (HashMap::new(), vec![]));
+        assert!(iface.list_services().await.is_empty());
+        assert!(iface.get_history(String::from("cpu")).await.is_empty());
+        assert_eq!(
+            iface.read_sensors().await.unwrap(),
+            r#"{"errors":{},"limits":{},"temperatures":{}}"#
+        );
+        let dump: serde_json::Value =
+            serde_json::from_str(&iface.diagnostic_dump(false).await.unwrap()).unwrap();
+        assert_eq!(dump["fans"], serde_json::json!([]));
+        assert_eq!(dump["controllers"], serde_json::json!([]));
+        assert_eq!(dump["tasks"], serde_json::json!([]));
+        assert!(
+            iface
+                .export_config()
+                .await
+                .unwrap()
+                .contains("controllers: []")
+        );
+
+        // Per-fan queries name the missing fan instead of panicking.
+        assert!(iface.get_connected_ports(1).await.is_err());
+        assert!(iface.fan_health(1, 1).await.is_err());
+        assert!(iface.identify_fan(1, 1).await.is_err());
+        assert_eq!(iface.get_fan_name(1, 1).await, "1:1");
+    }
+
+    #[tokio::test]
+    async fn color_zones_need_an_existing_fan() {
+        let mock = MockController::with_fans(2);
+        let colors = mock.colors.clone();
+        let iface = interface(Config::default(), Controllers::from(vec![mock.boxed()]));
+
+        for channel in [0, 3] {
+            let err = iface
+                .set_color_zones(1, channel, vec![(255, 0, 0)])
+                .await
+                .unwrap_err();
+            assert_eq!(
+                err,
+                zbus::fdo::Error::InvalidArgs(format!(
+                    "Fan `{channel}` not found on controller `1`"
+                ))
+            );
+        }
+        iface
+            .set_color_zones(1, 2, vec![(255, 0, 0), (0, 0, 255)])
+            .await
+            .unwrap();
+        assert_eq!(
+            *colors.lock().unwrap(),
+            [vec![(0, 0, 0)], vec![(0, 0, 0), (255, 0, 0)]]
+        );
+    }
+
+    #[tokio::test]
+    async fn overrides_are_listed_and_cleared() {
+        let mock = MockController::with_fans(2);
+        let (speeds, curves) = (mock.speeds.clone(), mock.curves.clone());
+        let cfg = Config::builder()
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "Front", &["Silent", "Loud"])
+                    .fan(2, "Rear", &["Silent", "Loud"]),
+            )
+            .build();
+        let iface = interface(cfg, Controllers::from(vec![mock.boxed()]));
+        assert!(iface.list_overrides().await.is_empty());
+
+        // What SetAutoControl(false) does, minus the property signal.
+        iface.auto_control.store(false, Ordering::Relaxed);
+        iface.set_fan_speed(1, 1, 80).await.unwrap();
+        iface.switch_active_curve(1, 2, String::from("Loud")).await;
+        iface
+            .switch_active_curve(1, 1, String::from("Silent"))
+            .await;
+        let listed = |kind: &str, fan: &str, value: &str| {
+            (String::from(kind), String::from(fan), String::from(value))
+        };
+        assert_eq!(
+            iface.list_overrides().await,
+            [
+                listed("auto_control", "", "off"),
+                listed("speed", "Front (1:1)", "80"),
+                listed("curve", "Rear (1:2)", "Loud"),
+            ]
+        );
+        assert_eq!(speeds.lock().unwrap()[0], 80);
+
+        iface.drop_overrides().await.unwrap();
+        assert!(iface.list_overrides().await.is_empty());
+        assert!(iface.auto_control.load(Ordering::Relaxed));
+        assert_eq!(curves.lock().unwrap()[&2], "Silent");
+        assert_eq!(iface.get_active_curve(1, 2).await.unwrap(), "Silent");
+
+        // A disabled daemon leaves the fans alone, calibration included.
+        iface.enabled.store(false, Ordering::Relaxed);
+        assert!(iface.set_fan_speed(1, 1, 50).await.is_err());
+        assert!(iface.calibrate_fan(1, 1).await.is_err());
+        assert_eq!(speeds.lock().unwrap()[0], 80);
+    }
+
+    #[tokio::test]
+    async fn controller_curve_switches_every_fan_or_none() {
+        let cfg = Config::builder()
+            .curve(CurveCfg::constant("Silent", 30))
+            .curve(CurveCfg::constant("Loud", 90))
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "Front", &["Silent", "Loud"])
+                    .fan(2, "Rear", &["Silent"])
+                    .fan(3, "Top", &["Silent", "Loud"]),
+            )
+            .build();
+        let mock = MockController::with_fans(3);
+        let curves = mock.curves.clone();
+        let iface = interface(cfg, Controllers::from(vec![mock.boxed()]));
+
+        let err = iface
+            .set_controller_curve(1, String::from("Loud"))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Rear (1:2)"), "{err}");
+        assert!(curves.lock().unwrap().is_empty());
+
+        assert_eq!(
+            iface
+                .set_controller_curve(1, String::from("Silent"))
+                .await
+                .unwrap(),
+            [
+                (1, true, String::new()),
+                (2, true, String::new()),
+                (3, true, String::new())
+            ]
+        );
+        let switched = curves.lock().unwrap().clone();
+        assert_eq!(
+            switched,
+            HashMap::from([1, 2, 3].map(|channel| (channel, String::from("Silent"))))
+        );
+        assert!(
+            iface
+                .set_controller_curve(2, String::from("Silent"))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn tried_curve_runs_once_per_fan_and_reverts() {
+        let cfg = Config::builder()
+            .curve(CurveCfg::constant("Silent", 30))
+            .curve(CurveCfg::constant("Loud", 90))
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "Front", &["Silent", "Loud"])
+                    .fan(2, "Rear", &["Silent"]),
+            )
+            .build();
+        let mock = MockController::with_fans(2);
+        let curves = mock.curves.clone();
+        curves.lock().unwrap().insert(1, String::from("Silent"));
+        let iface = interface(cfg, Controllers::from(vec![mock.boxed()]));
+        let loud = || String::from("Loud");
+
+        assert!(iface.try_curve(1, 1, loud(), 0).await.is_err());
+        assert!(iface.try_curve(1, 1, loud(), 601).await.is_err());
+        let err = iface.try_curve(1, 2, loud(), 10).await.unwrap_err();
+        assert!(err.to_string().contains("Rear (1:2)"), "{err}");
+
+        iface.try_curve(1, 1, loud(), 10).await.unwrap();
+        assert_eq!(curves.lock().unwrap()[&1], "Loud");
+        let err = iface.try_curve(1, 1, loud(), 10).await.unwrap_err();
+        assert!(err.to_string().contains("already trying"), "{err}");
+
+        tokio::time::sleep(Duration::from_secs(11)).await;
+        assert_eq!(curves.lock().unwrap()[&1], "Silent");
+        assert!(iface.trials.read().await.is_empty());
+        assert!(iface.list_overrides().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn curve_json_round_trips_through_update() {
+        let cfg = Config::builder()
+            .curve(CurveCfg::StepCurve {
+                id: String::from("Steps"),
+                tmps: vec![30.0, 60.0],
+                spds: vec![20, 80],
+                rpm: false,
+            })
+            .controller(ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B).fan(
+                1,
+                "Front",
+                &["Steps"],
+            ))
+            .build();
+        let mock = MockController::with_fans(1);
+        let stored = mock.curve_data.clone();
+        let iface = interface(cfg, Controllers::from(vec![mock.boxed()]));
+
+        let json = iface.get_curve("Steps").await.unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&json).unwrap(),
+            serde_json::json!({ "t": "StepCurve", "c": { "temps": [30.0, 60.0], "speeds": [20, 80] } })
+        );
+        iface.update_curve_data(1, 1, "Steps", &json).await.unwrap();
+        let curve = stored.lock().unwrap()[&(1, String::from("Steps"))].clone();
+        assert_eq!(serde_json::to_string(&curve).unwrap(), json);
+
+        let err = iface.get_curve("Nope").await.unwrap_err();
+        assert!(err.to_string().contains("`Nope` not found"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn pending_changes_are_classified_without_applying() {
+        let cfg = Config::builder()
+            .curve(CurveCfg::step("Steps", &[30.0, 60.0], &[20, 80]))
+            .controller(ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B).fan(
+                1,
+                "Front",
+                &["Steps"],
+            ))
+            .build();
+        let iface = interface(cfg.clone(), Controllers::from(vec![]));
+        let analyze = |cfg: &Config| {
+            let yaml = config::to_yaml(cfg).unwrap();
+            let iface = &iface;
+            async move { iface.analyze_pending_changes(&yaml).await.unwrap() }
+        };
+
+        assert_eq!(analyze(&cfg).await, (true, vec![]));
+
+        let mut curve_only = cfg.clone();
+        curve_only.curves[0] = CurveCfg::step("Steps", &[30.0, 60.0], &[30, 90]);
+        assert_eq!(
+            analyze(&curve_only).await,
+            (true, vec![String::from("curves")])
+        );
+
+        let mut hardware = cfg.clone();
+        hardware
+            .controllers
+            .push(ControllerCfg::riing_quad("quad-2", 0x264A, 0x232C).into());
+        assert_eq!(
+            analyze(&hardware).await,
+            (false, vec![String::from("controllers")])
+        );
+        assert_eq!(iface.cfg.read().await.controllers.len(), 1);
+
+        let err = iface
+            .analyze_pending_changes("version: 1\nprofile: [broken\n")
+            .await
+            .unwrap_err();
+        assert!(err.to_string().contains("Invalid config"), "{err}");
+    }
+
+    #[tokio::test]
+    async fn controller_names_reach_status_output() {
+        let cfg = Config::builder()
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .name("Desk front")
+                    .fan(1, "Front", &["Silent"]),
+            )
+            .controller(ControllerCfg::riing_quad("quad-2", 0x264A, 0x232C))
+            .build();
+        let controllers = Controllers::from(vec![
+            MockController::with_fans(1).boxed(),
+            MockController::with_fans(1).boxed(),
+        ]);
+        let iface = interface(cfg, controllers);
+
+        assert_eq!(
+            iface.list_controllers().await,
+            [
+                (1, String::from("Desk front")),
+                (2, String::from("TTRiingQuadquad-2"))
+            ]
+        );
+        let dump: serde_json::Value =
+            serde_json::from_str(&iface.diagnostic_dump(false).await.unwrap()).unwrap();
+        assert_eq!(dump["controllers"][0]["name"], "Desk front");
+        assert_eq!(dump["controllers"][1]["name"], "TTRiingQuadquad-2");
+    }
+
+    #[tokio::test]
+    async fn poll_reports_every_fan_and_sensor() {
+        let mock = MockController::with_fans(2);
+        *mock.speeds.lock().unwrap() = vec![40, 55];
+        let controllers =
+            Controllers::from(vec![mock.boxed(), MockController::with_fans(1).boxed()]);
+        let temps = HashMap::from([(String::from("cpu"), 41.5), (String::from("gpu"), 60.0)]);
+
+        let (reported, fans) = poll_payload(&temps, controllers.fan_stats().await);
+        assert_eq!(
+            reported,
+            HashMap::from([(String::from("cpu"), 41.5), (String::from("gpu"), 60.0)])
+        );
+        assert_eq!(fans, vec![(1, 1, 800, 40), (1, 2, 1100, 55), (2, 1, 0, 0)]);
+    }
+
+    /// `ActiveProfile`, `ControllerCount` and `FanCount` follow a reload, and `PropertiesChanged`
+    /// is emitted for them. Skipped without a session bus.
+    #[tokio::test]
+    async fn properties_follow_a_reloaded_config() {
+        let iface = empty_interface();
+        let live = iface.cfg.clone();
+        let reloaded = |profile: &str| {
+            Config::builder()
+                .profile(profile)
+                .controller(
+                    ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                        .fan(1, "Front", &["Silent"])
+                        .fan(2, "Rear", &["Silent"]),
+                )
+                .build()
+        };
+        *live.write().await = reloaded("quiet");
+        assert_eq!(iface.active_profile().await, "quiet");
+        assert_eq!(iface.controller_count().await, 1);
+        assert_eq!(iface.fan_count().await, 2);
+
+        if std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+            return;
+        }
+        let server = zbus::connection::Builder::session()
+            .unwrap()
+            .serve_at("/io/github/tt_riingd", iface)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = zbus::Connection::session().await.unwrap();
+        let properties = zbus::fdo::PropertiesProxy::builder(&client)
+            .destination(server.unique_name().unwrap().to_owned())
+            .unwrap()
+            .path("/io/github/tt_riingd")
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let mut changes = properties.receive_properties_changed().await.unwrap();
+
+        *live.write().await = reloaded("loud");
+        let served = server
+            .object_server()
+            .interface::<_, DBusInterface>("/io/github/tt_riingd")
+            .await
+            .unwrap();
+        served
+            .get()
+            .await
+            .config_changed(served.signal_emitter())
+            .await
+            .unwrap();
+        let change = changes.next().await.unwrap();
+        let args = change.args().unwrap();
+        let profile = args.changed_properties().get("ActiveProfile").unwrap();
+        assert_eq!(<&str>::try_from(profile).unwrap(), "loud");
+    }
+
+    #[tokio::test]
+    async fn poll_answers_over_the_session_bus() {
+        if std::env::var_os("DBUS_SESSION_BUS_ADDRESS").is_none() {
+            return;
+        }
+        let mock = MockController::with_fans(1);
+        *mock.speeds.lock().unwrap() = vec![40];
+        let iface = interface(Config::default(), Controllers::from(vec![mock.boxed()]));
+        *iface.latest.write().await = HashMap::from([(String::from("cpu"), 41.5)]);
+        // Served under the connection's unique name only, so a running daemon is left alone.
+        let server = zbus::connection::Builder::session()
+            .unwrap()
+            .serve_at("/io/github/tt_riingd", iface)
+            .unwrap()
+            .build()
+            .await
+            .unwrap();
+        let client = zbus::Connection::session().await.unwrap();
+        let proxy = zbus::Proxy::new(
+            &client,
+            server.unique_name().unwrap().to_owned(),
+            "/io/github/tt_riingd",
+            "io.github.tt_riingd1",
+        )
+        .await
+        .unwrap();
+
+        let (temps, fans): PollReply = proxy.call("Poll", &()).await.unwrap();
+        assert_eq!(temps, HashMap::from([(String::from("cpu"), 41.5)]));
+        assert_eq!(fans, vec![(1, 1, 800, 40)]);
+    }
 }
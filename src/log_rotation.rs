@@ -0,0 +1,118 @@
+use std::{
+    ffi::OsString,
+    fs::{self, OpenOptions},
+    io,
+    path::PathBuf,
+    time::Duration,
+};
+
+use log::warn;
+use tokio::time::interval;
+
+/// Where the daemon's stdout and stderr go.
+pub const LOG_FILE: &str = "/var/tmp/tt_riingd.log";
+
+/// How often [`rotate_periodically`] checks the size of the log.
+const CHECK_EVERY: Duration = Duration::from_secs(60);
+
+/// Size-based rotation of the daemon's log file: once it reaches `max_bytes` it becomes
+/// `<path>.1`, older copies move up to `<path>.<keep>` and the oldest is dropped. stdout and
+/// stderr stay open on the file in append mode, so it is copied and truncated in place
+/// rather than renamed.
+#[derive(Debug, Clone)]
+pub struct LogRotation {
+    path: PathBuf,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl LogRotation {
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64, keep: usize) -> Self {
+        Self {
+            path: path.into(),
+            max_bytes,
+            keep,
+        }
+    }
+
+    fn rotated(&self, n: usize) -> PathBuf {
+        let mut name = OsString::from(self.path.as_os_str());
+        name.push(format!(".{n}"));
+        name.into()
+    }
+
+    /// Whether the file has reached `max_bytes`. A missing file never has.
+    pub fn needs_rotation(&self) -> bool {
+        fs::metadata(&self.path).is_ok_and(|meta| meta.len() >= self.max_bytes)
+    }
+
+    /// Shifts the rotated copies, copies the file to `<path>.1` and empties it. With `keep`
+    /// 0 the file is only emptied.
+    pub fn rotate(&self) -> io::Result<()> {
+        if self.keep > 0 {
+            for n in (1..self.keep).rev() {
+                let from = self.rotated(n);
+                if from.exists() {
+                    fs::rename(from, self.rotated(n + 1))?;
+                }
+            }
+            fs::copy(&self.path, self.rotated(1))?;
+        }
+        OpenOptions::new().write(true).open(&self.path)?.set_len(0)
+    }
+
+    /// Rotates when [`Self::needs_rotation`], returning whether it did.
+    pub fn rotate_if_needed(&self) -> io::Result<bool> {
+        if !self.needs_rotation() {
+            return Ok(false);
+        }
+        self.rotate().map(|_| true)
+    }
+}
+
+/// Checks the log once a minute, starting right away, and rotates it when it is full.
+pub async fn rotate_periodically(rotation: LogRotation) {
+    let mut check = interval(CHECK_EVERY);
+    loop {
+        check.tick().await;
+        if let Err(e) = rotation.rotate_if_needed() {
+            warn!("Failed to rotate {}: {e}", rotation.path.display());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::LogRotation;
+
+    #[test]
+    fn rotation_keeps_the_newest_copies() {
+        let dir = env::temp_dir().join(format!("tt_riingd_logs_{}", process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("daemon.log");
+        let rotation = LogRotation::new(&path, 4, 2);
+        let read = |name: &str| fs::read_to_string(dir.join(name)).ok();
+
+        assert!(!rotation.rotate_if_needed().unwrap());
+        fs::write(&path, "abc").unwrap();
+        assert!(!rotation.rotate_if_needed().unwrap());
+
+        for line in ["first", "second", "third"] {
+            fs::write(&path, line).unwrap();
+            assert!(rotation.rotate_if_needed().unwrap());
+        }
+        assert_eq!(read("daemon.log").as_deref(), Some(""));
+        assert_eq!(read("daemon.log.1").as_deref(), Some("third"));
+        assert_eq!(read("daemon.log.2").as_deref(), Some("second"));
+        assert_eq!(read("daemon.log.3"), None);
+
+        fs::write(&path, "fourth").unwrap();
+        LogRotation::new(&path, 4, 0).rotate().unwrap();
+        assert_eq!(read("daemon.log").as_deref(), Some(""));
+        assert_eq!(read("daemon.log.1").as_deref(), Some("third"));
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
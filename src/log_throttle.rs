@@ -0,0 +1,119 @@
+use std::{
+    collections::HashMap,
+    fmt::Display,
+    time::{Duration, Instant},
+};
+
+use log::error;
+
+/// Minimum time between two logs of the same error source.
+pub const THROTTLE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[derive(Debug)]
+struct Source {
+    last_logged: Instant,
+    suppressed: u32,
+}
+
+/// Rate limits repeated errors per source: the first one is logged, later ones at most once
+/// per interval together with the number suppressed in between.
+#[derive(Debug)]
+pub struct LogThrottle {
+    interval: Duration,
+    sources: HashMap<String, Source>,
+}
+
+impl Default for LogThrottle {
+    fn default() -> Self {
+        Self::new(THROTTLE_INTERVAL)
+    }
+}
+
+impl LogThrottle {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            sources: HashMap::new(),
+        }
+    }
+
+    pub fn error(&mut self, source: &str, msg: impl Display) {
+        self.report(source, Instant::now(), msg, |line| error!("{line}"));
+    }
+
+    /// Forgets `source`, so its next failure is logged right away.
+    pub fn clear(&mut self, source: &str) {
+        self.sources.remove(source);
+    }
+
+    fn report(&mut self, source: &str, now: Instant, msg: impl Display, log: impl FnOnce(String)) {
+        let Some(state) = self.sources.get_mut(source) else {
+            self.sources.insert(
+                source.to_string(),
+                Source {
+                    last_logged: now,
+                    suppressed: 0,
+                },
+            );
+            return log(format!("{source}: {msg}"));
+        };
+        if now.duration_since(state.last_logged) < self.interval {
+            state.suppressed += 1;
+            return;
+        }
+        let suppressed = std::mem::take(&mut state.suppressed);
+        state.last_logged = now;
+        if suppressed > 0 {
+            log(format!(
+                "{source}: {msg} ({suppressed} similar errors suppressed)"
+            ));
+        } else {
+            log(format!("{source}: {msg}"));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::LogThrottle;
+
+    #[test]
+    fn repeated_failures_are_logged_at_throttled_rate() {
+        let mut throttle = LogThrottle::new(Duration::from_secs(60));
+        let start = Instant::now();
+        let mut lines = vec![];
+
+        // One failure every 2 s tick for 3 minutes, from two sources.
+        for tick in 0..90 {
+            let now = start + Duration::from_secs(tick * 2);
+            throttle.report("sensor cpu", now, "read failed", |l| lines.push(l));
+            if tick < 3 {
+                throttle.report("controller 1", now, "write failed", |l| lines.push(l));
+            }
+        }
+
+        assert_eq!(
+            lines,
+            [
+                "sensor cpu: read failed",
+                "controller 1: write failed",
+                "sensor cpu: read failed (29 similar errors suppressed)",
+                "sensor cpu: read failed (29 similar errors suppressed)",
+            ]
+        );
+    }
+
+    #[test]
+    fn cleared_source_logs_immediately() {
+        let mut throttle = LogThrottle::new(Duration::from_secs(60));
+        let now = Instant::now();
+        let mut count = 0;
+        throttle.report("fan", now, "err", |_| count += 1);
+        throttle.report("fan", now, "err", |_| count += 1);
+        throttle.clear("fan");
+        throttle.report("fan", now, "err", |_| count += 1);
+        assert_eq!(count, 2);
+    }
+}
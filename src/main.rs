@@ -1,42 +1,104 @@
+mod app_context;
+#[cfg(feature = "dbus")]
+mod broadcast;
+mod calibration;
 mod cli;
+mod colors;
 mod config;
+mod config_builder;
+mod config_watcher;
 mod controller;
+mod curve_bands;
+#[cfg(feature = "dbus")]
+mod diagnostics;
 mod drivers;
+mod events;
+#[cfg(feature = "dbus")]
+mod explain;
+mod fan_control;
 mod fan_controller;
 mod fan_curve;
+mod fan_groups;
+mod history;
+mod http_api;
+#[cfg(feature = "dbus")]
 mod interface;
+mod log_rotation;
+mod log_throttle;
 mod mappings;
+mod overrides;
+mod schedules;
+mod sensor_snapshot;
 mod sensors;
+mod services;
+mod shutdown;
+mod state;
 mod temperature_sensors;
+mod thresholds;
+mod trend;
+mod watchdog;
 
-use std::{collections::HashMap, fs::File, path::PathBuf, sync::Arc, time::Duration};
+use std::{
+    collections::HashMap,
+    fs::OpenOptions,
+    io,
+    path::PathBuf,
+    process::ExitCode,
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
 
-use anyhow::{Result, anyhow};
+use anyhow::{Context, Result, anyhow};
+pub use app_context::AppContext;
+#[cfg(feature = "dbus")]
+use broadcast::ChangeFilter;
+use calibration::Calibration;
 use clap::Parser;
-use config::ColorCfg;
+use colors::ColorApplier;
+use config::Config;
+use config_watcher::{ConfigReloader, ConfigWatcher, FileEvents, FileSource, WatchEvent};
 use daemonize::Daemonize;
-use event_listener::Listener;
-use log::{LevelFilter, error, info};
-use mappings::{ColorMapping, Mapping};
+#[cfg(feature = "dbus")]
+use events::Subscriber;
+use events::{Event, EventBus};
+use fan_control::{FanRules, Readings};
+use history::TemperatureHistory;
+use http_api::HttpApi;
+use log::{LevelFilter, error, info, warn};
+use log_rotation::{LOG_FILE, LogRotation};
 use once_cell::sync::Lazy;
-use sensors::TemperatureSensor;
+use overrides::Overrides;
+use sensors::SensorRegistry;
+use services::{Services, spawn_dedicated};
+use shutdown::{SERVICE_CHECK, ShutdownReason};
+use state::DaemonState;
+#[cfg(feature = "dbus")]
+use std::sync::atomic::AtomicU64;
 use syslog::{BasicLogger, Facility, Formatter3164};
-use temperature_sensors::lm_sensor;
-use tokio::{sync::RwLock, task::JoinHandle, time::interval};
+use temperature_sensors::{
+    drive::{DriveFactory, SYSFS_ROOT},
+    hwmon::{HWMON_ROOT, HwmonFactory},
+    lm_sensor::LmSensorFactory,
+};
+use thresholds::{CriticalAlerts, Thresholds};
+use tokio::{
+    net::TcpListener,
+    sync::{RwLock, watch},
+    task::JoinHandle,
+    time::interval,
+};
 use tokio_stream::{StreamExt, wrappers::IntervalStream};
+use trend::Trends;
+use watchdog::Heartbeat;
+#[cfg(feature = "dbus")]
 use zbus::connection;
 
+#[cfg(feature = "dbus")]
 use interface::{DBusInterface, DBusInterfaceSignals};
 
-pub struct AppContext {
-    pub cfg: config::Config,
-    pub controllers: controller::Controllers,
-    pub sensors: Vec<Box<dyn TemperatureSensor>>,
-    pub mapping: Arc<Mapping>,
-    pub colors: Arc<Vec<ColorCfg>>,
-    pub color_mappings: Arc<ColorMapping>,
-}
-
 pub struct LMSensorsRef(pub lm_sensors::LMSensors);
 
 unsafe impl Sync for LMSensorsRef {}
@@ -66,7 +128,11 @@ fn init_log() -> Result<()> {
 }
 
 fn into_daemon() -> Result<()> {
-    File::create("/var/tmp/tt_riingd.log")
+    // Appended to, so the log survives restarts; `log-rotation` keeps its size in check.
+    OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(LOG_FILE)
         .and_then(|out| Ok((out.try_clone()?, out)))
         .map_err(|e| anyhow!("{e}"))
         .and_then(|(stderr, stdout)| {
@@ -78,55 +144,12 @@ fn into_daemon() -> Result<()> {
         })
 }
 
-fn spawn_monitoring_task(
-    sensors_data: Arc<RwLock<HashMap<String, f32>>>,
-    tick_seconds: u64,
-    controllers: controller::Controllers,
-    sensors: Vec<Box<dyn TemperatureSensor>>,
-    mapping: Arc<Mapping>,
-) -> JoinHandle<()> {
-    tokio::spawn({
-        let mut interval_stream = IntervalStream::new(interval(Duration::from_secs(tick_seconds)));
-        async move {
-            while interval_stream.next().await.is_some() {
-                for sensor in &sensors {
-                    let temp = sensor.read_temperature().await;
-
-                    match temp {
-                        Ok(t) => {
-                            let Some(name) = sensor.sensor_name().await else {
-                                continue;
-                            };
-                            sensors_data.write().await.insert(name.clone(), t);
-                            #[cfg(debug_assertions)]
-                            {
-                                info!("Temperature of {name}: {t}°C");
-                            }
-                            for fan in mapping.fans_for_sensor(&name) {
-                                if let Err(e) = controllers
-                                    .update_channel(fan.controller_id as u8, fan.channel as u8, t)
-                                    .await
-                                {
-                                    error!("update_channel error: {e}");
-                                }
-                            }
-                        }
-                        Err(e) => error!("Temperature read error: {e}"),
-                    }
-                }
-                #[cfg(debug_assertions)]
-                {
-                    info!("[timer] tick");
-                }
-            }
-        }
-    })
-}
-
+#[cfg(feature = "dbus")]
 fn spawn_broadcast_task(
     connection: zbus::Connection,
     sensors_data: Arc<RwLock<HashMap<String, f32>>>,
     broadcast_tick: u64,
+    mut changes: ChangeFilter,
 ) -> JoinHandle<()> {
     #[cfg(debug_assertions)]
     {
@@ -136,7 +159,6 @@ fn spawn_broadcast_task(
     tokio::spawn({
         let mut interval_stream =
             IntervalStream::new(interval(Duration::from_secs(broadcast_tick)));
-        let mut cache: HashMap<String, f32> = HashMap::new();
         async move {
             while interval_stream.next().await.is_some() {
                 if let Ok(interface) = connection
@@ -145,17 +167,11 @@ fn spawn_broadcast_task(
                     .await
                 {
                     let snapshot = sensors_data.read().await.clone();
-                    if (!(snapshot
-                        .iter()
-                        .any(|(s, t)| (t - cache.get(s).unwrap_or(t)).abs() >= 0.2))
-                        && !cache.is_empty())
-                        || snapshot.is_empty()
-                    {
+                    if !changes.should_publish(&snapshot) {
                         continue;
                     }
 
-                    let _ = interface.temperature_changed(snapshot.clone()).await;
-                    cache = snapshot;
+                    let _ = interface.temperature_changed(snapshot).await;
                 } else {
                     error!("Failed to get object server interface");
                     continue;
@@ -169,134 +185,442 @@ fn spawn_broadcast_task(
     })
 }
 
+/// Re-emits bus events as D-Bus signals.
+#[cfg(feature = "dbus")]
+fn spawn_event_forwarder(connection: zbus::Connection, mut events: Subscriber) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let Ok(interface) = connection
+            .object_server()
+            .interface::<_, DBusInterface>("/io/github/tt_riingd")
+            .await
+        else {
+            error!("Failed to get object server interface");
+            return;
+        };
+        loop {
+            let event = events.recv().await;
+            let emitter = interface.signal_emitter();
+            let ret = match event {
+                Event::ServiceStarted { name } => {
+                    DBusInterface::service_started(emitter, name).await
+                }
+                Event::ServiceStopped { name } => {
+                    DBusInterface::service_stopped(emitter, name).await
+                }
+                Event::ControllerReconnected { name } => {
+                    DBusInterface::controller_reconnected(emitter, name).await
+                }
+                Event::ThresholdCrossed {
+                    sensor,
+                    value,
+                    alert,
+                } => DBusInterface::threshold_crossed(emitter, sensor, value as f64, alert).await,
+                Event::ThermalTrend { sensor, trend } => {
+                    DBusInterface::thermal_trend(emitter, sensor, trend.to_string()).await
+                }
+                Event::ColorChanged { applied, failed } => {
+                    DBusInterface::color_changed(emitter, applied as u32, failed as u32).await
+                }
+                Event::CriticalTemperature {
+                    sensor,
+                    temperature,
+                    active,
+                } => {
+                    DBusInterface::critical_temperature(emitter, sensor, temperature as f64, active)
+                        .await
+                }
+                Event::ConfigChanged => interface.get().await.config_changed(emitter).await,
+            };
+            if let Err(e) = ret {
+                error!("Failed to emit D-Bus signal: {e}");
+            }
+        }
+    })
+}
+
+/// Re-applies the colors every 3 s, and right away when a critical alert starts or ends or
+/// a reloaded config arrives through `config`.
 fn spawn_color_task(
-    controllers: controller::Controllers,
-    color_map: Arc<ColorMapping>,
-    colors: Arc<Vec<ColorCfg>>,
+    mut colors: ColorApplier,
+    mut config: watch::Receiver<Arc<Config>>,
 ) -> JoinHandle<()> {
     tokio::spawn({
         let mut interval_stream = IntervalStream::new(interval(Duration::from_secs(3)));
+        let mut events = colors.subscribe();
         async move {
-            while interval_stream.next().await.is_some() {
-                let map: Vec<_> = color_map
-                    .iter()
-                    .filter_map(|entry| {
-                        colors
-                            .iter()
-                            .find(|&c| c.color == *entry.key())
-                            .map(|finded| (finded, entry.value().clone()))
-                    })
-                    .collect();
-                for (cfg, fans) in map {
-                    for fan in fans {
-                        let ret = controllers
-                            .update_channel_color(
-                                fan.controller_id as u8,
-                                fan.channel as u8,
-                                cfg.rgb[0],
-                                cfg.rgb[1],
-                                cfg.rgb[2],
-                            )
-                            .await;
-                        if let Err(e) = ret {
-                            error!("update_channel_color error: {e}");
+            loop {
+                tokio::select! {
+                    tick = interval_stream.next() => if tick.is_none() { break },
+                    event = events.recv() => if !colors.observe(&event) { continue },
+                    Ok(()) = config.changed() => colors.reload(&config.borrow_and_update()),
+                }
+                colors.apply().await;
+            }
+        }
+    })
+}
+
+/// Reloads the config when its file changes and keeps `live` and the status flags current,
+/// publishing [`Event::ConfigChanged`] after each change.
+fn spawn_config_watcher_task(
+    path: PathBuf,
+    mut reloader: ConfigReloader,
+    live: Arc<RwLock<Config>>,
+    config_missing: Arc<AtomicBool>,
+    reload_error: Arc<RwLock<Option<String>>>,
+    bus: EventBus,
+    tick_seconds: u64,
+) -> JoinHandle<()> {
+    tokio::spawn({
+        let mut interval_stream = IntervalStream::new(interval(Duration::from_secs(tick_seconds)));
+        let mut file_events = FileEvents::new(&path);
+        let mut watcher = ConfigWatcher::new(path);
+        async move {
+            loop {
+                tokio::select! {
+                    tick = interval_stream.next() => if tick.is_none() { break },
+                    () = file_events.changed() => {}
+                }
+                let Some(event) = watcher.poll() else {
+                    continue;
+                };
+                config_missing.store(watcher.is_missing(), Ordering::Relaxed);
+                match event {
+                    WatchEvent::Missing => warn!(
+                        "Config {} was removed, keeping the loaded configuration",
+                        watcher.path().display()
+                    ),
+                    WatchEvent::Recreated | WatchEvent::Modified => {
+                        let before = reloader.config().clone();
+                        match reloader.reload() {
+                            Ok(()) => {
+                                let cfg = reloader.config();
+                                info!("Config (profile {}) reloaded", cfg.profile);
+                                let changed = before.analyze_changes(cfg).unwrap_or_default();
+                                let restart = config::restart_sections(&changed);
+                                if !restart.is_empty() {
+                                    warn!(
+                                        "Changes to {} take effect after a restart",
+                                        restart.join(", ")
+                                    );
+                                }
+                                *live.write().await = cfg.clone();
+                            }
+                            Err(e) => error!("Config on disk is invalid: {e:#}"),
                         }
+                        *reload_error.write().await = reloader.last_error().map(str::to_string);
                     }
                 }
+                bus.publish(Event::ConfigChanged);
             }
         }
     })
 }
 
-async fn init_context(config_path: Option<PathBuf>) -> Result<AppContext> {
-    let config = config::load(config_path)?;
-    let controllers = controller::Controllers::init_from_cfg(&config)?;
-    let sensors = lm_sensor::LmSensorSource::discover(&LMSENSORS.0, &config.sensors)?;
-
-    #[cfg(debug_assertions)]
-    {
-        info!("Loaded {} temperature sensors", sensors.len());
+/// `piped` is the config already read from stdin for `--config -`.
+async fn init_context(cli: &cli::Cli, piped: Option<Config>) -> Result<AppContext> {
+    let config_path = config::resolve(cli.config.clone())?;
+    let mut config = match piped {
+        Some(config) => config,
+        None => config::load(&config_path)?,
+    };
+    cli.apply_overrides(&mut config);
+    if config.trace_hid {
+        log::set_max_level(LevelFilter::Debug);
     }
-
-    let mapping = Arc::new(Mapping::load_mappings(&config.mappings));
-    let colors = Arc::new(config.colors.clone());
-    let color_mappings = Arc::new(ColorMapping::build_color_mapping(&config.color_mappings));
-
-    Ok(AppContext {
-        cfg: config,
-        controllers,
-        sensors,
-        mapping,
-        colors,
-        color_mappings,
+    let mut registry = SensorRegistry::default();
+    registry.register("lm-sensors", LmSensorFactory(&LMSENSORS.0));
+    registry.register(
+        "hwmon",
+        HwmonFactory {
+            root: PathBuf::from(HWMON_ROOT),
+        },
+    );
+    registry.register(
+        "drive",
+        DriveFactory {
+            root: PathBuf::from(SYSFS_ROOT),
+        },
+    );
+    // Opening the controllers waits out late USB enumeration, so keep it off the workers.
+    tokio::task::spawn_blocking(move || {
+        AppContext::new(
+            config,
+            config_path,
+            registry,
+            controller::Controllers::init_from_cfg,
+        )
     })
+    .await?
 }
 
 #[tokio::main]
-async fn tokio_main(config_path: Option<PathBuf>) -> Result<()> {
+async fn tokio_main(cli: cli::Cli, piped: Option<Config>) -> Result<ShutdownReason> {
     #[cfg(feature = "tokio-console")]
     {
         console_subscriber::init();
     }
     let AppContext {
         cfg,
+        config_path,
         controllers,
         sensors,
+        sensor_kinds,
+        external,
         mapping,
         colors,
         color_mappings,
-    } = init_context(config_path).await?;
+        bus,
+        cancel,
+    } = init_context(&cli, piped).await?;
+    // Only the D-Bus interface can push external sensor values.
+    #[cfg(not(feature = "dbus"))]
+    let _ = external;
 
     // First set
-    controllers.send_init().await?;
-
-    let stop = event_listener::Event::new();
-    let stop_listener = stop.listen();
+    fan_control::initialize(&cfg, &controllers)
+        .await
+        .context("Failed to initialize hardware controllers")?;
 
-    let conn = connection::Builder::session()?
-        .name("io.github.tt_riingd")?
-        .serve_at(
-            "/io/github/tt_riingd",
-            DBusInterface {
-                controllers: controllers.clone(),
-                stop,
-                version: cfg.version.to_string(),
-            },
-        )?
-        .build()
-        .await?;
-
-    let _color = spawn_color_task(controllers.clone(), color_mappings.clone(), colors.clone());
+    let state = DaemonState::load(
+        cfg.state_file
+            .clone()
+            .unwrap_or_else(|| DaemonState::default_path(&config_path)),
+    );
+    let calibration = Calibration::load(
+        cfg.calibration_file
+            .clone()
+            .unwrap_or_else(|| Calibration::default_path(&config_path)),
+    );
+    calibration.apply_to(&controllers).await;
+    let mut rules = FanRules::from_cfg(&cfg);
+    rules.enabled.store(state.enabled(), Ordering::Relaxed);
+    rules.critical = fan_control::critical_temperatures(&sensors).await;
+    for (sensor, crit) in &rules.critical {
+        info!(
+            "Sensor {sensor} reports a critical limit of {crit}°C; its fans run at full speed from there"
+        );
+    }
+    fan_control::apply_startup(&cfg, &controllers, &sensors, &mapping, &mut rules).await;
 
+    let history = Arc::new(RwLock::new(TemperatureHistory::new(cfg.history_length)));
     let sensors_data = Arc::new(RwLock::new(HashMap::new()));
-    let _timer = spawn_monitoring_task(
-        sensors_data.clone(),
-        cfg.tick_seconds as u64,
-        controllers,
-        sensors,
-        mapping,
-    );
+    let config_missing = Arc::new(AtomicBool::new(false));
+    let reload_error = Arc::new(RwLock::new(None));
+    let overrides = Arc::new(RwLock::new(Overrides::default()));
+    let live_cfg = Arc::new(RwLock::new(cfg.clone()));
 
-    let _broadcast = if cfg.enable_broadcast {
-        Some(spawn_broadcast_task(
-            conn.clone(),
-            sensors_data.clone(),
-            cfg.broadcast_interval as u64,
-        ))
-    } else {
-        None
+    let mut services = Services::new(bus.clone(), cancel);
+    #[cfg(feature = "dbus")]
+    let events_lagged = Arc::new(AtomicU64::new(0));
+    #[cfg(feature = "dbus")]
+    let (conn, stop_listener) = {
+        let stop = event_listener::Event::new();
+        let stop_listener = stop.listen();
+        let conn = connection::Builder::session()?
+            .name("io.github.tt_riingd")?
+            .serve_at(
+                "/io/github/tt_riingd",
+                DBusInterface {
+                    controllers: controllers.clone(),
+                    cfg: live_cfg.clone(),
+                    history: history.clone(),
+                    latest: sensors_data.clone(),
+                    config_missing: config_missing.clone(),
+                    reload_error: reload_error.clone(),
+                    external,
+                    sensors: sensors.clone(),
+                    auto_control: rules.auto_control.clone(),
+                    events_lagged: events_lagged.clone(),
+                    services: services.table(),
+                    names: rules.names.clone(),
+                    calibration: Arc::new(RwLock::new(calibration)),
+                    overrides: overrides.clone(),
+                    trials: Arc::default(),
+                    enabled: rules.enabled.clone(),
+                    state: Arc::new(RwLock::new(state)),
+                    apply_now: rules.apply_now.clone(),
+                    stop,
+                    version: cfg.version.to_string(),
+                },
+            )?
+            .build()
+            .await?;
+        (conn, stop_listener)
     };
 
-    stop_listener.wait();
+    // The connection itself serves `RestartService`, so only the signal forwarder is a service.
+    #[cfg(feature = "dbus")]
+    {
+        let (conn, bus) = (conn.clone(), bus.clone());
+        services.start_restartable("dbus-signals", move || {
+            spawn_event_forwarder(conn.clone(), bus.subscriber_counting(events_lagged.clone()))
+        });
+    }
+
+    // Every config the reloader accepts goes to the fan loops and the color service.
+    let reloader = ConfigReloader::new(cfg.clone(), FileSource::new(&config_path))
+        .with_sensor_kinds(sensor_kinds);
+    let updates = reloader.subscribe();
+    // A config piped in on stdin has no file to watch.
+    if !config::is_stdin(&config_path) {
+        services.start(
+            "config-watcher",
+            spawn_config_watcher_task(
+                config_path.clone(),
+                reloader,
+                live_cfg.clone(),
+                config_missing,
+                reload_error,
+                bus.clone(),
+                cfg.tick_seconds as u64,
+            ),
+        );
+    }
+
+    // Started even without colors, so a reload can add some.
+    let color_applier = ColorApplier::new(
+        controllers.clone(),
+        color_mappings.clone(),
+        colors.clone(),
+        cfg.default_color.clone(),
+        cfg.color_apply_mode,
+        bus.clone(),
+    )
+    .with_alert_color(cfg.alert_color.clone());
+    {
+        let updates = updates.clone();
+        services.start_restartable("color", move || {
+            spawn_color_task(color_applier.clone(), updates.clone())
+        });
+    }
+
+    if cfg.log_max_kb > 0 {
+        let rotation = LogRotation::new(LOG_FILE, cfg.log_max_kb * 1024, cfg.log_keep);
+        services.start_restartable("log-rotation", move || {
+            tokio::spawn(log_rotation::rotate_periodically(rotation.clone()))
+        });
+    }
+
+    if let Some(http) = &cfg.http_api {
+        let listener = TcpListener::bind(&http.bind)
+            .await
+            .with_context(|| format!("Failed to bind HTTP API to {}", http.bind))?;
+        info!("HTTP API listening on {}", http.bind);
+        let api = HttpApi {
+            controllers: controllers.clone(),
+            latest: sensors_data.clone(),
+            auto_control: rules.auto_control.clone(),
+            enabled: rules.enabled.clone(),
+            overrides: overrides.clone(),
+            names: rules.names.clone(),
+            cfg: live_cfg.clone(),
+        };
+        services.start(
+            "http-api",
+            tokio::spawn(http_api::serve(listener, Arc::new(api))),
+        );
+    }
+
+    let mut readings = Some(Readings {
+        latest: sensors_data.clone(),
+        history,
+        thresholds: Thresholds::new(&cfg.thresholds, bus.clone()),
+        critical: CriticalAlerts::new(rules.critical.clone(), bus.clone()),
+        trends: cfg.trend.map(|trend| Trends::new(trend, bus.clone())),
+    });
+    let mut heartbeats = vec![];
+    let mut fan_loops = vec![];
+    for (tick, rules) in rules.split_by_tick(&cfg) {
+        // The global tick records readings; controllers with their own tick get extra loops.
+        let (name, readings) = if tick == cfg.tick_seconds {
+            (String::from("monitoring"), readings.take())
+        } else {
+            (format!("monitoring-{tick}s"), None)
+        };
+        let heartbeat = Heartbeat::default();
+        heartbeats.push((name.clone(), heartbeat.clone()));
+        fan_loops.push(name.clone());
+        let task = fan_control::control_loop(
+            Duration::from_secs(tick as u64),
+            controllers.clone(),
+            sensors.clone(),
+            updates.clone(),
+            rules,
+            readings,
+            heartbeat,
+        );
+        let handle = if cfg.dedicated_monitoring_thread {
+            spawn_dedicated(&name, task)
+                .with_context(|| format!("Failed to start {name} thread"))?
+        } else {
+            tokio::spawn(task)
+        };
+        services.start(name, handle);
+    }
+    if let Some(timeout) = cfg.watchdog_timeout {
+        let (controllers, panic_speed) = (controllers.clone(), cfg.panic_speed);
+        services.start_restartable("watchdog", move || {
+            tokio::spawn(watchdog::watchdog(
+                controllers.clone(),
+                heartbeats.clone(),
+                Duration::from_secs(timeout),
+                panic_speed,
+            ))
+        });
+    }
+
+    #[cfg(feature = "dbus")]
+    if cfg.enable_broadcast {
+        let (conn, sensors_data) = (conn.clone(), sensors_data.clone());
+        let (interval, min_change) = (cfg.broadcast_interval as u64, cfg.broadcast_min_change);
+        services.start_restartable("broadcast", move || {
+            spawn_broadcast_task(
+                conn.clone(),
+                sensors_data.clone(),
+                interval,
+                ChangeFilter::new(min_change),
+            )
+        });
+    }
+
+    #[cfg(feature = "dbus")]
+    let stop_requested = stop_listener;
+    // Without D-Bus there is no `Stop()` method, so only signals end the daemon.
+    #[cfg(not(feature = "dbus"))]
+    let stop_requested = std::future::pending::<()>();
+    let signal = shutdown::on_signal()?;
+    let table = services.table();
+    let reason = tokio::select! {
+        _ = signal => ShutdownReason::Signal,
+        () = stop_requested => ShutdownReason::DbusRequest,
+        name = table.first_finished(&fan_loops, SERVICE_CHECK) => ShutdownReason::ServiceFailed(name),
+    };
+    info!("Stopping: {reason}");
+    services.shutdown().await;
     info!("Stopped");
 
-    Ok(())
+    Ok(reason)
 }
 
-fn main() -> Result<()> {
+/// Exits with the [`ShutdownReason`]'s code; any error is a [`ShutdownReason::FatalError`].
+fn main() -> ExitCode {
     let cli = cli::Cli::parse();
-
-    into_daemon()
-        .and_then(|_| init_log())
-        .and_then(|_| tokio_main(cli.config))
+    // Daemonizing points stdin at /dev/null, so a piped config is read first.
+    let reason = cli
+        .config
+        .as_deref()
+        .filter(|path| config::is_stdin(path))
+        .map(|_| config::read(io::stdin().lock(), "stdin"))
+        .transpose()
+        .and_then(|piped| {
+            into_daemon()
+                .and_then(|_| init_log())
+                .and_then(|_| tokio_main(cli, piped))
+        })
+        .unwrap_or_else(|e| {
+            eprintln!("Error: {e:?}");
+            ShutdownReason::FatalError
+        });
+    ExitCode::from(reason.exit_code())
 }
@@ -1,15 +1,90 @@
+use std::{collections::HashMap, fmt, sync::Arc};
+
 use dashmap::{DashMap, DashSet};
+use log::warn;
 
-use crate::config::{ColorMappingCfg, MappingCfg};
+use crate::config::{ColorMappingCfg, Config, ControllerCfg, MappingCfg};
 
 pub type SensorKey = String;
 
-#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq, PartialOrd, Ord)]
 pub struct FanRef {
     pub controller_id: usize,
     pub channel: usize,
 }
 
+impl fmt::Display for FanRef {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.controller_id, self.channel)
+    }
+}
+
+/// Configured fan and controller names, so logs and status output can say "CPU left"
+/// instead of `1:2`.
+#[derive(Default, Debug, Clone)]
+pub struct FanNames {
+    fans: Arc<HashMap<FanRef, String>>,
+    /// Indexed by controller id - 1.
+    controllers: Arc<Vec<String>>,
+}
+
+impl FanNames {
+    pub fn from_cfg(cfg: &Config) -> Self {
+        let names = cfg
+            .controllers
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, ctrl)| {
+                let ControllerCfg::RiingQuad { fans, .. } = ctrl;
+                fans.iter()
+                    .filter(|fan| !fan.name.is_empty())
+                    .map(move |fan| {
+                        let fan_ref = FanRef {
+                            controller_id: idx + 1,
+                            channel: fan.idx as usize,
+                        };
+                        (fan_ref, fan.name.clone())
+                    })
+            })
+            .collect();
+        Self {
+            fans: Arc::new(names),
+            controllers: Arc::new(cfg.controllers.iter().map(ControllerCfg::name).collect()),
+        }
+    }
+
+    /// Name of a controller by its 1-based id, or `controller <id>` if it isn't configured.
+    pub fn controller(&self, controller: u8) -> String {
+        (controller as usize)
+            .checked_sub(1)
+            .and_then(|idx| self.controllers.get(idx))
+            .cloned()
+            .unwrap_or_else(|| format!("controller {controller}"))
+    }
+
+    /// `name (id)` of a controller for log lines.
+    pub fn controller_label(&self, controller: u8) -> String {
+        format!("{} ({controller})", self.controller(controller))
+    }
+
+    /// The fan's configured name, or `controller:channel` when it has none.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn name(&self, fan: FanRef) -> String {
+        self.fans
+            .get(&fan)
+            .cloned()
+            .unwrap_or_else(|| fan.to_string())
+    }
+
+    /// `name (controller:channel)` for log lines, or just `controller:channel`.
+    pub fn label(&self, fan: FanRef) -> String {
+        match self.fans.get(&fan) {
+            Some(name) => format!("{name} ({fan})"),
+            None => fan.to_string(),
+        }
+    }
+}
+
 #[derive(Default, Debug)]
 pub struct Mapping {
     fans2sensor: DashMap<FanRef, SensorKey>,
@@ -22,27 +97,42 @@ pub struct ColorMapping {
 }
 
 impl ColorMapping {
+    /// Groups the fans of `color_cfg` by color. A fan mapped to several colors keeps the one
+    /// of the first entry that lists it; later ones are skipped with a warning.
     pub fn build_color_mapping(color_cfg: &[ColorMappingCfg]) -> Self {
-        color_cfg
-            .iter()
-            .flat_map(|c| {
-                let ckey = c.color.clone();
-                c.targets.iter().map(move |t| (ckey.clone(), t))
-            })
-            .fold(Self::default(), |acc, (sensor, target)| {
+        let mut claimed: HashMap<FanRef, &str> = HashMap::new();
+        let mapping = Self::default();
+        for entry in color_cfg {
+            for target in &entry.targets {
                 let fan = FanRef {
                     controller_id: target.controller as usize,
                     channel: target.fan_idx as usize,
                 };
-
-                acc.color2fans.entry(sensor).or_default().insert(fan);
-                acc
-            })
+                let first = *claimed.entry(fan).or_insert(&entry.color);
+                if first != entry.color {
+                    warn!(
+                        "Fan {fan} is mapped to colors `{first}` and `{}`; keeping `{first}`",
+                        entry.color
+                    );
+                    continue;
+                }
+                mapping
+                    .color2fans
+                    .entry(entry.color.clone())
+                    .or_default()
+                    .insert(fan);
+            }
+        }
+        mapping
     }
 
     pub fn iter(&self) -> dashmap::iter::Iter<String, DashSet<FanRef>> {
         self.color2fans.iter()
     }
+
+    pub fn is_empty(&self) -> bool {
+        self.color2fans.is_empty()
+    }
 }
 
 impl Mapping {
@@ -92,3 +182,68 @@ impl Mapping {
             .flat_map(|set| set.iter().map(|r| *r).collect::<Vec<_>>())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorMapping, FanNames, FanRef};
+    use crate::config::{Config, ControllerCfg};
+
+    #[test]
+    fn names_fall_back_to_indices() {
+        let cfg = Config::builder()
+            .controller(
+                ControllerCfg::riing_quad("quad-1", 0x264A, 0x232B)
+                    .fan(1, "CPU left", &["Silent"])
+                    .fan(2, "", &["Silent"]),
+            )
+            .build();
+        let names = FanNames::from_cfg(&cfg);
+        let fan = |channel| FanRef {
+            controller_id: 1,
+            channel,
+        };
+
+        assert_eq!(names.name(fan(1)), "CPU left");
+        assert_eq!(names.label(fan(1)), "CPU left (1:1)");
+        assert_eq!(names.name(fan(2)), "1:2");
+        assert_eq!(names.name(fan(3)), "1:3");
+        assert_eq!(names.label(fan(3)), "1:3");
+    }
+
+    #[test]
+    fn overlapping_color_mappings_keep_the_first_color() {
+        let mut cfg = Config::builder()
+            .color("red", [255, 0, 0])
+            .color("blue", [0, 0, 255])
+            .color_mapping("red", &[(1, 1), (1, 2)])
+            .color_mapping("blue", &[(1, 2), (1, 3)])
+            .color_mapping("red", &[(1, 2)])
+            .build();
+        cfg.validate().unwrap();
+
+        let mapping = ColorMapping::build_color_mapping(&cfg.color_mappings);
+        let mut colors: Vec<(String, Vec<usize>)> = mapping
+            .iter()
+            .map(|entry| {
+                let mut channels: Vec<_> = entry.value().iter().map(|fan| fan.channel).collect();
+                channels.sort();
+                (entry.key().clone(), channels)
+            })
+            .collect();
+        colors.sort();
+        assert_eq!(
+            colors,
+            [
+                (String::from("blue"), vec![3]),
+                (String::from("red"), vec![1, 2])
+            ]
+        );
+
+        cfg.strict_color_mappings = true;
+        let err = cfg.validate().unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "color_mappings: fan 1:2 is mapped to both `red` and `blue`"
+        );
+    }
+}
@@ -0,0 +1,70 @@
+use std::collections::BTreeMap;
+
+use crate::mappings::{FanNames, FanRef};
+
+/// Changes made at runtime over D-Bus or the HTTP API that take fans away from what the
+/// config says. Turning automatic control off is tracked by the shared `auto_control` flag.
+#[derive(Debug, Default)]
+pub struct Overrides {
+    /// Speeds set with `SetFanSpeed` or `POST /fan/..`; they only hold while automatic control is off.
+    speeds: BTreeMap<FanRef, u8>,
+    /// Curves switched to with `SwitchActiveCurve`.
+    curves: BTreeMap<FanRef, String>,
+}
+
+impl Overrides {
+    pub fn set_speed(&mut self, fan: FanRef, speed: u8) {
+        self.speeds.insert(fan, speed);
+    }
+
+    /// Speed the fan was set to by hand, if any.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn speed(&self, fan: FanRef) -> Option<u8> {
+        self.speeds.get(&fan).copied()
+    }
+
+    /// Records `curve` as the fan's curve, or forgets the override when it is the one the
+    /// config starts the fan on.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn set_curve(&mut self, fan: FanRef, curve: &str, configured: Option<&str>) {
+        if configured == Some(curve) {
+            self.curves.remove(&fan);
+        } else {
+            self.curves.insert(fan, curve.to_string());
+        }
+    }
+
+    /// Drops the manual speeds, which the next tick overwrites once automatic control is on.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn clear_speeds(&mut self) {
+        self.speeds.clear();
+    }
+
+    /// `(kind, fan, value)` for every override in effect: `auto_control` when it is off,
+    /// then `speed` and `curve` per fan.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn list(&self, auto_control: bool, names: &FanNames) -> Vec<(String, String, String)> {
+        let mut list = Vec::new();
+        if !auto_control {
+            list.push(("auto_control".into(), String::new(), "off".into()));
+            list.extend(
+                self.speeds
+                    .iter()
+                    .map(|(&fan, speed)| ("speed".into(), names.label(fan), speed.to_string())),
+            );
+        }
+        list.extend(
+            self.curves
+                .iter()
+                .map(|(&fan, curve)| ("curve".into(), names.label(fan), curve.clone())),
+        );
+        list
+    }
+
+    /// Forgets every override and returns the fans whose curve has to be switched back.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn take_curves(&mut self) -> BTreeMap<FanRef, String> {
+        self.speeds.clear();
+        std::mem::take(&mut self.curves)
+    }
+}
@@ -0,0 +1,156 @@
+use std::{fmt::Debug, sync::Arc};
+
+use chrono::{Local, Timelike};
+
+use crate::{config::Config, fan_curve::FanCurve};
+
+/// Source of the current time of day for [`Schedules`].
+pub trait Clock: Send + Sync + Debug {
+    /// Minutes since midnight, 0-1439.
+    fn minute_of_day(&self) -> u16;
+}
+
+/// The system's local time: the `TZ` variable when set, `/etc/localtime` otherwise. Windows
+/// are wall-clock times, so they move with daylight saving changes; the zone is looked up on
+/// every call, so a changed `TZ` database is picked up without a restart.
+#[derive(Debug)]
+pub struct LocalClock;
+
+impl Clock for LocalClock {
+    fn minute_of_day(&self) -> u16 {
+        let now = Local::now();
+        (now.hour() * 60 + now.minute()) as u16
+    }
+}
+
+/// Always reports the same time of day.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct FixedClock(pub u16);
+
+#[cfg(test)]
+impl Clock for FixedClock {
+    fn minute_of_day(&self) -> u16 {
+        self.0
+    }
+}
+
+/// A configured `schedules` entry.
+#[derive(Debug, Clone)]
+pub struct QuietWindow {
+    from: u16,
+    to: u16,
+    pub max_speed: Option<u8>,
+    pub curve: Option<FanCurve>,
+}
+
+impl QuietWindow {
+    fn contains(&self, minute: u16) -> bool {
+        if self.from < self.to {
+            (self.from..self.to).contains(&minute)
+        } else {
+            minute >= self.from || minute < self.to
+        }
+    }
+}
+
+/// Time-of-day windows that cap or override fan speeds, checked on every tick.
+#[derive(Debug, Clone)]
+pub struct Schedules {
+    windows: Vec<QuietWindow>,
+    clock: Arc<dyn Clock>,
+}
+
+impl Schedules {
+    /// Builds the windows that pass [`Config::validate`], read against [`LocalClock`].
+    pub fn from_cfg(cfg: &Config) -> Self {
+        let windows = cfg
+            .schedules
+            .iter()
+            .filter_map(|schedule| {
+                let (from, to) = schedule.minutes().ok()?;
+                let curve = match &schedule.curve {
+                    Some(id) => Some(FanCurve::from(
+                        cfg.curves.iter().find(|c| &c.get_id() == id)?,
+                    )),
+                    None => None,
+                };
+                Some(QuietWindow {
+                    from,
+                    to,
+                    max_speed: schedule.max_speed,
+                    curve,
+                })
+            })
+            .collect();
+        Self {
+            windows,
+            clock: Arc::new(LocalClock),
+        }
+    }
+
+    /// Windows of a reloaded config, read against the same clock.
+    pub fn reloaded(&self, cfg: &Config) -> Self {
+        Self {
+            clock: self.clock.clone(),
+            ..Self::from_cfg(cfg)
+        }
+    }
+
+    #[cfg(test)]
+    pub fn with_clock(self, clock: impl Clock + 'static) -> Self {
+        Self {
+            clock: Arc::new(clock),
+            ..self
+        }
+    }
+
+    /// The first window the current time of day falls in.
+    pub fn active(&self) -> Option<&QuietWindow> {
+        if self.windows.is_empty() {
+            return None;
+        }
+        let minute = self.clock.minute_of_day();
+        self.windows.iter().find(|w| w.contains(minute))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FixedClock, Schedules};
+    use crate::config::{Config, ScheduleCfg};
+
+    fn schedules(from: &str, to: &str, minute: u16) -> Schedules {
+        let cfg = Config::builder()
+            .schedule(ScheduleCfg {
+                from: from.to_string(),
+                to: to.to_string(),
+                max_speed: Some(30),
+                curve: None,
+            })
+            .build();
+        cfg.validate().unwrap();
+        Schedules::from_cfg(&cfg).with_clock(FixedClock(minute))
+    }
+
+    #[test]
+    fn windows_wrap_past_midnight() {
+        assert!(schedules("22:00", "07:00", 23 * 60).active().is_some());
+        assert!(schedules("22:00", "07:00", 6 * 60 + 59).active().is_some());
+        assert!(schedules("22:00", "07:00", 7 * 60).active().is_none());
+        assert!(schedules("22:00", "07:00", 12 * 60).active().is_none());
+        assert!(schedules("12:00", "13:30", 13 * 60).active().is_some());
+        assert!(schedules("12:00", "13:30", 11 * 60 + 59).active().is_none());
+
+        let bad = Config::builder()
+            .schedule(ScheduleCfg {
+                from: "25:00".to_string(),
+                to: "07:00".to_string(),
+                max_speed: Some(30),
+                curve: None,
+            })
+            .build();
+        let msg = format!("{:#}", bad.validate().unwrap_err());
+        assert!(msg.contains("`25:00` is not a time of day"), "{msg}");
+    }
+}
@@ -0,0 +1,97 @@
+use std::{collections::BTreeMap, time::Duration};
+
+use futures::future::join_all;
+use serde::Serialize;
+
+use crate::sensors::TemperatureSensor;
+
+/// How long a single sensor read may take before it is reported as failed.
+pub const SENSOR_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Result of reading every sensor once: values by sensor name, and the error of each sensor
+/// that failed or timed out.
+#[derive(Debug, Default, Clone, PartialEq, Serialize)]
+pub struct SensorSnapshot {
+    pub temperatures: BTreeMap<String, f32>,
+    pub errors: BTreeMap<String, String>,
+}
+
+impl SensorSnapshot {
+    /// Reads all `sensors` concurrently, each bounded by `timeout`. Sensors without a name
+    /// are keyed by their position.
+    pub async fn read(sensors: &[Box<dyn TemperatureSensor>], timeout: Duration) -> Self {
+        let reads = sensors.iter().enumerate().map(|(idx, sensor)| async move {
+            let name = sensor
+                .sensor_name()
+                .await
+                .unwrap_or_else(|| format!("sensor{idx}"));
+            let temp = match tokio::time::timeout(timeout, sensor.read_temperature()).await {
+                Ok(res) => res.map_err(|e| e.to_string()),
+                Err(_) => Err(format!("timed out after {} ms", timeout.as_millis())),
+            };
+            (name, temp)
+        });
+        join_all(reads)
+            .await
+            .into_iter()
+            .fold(Self::default(), |mut snapshot, (name, temp)| {
+                match temp {
+                    Ok(t) => _ = snapshot.temperatures.insert(name, t),
+                    Err(e) => _ = snapshot.errors.insert(name, e),
+                }
+                snapshot
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use anyhow::{Result, anyhow};
+    use async_trait::async_trait;
+
+    use super::SensorSnapshot;
+    use crate::sensors::TemperatureSensor;
+
+    enum MockSensor {
+        Ok(&'static str, f32),
+        Failing(&'static str),
+        Hanging(&'static str),
+    }
+
+    #[async_trait]
+    impl TemperatureSensor for MockSensor {
+        async fn read_temperature(&self) -> Result<f32> {
+            match self {
+                MockSensor::Ok(_, t) => Ok(*t),
+                MockSensor::Failing(_) => Err(anyhow!("chip not found")),
+                MockSensor::Hanging(_) => std::future::pending().await,
+            }
+        }
+        async fn sensor_name(&self) -> Option<String> {
+            match self {
+                MockSensor::Ok(name, _) | MockSensor::Failing(name) | MockSensor::Hanging(name) => {
+                    Some(name.to_string())
+                }
+            }
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn snapshot_separates_values_and_failures() {
+        let sensors: Vec<Box<dyn TemperatureSensor>> = vec![
+            Box::new(MockSensor::Ok("cpu", 55.5)),
+            Box::new(MockSensor::Failing("gpu")),
+            Box::new(MockSensor::Hanging("nvme")),
+            Box::new(MockSensor::Ok("water", 31.0)),
+        ];
+        let snapshot = SensorSnapshot::read(&sensors, Duration::from_millis(500)).await;
+
+        assert_eq!(snapshot.temperatures.len(), 2);
+        assert_eq!(snapshot.temperatures["cpu"], 55.5);
+        assert_eq!(snapshot.temperatures["water"], 31.0);
+        assert_eq!(snapshot.errors["gpu"], "chip not found");
+        assert_eq!(snapshot.errors["nvme"], "timed out after 500 ms");
+    }
+}
@@ -1,5 +1,17 @@
-use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use anyhow::{Result, anyhow};
 use async_trait::async_trait;
+use serde::Serialize;
+
+use crate::config::SensorCfg;
+
+/// Thresholds a sensor's hardware reports, in °C; `None` where it reports none.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Serialize)]
+pub struct SensorLimits {
+    pub max: Option<f32>,
+    pub crit: Option<f32>,
+}
 
 #[async_trait]
 pub trait TemperatureSensor: Send + Sync {
@@ -7,4 +19,152 @@ pub trait TemperatureSensor: Send + Sync {
     async fn sensor_name(&self) -> Option<String> {
         None
     }
+    /// The hardware's own thresholds; none for sensors that have no such notion.
+    async fn limits(&self) -> SensorLimits {
+        SensorLimits::default()
+    }
+}
+
+/// Limits of every sensor that reports at least one, keyed like
+/// [`crate::sensor_snapshot::SensorSnapshot`].
+pub async fn sensor_limits(
+    sensors: &[Box<dyn TemperatureSensor>],
+) -> BTreeMap<String, SensorLimits> {
+    let mut limits = BTreeMap::new();
+    for (idx, sensor) in sensors.iter().enumerate() {
+        let reported = sensor.limits().await;
+        if reported == SensorLimits::default() {
+            continue;
+        }
+        let name = sensor
+            .sensor_name()
+            .await
+            .unwrap_or_else(|| format!("sensor{idx}"));
+        limits.insert(name, reported);
+    }
+    limits
+}
+
+/// Builds the sensors of one `kind` from their `sensors` entries.
+pub trait SensorFactory: Send + Sync {
+    /// `Ok(None)` when the sensor's hardware is not present, which skips it at startup.
+    fn build(&self, cfg: &SensorCfg) -> Result<Option<Box<dyn TemperatureSensor>>>;
+}
+
+impl<F> SensorFactory for F
+where
+    F: Fn(&SensorCfg) -> Result<Option<Box<dyn TemperatureSensor>>> + Send + Sync,
+{
+    fn build(&self, cfg: &SensorCfg) -> Result<Option<Box<dyn TemperatureSensor>>> {
+        self(cfg)
+    }
+}
+
+/// Sensor backends keyed by the `kind` tag of a `sensors` entry.
+#[derive(Default)]
+pub struct SensorRegistry {
+    factories: HashMap<String, Box<dyn SensorFactory>>,
+}
+
+impl SensorRegistry {
+    /// Makes `factory` build every entry of `kind`, replacing any earlier one.
+    pub fn register(&mut self, kind: impl Into<String>, factory: impl SensorFactory + 'static) {
+        self.factories.insert(kind.into(), Box::new(factory));
+    }
+
+    /// Builds the configured sensors in config order. An entry whose `kind` has no
+    /// registered backend is an error, so a misspelled kind doesn't go unnoticed.
+    pub fn build_all(&self, cfg: &[SensorCfg]) -> Result<Vec<Box<dyn TemperatureSensor>>> {
+        self.kinds().check(cfg)?;
+        let mut sensors = Vec::new();
+        for entry in cfg {
+            if let Some(factory) = self.factories.get(entry.kind()) {
+                sensors.extend(factory.build(entry)?);
+            }
+        }
+        Ok(sensors)
+    }
+
+    /// The kinds that have a backend, for checking configs loaded after startup.
+    pub fn kinds(&self) -> SensorKinds {
+        SensorKinds(self.factories.keys().cloned().collect())
+    }
+}
+
+/// The `kind` tags a [`SensorRegistry`] can build.
+#[derive(Debug, Clone, Default)]
+pub struct SensorKinds(HashSet<String>);
+
+impl SensorKinds {
+    /// Fails on the first entry whose `kind` has no backend.
+    pub fn check(&self, cfg: &[SensorCfg]) -> Result<()> {
+        match cfg.iter().find(|entry| !self.0.contains(entry.kind())) {
+            Some(entry) => Err(anyhow!(
+                "sensor `{}`: no backend for kind `{}`",
+                entry.id(),
+                entry.kind()
+            )),
+            None => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+    use async_trait::async_trait;
+
+    use super::{SensorRegistry, TemperatureSensor};
+    use crate::config::{self, SensorCfg};
+
+    struct Fixed {
+        id: String,
+        temp: f32,
+    }
+
+    #[async_trait]
+    impl TemperatureSensor for Fixed {
+        async fn read_temperature(&self) -> Result<f32> {
+            Ok(self.temp)
+        }
+        async fn sensor_name(&self) -> Option<String> {
+            Some(self.id.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn custom_kind_is_built_from_config() {
+        let cfg =
+            config::parse("version: 1\nsensors:\n  - { id: desk, kind: fixed, temp: 31.5 }\n")
+                .unwrap();
+        let mut registry = SensorRegistry::default();
+        registry.register("fixed", |cfg: &SensorCfg| {
+            let SensorCfg::Custom { id, params, .. } = cfg else {
+                return Ok(None);
+            };
+            let temp = params.get("temp").and_then(|t| t.as_f64()).unwrap_or(0.0);
+            Ok(Some(Box::new(Fixed {
+                id: id.clone(),
+                temp: temp as f32,
+            }) as Box<dyn TemperatureSensor>))
+        });
+
+        let sensors = registry.build_all(&cfg.sensors).unwrap();
+        assert_eq!(sensors.len(), 1);
+        assert_eq!(sensors[0].sensor_name().await.as_deref(), Some("desk"));
+        assert_eq!(sensors[0].read_temperature().await.unwrap(), 31.5);
+
+        let cfg = config::parse("version: 1\nsensors:\n  - { id: desk, kind: fixd }\n").unwrap();
+        let err = registry.build_all(&cfg.sensors).err().unwrap().to_string();
+        assert_eq!(err, "sensor `desk`: no backend for kind `fixd`");
+
+        // A built-in kind with a missing field doesn't silently become a custom one.
+        let err = config::check(
+            config::parse("version: 1\nsensors:\n  - { id: vrm, kind: hwmon, input: temp2 }\n")
+                .unwrap(),
+        )
+        .unwrap_err()
+        .to_string();
+        assert!(err.contains("invalid fields for kind `hwmon`"), "{err}");
+    }
 }
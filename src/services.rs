@@ -0,0 +1,472 @@
+use std::{
+    fmt, io,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use anyhow::{Result, anyhow};
+use log::info;
+use tokio::{runtime, sync::oneshot, task::JoinHandle, time::interval};
+
+use crate::{
+    events::{Event, EventBus},
+    history::unix_now,
+};
+
+/// Set once the daemon starts shutting down, so blocking device I/O can bail out early
+/// instead of holding up the runtime.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken(Arc<AtomicBool>);
+
+impl CancelToken {
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// Runs `task` on a thread of its own with a single-threaded runtime, so it never waits
+/// for a worker busy with other tasks. Aborting the returned handle stops the task.
+pub fn spawn_dedicated<F>(name: &str, task: F) -> io::Result<JoinHandle<()>>
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    let runtime = runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    let (stop_tx, stop_rx) = oneshot::channel::<()>();
+    let (done_tx, done_rx) = oneshot::channel();
+    thread::Builder::new()
+        .name(name.to_string())
+        .spawn(move || {
+            runtime.block_on(async {
+                tokio::select! {
+                    _ = task => {}
+                    _ = stop_rx => {}
+                }
+            });
+            let _ = done_tx.send(());
+        })?;
+    Ok(tokio::spawn(async move {
+        // Dropped on abort, which wakes `stop_rx` on the dedicated thread.
+        let _stop = stop_tx;
+        let _ = done_rx.await;
+    }))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TaskStatus {
+    Running,
+    /// The task returned or panicked on its own.
+    Finished,
+    /// Stopped by [`Services::shutdown`] or [`TaskTable::stop_task`].
+    Stopped,
+}
+
+impl fmt::Display for TaskStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            TaskStatus::Running => "running",
+            TaskStatus::Finished => "finished",
+            TaskStatus::Stopped => "stopped",
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TaskInfo {
+    pub name: String,
+    /// Unix time of the latest start.
+    pub started: u64,
+    /// Times the service was started again under the same name.
+    pub restarts: u32,
+    pub status: TaskStatus,
+}
+
+/// Spawns a service's task again, for services that can be restarted on their own.
+#[derive(Clone)]
+struct Spawner(Arc<dyn Fn() -> JoinHandle<()> + Send + Sync>);
+
+impl fmt::Debug for Spawner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Spawner")
+    }
+}
+
+#[derive(Debug)]
+struct TaskEntry {
+    info: TaskInfo,
+    /// Taken when the service is stopped.
+    handle: Option<JoinHandle<()>>,
+    spawner: Option<Spawner>,
+}
+
+impl TaskEntry {
+    fn info(&self) -> TaskInfo {
+        let mut info = self.info.clone();
+        if info.status == TaskStatus::Running
+            && self.handle.as_ref().is_none_or(JoinHandle::is_finished)
+        {
+            info.status = TaskStatus::Finished;
+        }
+        info
+    }
+
+    fn start(&mut self, handle: JoinHandle<()>) {
+        if let Some(old) = self.handle.replace(handle) {
+            old.abort();
+        }
+        self.info.started = unix_now();
+        self.info.restarts += 1;
+        self.info.status = TaskStatus::Running;
+    }
+}
+
+/// Every service started so far, shared with the D-Bus interface so single services can
+/// be inspected, stopped and restarted.
+#[derive(Debug, Clone, Default)]
+pub struct TaskTable {
+    entries: Arc<Mutex<Vec<TaskEntry>>>,
+    bus: EventBus,
+}
+
+impl TaskTable {
+    /// Recovers from poisoning: entries are only replaced whole, so a panic elsewhere
+    /// can't leave one half-written.
+    fn entries(&self) -> MutexGuard<'_, Vec<TaskEntry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    pub fn task_info(&self, name: &str) -> Option<TaskInfo> {
+        let entries = self.entries();
+        entries
+            .iter()
+            .find(|e| e.info.name == name)
+            .map(TaskEntry::info)
+    }
+
+    /// Resolves with the first of `names` whose task returns or panics on its own; services
+    /// stopped on purpose don't count. Checked every `period`.
+    pub async fn first_finished(&self, names: &[String], period: Duration) -> String {
+        let mut check = interval(period);
+        loop {
+            check.tick().await;
+            let finished = names.iter().find(|name| {
+                self.task_info(name)
+                    .is_some_and(|info| info.status == TaskStatus::Finished)
+            });
+            if let Some(name) = finished {
+                return name.clone();
+            }
+        }
+    }
+
+    /// Services in first-start order.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn list_tasks(&self) -> Vec<TaskInfo> {
+        self.entries().iter().map(TaskEntry::info).collect()
+    }
+
+    /// Aborts the service's task. Returns `false` if no running service has that name.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn stop_task(&self, name: &str) -> bool {
+        if self
+            .task_info(name)
+            .is_none_or(|info| info.status != TaskStatus::Running)
+        {
+            return false;
+        }
+        if let Some(entry) = self.entries().iter_mut().find(|e| e.info.name == name) {
+            if let Some(handle) = entry.handle.take() {
+                handle.abort();
+            }
+            entry.info.status = TaskStatus::Stopped;
+        }
+        info!("Service {name} stopped");
+        self.bus.publish(Event::ServiceStopped {
+            name: name.to_string(),
+        });
+        true
+    }
+
+    /// Stops the service if it is running and starts a fresh task for it. Only services
+    /// started with [`Services::start_restartable`] can be restarted.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn restart_service(&self, name: &str) -> Result<()> {
+        let spawner = self
+            .entries()
+            .iter()
+            .find(|e| e.info.name == name)
+            .ok_or_else(|| anyhow!("No service named `{name}`"))?
+            .spawner
+            .clone()
+            .ok_or_else(|| anyhow!("Service `{name}` can't be restarted on its own"))?;
+        self.stop_task(name);
+        let handle = (spawner.0)();
+        if let Some(entry) = self.entries().iter_mut().find(|e| e.info.name == name) {
+            entry.start(handle);
+        }
+        info!("Service {name} started");
+        self.bus.publish(Event::ServiceStarted {
+            name: name.to_string(),
+        });
+        Ok(())
+    }
+
+    fn started(&self, name: &str, handle: JoinHandle<()>, spawner: Option<Spawner>) {
+        let mut entries = self.entries();
+        match entries.iter_mut().find(|e| e.info.name == name) {
+            Some(entry) => {
+                entry.start(handle);
+                entry.spawner = spawner;
+            }
+            None => entries.push(TaskEntry {
+                info: TaskInfo {
+                    name: name.to_string(),
+                    started: unix_now(),
+                    restarts: 0,
+                    status: TaskStatus::Running,
+                },
+                handle: Some(handle),
+                spawner,
+            }),
+        }
+    }
+
+    /// Marks every service still running as stopped and hands out their tasks, last
+    /// started first.
+    fn take_all(&self) -> Vec<(String, JoinHandle<()>)> {
+        let mut entries = self.entries();
+        entries
+            .iter_mut()
+            .rev()
+            .filter_map(|entry| {
+                if entry.info().status == TaskStatus::Running {
+                    entry.info.status = TaskStatus::Stopped;
+                }
+                let handle = entry.handle.take()?;
+                Some((entry.info.name.clone(), handle))
+            })
+            .collect()
+    }
+}
+
+/// Long-running daemon tasks, kept in start order.
+#[derive(Debug)]
+pub struct Services {
+    cancel: CancelToken,
+    table: TaskTable,
+}
+
+impl Services {
+    pub fn new(bus: EventBus, cancel: CancelToken) -> Self {
+        Self {
+            cancel,
+            table: TaskTable {
+                entries: Arc::default(),
+                bus,
+            },
+        }
+    }
+
+    pub fn table(&self) -> TaskTable {
+        self.table.clone()
+    }
+
+    /// Starts a service. Starting one again under the same name replaces its task.
+    pub fn start(&mut self, name: impl Into<String>, handle: JoinHandle<()>) {
+        self.register(name.into(), handle, None);
+    }
+
+    /// Starts a service whose task `spawn` can create again, so it can be restarted with
+    /// [`TaskTable::restart_service`] without restarting the daemon.
+    pub fn start_restartable<F>(&mut self, name: impl Into<String>, spawn: F)
+    where
+        F: Fn() -> JoinHandle<()> + Send + Sync + 'static,
+    {
+        let handle = spawn();
+        self.register(name.into(), handle, Some(Spawner(Arc::new(spawn))));
+    }
+
+    fn register(&mut self, name: String, handle: JoinHandle<()>, spawner: Option<Spawner>) {
+        info!("Service {name} started");
+        self.table.started(&name, handle, spawner);
+        self.table
+            .bus
+            .publish(Event::ServiceStarted { name: name.clone() });
+    }
+
+    /// Cancels pending device I/O, then stops services in reverse start order.
+    pub async fn shutdown(&mut self) {
+        self.cancel.cancel();
+        for (name, handle) in self.table.take_all() {
+            handle.abort();
+            let _ = handle.await;
+            info!("Service {name} stopped");
+            self.table.bus.publish(Event::ServiceStopped { name });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        future::pending,
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use super::{CancelToken, Services, TaskStatus};
+    use crate::events::{Event, EventBus};
+
+    #[tokio::test]
+    async fn publishes_lifecycle_in_order() {
+        let bus = EventBus::default();
+        let mut rx = bus.subscribe();
+        let cancel = CancelToken::default();
+        let mut services = Services::new(bus, cancel.clone());
+
+        for name in ["config-watcher", "color", "monitoring"] {
+            services.start(name, tokio::spawn(pending()));
+        }
+        services.shutdown().await;
+        assert!(cancel.is_cancelled());
+
+        let mut events = vec![];
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        let started = |name: &str| Event::ServiceStarted {
+            name: name.to_string(),
+        };
+        let stopped = |name: &str| Event::ServiceStopped {
+            name: name.to_string(),
+        };
+        assert_eq!(
+            events,
+            vec![
+                started("config-watcher"),
+                started("color"),
+                started("monitoring"),
+                stopped("monitoring"),
+                stopped("color"),
+                stopped("config-watcher"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn poisoned_table_still_reads() {
+        let mut services = Services::new(EventBus::default(), CancelToken::default());
+        services.start("monitoring", tokio::spawn(pending()));
+        let table = services.table();
+
+        let poisoner = table.clone();
+        let _ = std::thread::spawn(move || {
+            let _guard = poisoner.entries.lock().unwrap();
+            panic!("poison the task table");
+        })
+        .join();
+        assert!(table.entries.is_poisoned());
+
+        let info = table.task_info("monitoring").unwrap();
+        assert_eq!(info.status, TaskStatus::Running);
+        services.shutdown().await;
+        assert_eq!(table.list_tasks()[0].status, TaskStatus::Stopped);
+    }
+
+    #[tokio::test]
+    async fn tracks_status_and_restarts() {
+        let mut services = Services::new(EventBus::default(), CancelToken::default());
+        let table = services.table();
+
+        services.start("monitoring", tokio::spawn(pending()));
+        services.start("oneshot", tokio::spawn(async {}));
+        services.start("monitoring", tokio::spawn(pending()));
+        tokio::task::yield_now().await;
+
+        let status = |name| {
+            table
+                .task_info(name)
+                .map(|info| (info.status, info.restarts))
+        };
+        assert_eq!(status("monitoring"), Some((TaskStatus::Running, 1)));
+        assert_eq!(status("oneshot"), Some((TaskStatus::Finished, 0)));
+        assert_eq!(status("color"), None);
+
+        services.shutdown().await;
+        let names: Vec<_> = table
+            .list_tasks()
+            .into_iter()
+            .map(|info| (info.name, info.status))
+            .collect();
+        assert_eq!(
+            names,
+            vec![
+                (String::from("monitoring"), TaskStatus::Stopped),
+                (String::from("oneshot"), TaskStatus::Finished),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn restarts_a_single_service_with_a_fresh_task() {
+        let bus = EventBus::default();
+        let mut rx = bus.subscribe();
+        let mut services = Services::new(bus, CancelToken::default());
+        let table = services.table();
+        let spawned = Arc::new(AtomicUsize::new(0));
+        services.start_restartable("color", {
+            let spawned = spawned.clone();
+            move || {
+                spawned.fetch_add(1, Ordering::Relaxed);
+                tokio::spawn(pending())
+            }
+        });
+        services.start("monitoring", tokio::spawn(pending()));
+
+        assert!(table.stop_task("color"));
+        assert!(!table.stop_task("color"));
+        assert_eq!(
+            table.task_info("color").unwrap().status,
+            TaskStatus::Stopped
+        );
+
+        table.restart_service("color").unwrap();
+        let info = table.task_info("color").unwrap();
+        assert_eq!((info.status, info.restarts), (TaskStatus::Running, 1));
+        assert_eq!(spawned.load(Ordering::Relaxed), 2);
+        assert_eq!(
+            table.task_info("monitoring").unwrap().status,
+            TaskStatus::Running
+        );
+
+        let msg = table.restart_service("monitoring").unwrap_err().to_string();
+        assert!(msg.contains("can't be restarted"), "{msg}");
+        assert!(table.restart_service("nope").is_err());
+
+        services.shutdown().await;
+        let mut events = vec![];
+        while let Ok(event) = rx.try_recv() {
+            events.push(event);
+        }
+        let stopped_color = events
+            .iter()
+            .filter(|e| {
+                **e == Event::ServiceStopped {
+                    name: "color".into(),
+                }
+            })
+            .count();
+        assert_eq!(stopped_color, 2);
+    }
+}
@@ -0,0 +1,78 @@
+use std::{fmt, thread, time::Duration};
+
+use anyhow::Result;
+use signal_hook::{
+    consts::{SIGINT, SIGTERM},
+    iterator::Signals,
+};
+use tokio::sync::oneshot;
+
+/// How often the fan control loops are checked for having ended.
+pub const SERVICE_CHECK: Duration = Duration::from_secs(1);
+
+/// Why the daemon stopped, so a service manager can tell a requested stop from a failure
+/// by the exit code alone.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ShutdownReason {
+    /// SIGINT or SIGTERM.
+    Signal,
+    /// `Stop()` over D-Bus.
+    DbusRequest,
+    /// Startup or the daemon itself failed with an error.
+    FatalError,
+    /// A fan control loop returned or panicked, leaving fans without control.
+    ServiceFailed(String),
+}
+
+impl ShutdownReason {
+    /// 0 for stops that were asked for, so `Restart=on-failure` leaves them alone; a distinct
+    /// non-zero code for each kind of failure.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            Self::Signal | Self::DbusRequest => 0,
+            Self::FatalError => 1,
+            Self::ServiceFailed(_) => 3,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Signal => f.write_str("signal"),
+            Self::DbusRequest => f.write_str("D-Bus stop request"),
+            Self::FatalError => f.write_str("fatal error"),
+            Self::ServiceFailed(name) => write!(f, "service {name} failed"),
+        }
+    }
+}
+
+/// Resolves on the first SIGINT or SIGTERM, which a thread of its own waits for.
+pub fn on_signal() -> Result<oneshot::Receiver<()>> {
+    let mut signals = Signals::new([SIGINT, SIGTERM])?;
+    let (tx, rx) = oneshot::channel();
+    thread::Builder::new()
+        .name(String::from("signals"))
+        .spawn(move || {
+            signals.forever().next();
+            let _ = tx.send(());
+        })?;
+    Ok(rx)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ShutdownReason;
+
+    #[test]
+    fn reasons_map_to_fixed_exit_codes() {
+        let codes = [
+            ShutdownReason::Signal,
+            ShutdownReason::DbusRequest,
+            ShutdownReason::FatalError,
+            ShutdownReason::ServiceFailed(String::from("monitoring")),
+        ]
+        .map(|reason| reason.exit_code());
+        assert_eq!(codes, [0, 0, 1, 3]);
+    }
+}
@@ -0,0 +1,92 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::config;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Saved {
+    enabled: bool,
+}
+
+impl Default for Saved {
+    fn default() -> Self {
+        Self { enabled: true }
+    }
+}
+
+/// Runtime switches that outlive a restart, saved as JSON.
+#[derive(Debug, Default)]
+pub struct DaemonState {
+    path: Option<PathBuf>,
+    saved: Saved,
+}
+
+impl DaemonState {
+    /// Reads the saved state; a missing or unreadable file starts enabled.
+    pub fn load(path: impl Into<PathBuf>) -> Self {
+        let path = path.into();
+        let saved = match fs::read_to_string(&path) {
+            Ok(txt) => serde_json::from_str(&txt).unwrap_or_else(|e| {
+                warn!("Ignoring state {}: {e}", path.display());
+                Saved::default()
+            }),
+            Err(_) => Saved::default(),
+        };
+        Self {
+            path: Some(path),
+            saved,
+        }
+    }
+
+    /// Default location: `state.json` next to the config file, see [`config::data_file`].
+    pub fn default_path(config_path: &Path) -> PathBuf {
+        config::data_file(config_path, "state.json")
+    }
+
+    /// Whether the daemon controls the fans at all.
+    pub fn enabled(&self) -> bool {
+        self.saved.enabled
+    }
+
+    /// Stores the master switch and writes the file.
+    #[cfg_attr(not(feature = "dbus"), allow(dead_code))]
+    pub fn set_enabled(&mut self, enabled: bool) -> Result<()> {
+        self.saved.enabled = enabled;
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        let txt = serde_json::to_string_pretty(&self.saved)?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir).with_context(|| format!("creating {}", dir.display()))?;
+        }
+        fs::write(path, txt).with_context(|| format!("writing {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, process};
+
+    use super::DaemonState;
+
+    #[test]
+    fn disabled_state_survives_a_reload() {
+        let path = env::temp_dir().join(format!("tt_riingd_state_{}.json", process::id()));
+        let _ = fs::remove_file(&path);
+
+        let mut state = DaemonState::load(&path);
+        assert!(state.enabled());
+        state.set_enabled(false).unwrap();
+        assert!(!DaemonState::load(&path).enabled());
+
+        fs::write(&path, "not json").unwrap();
+        assert!(DaemonState::load(&path).enabled());
+        fs::remove_file(&path).unwrap();
+    }
+}
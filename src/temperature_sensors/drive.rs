@@ -0,0 +1,147 @@
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+
+use super::hwmon::parse_millidegrees;
+use crate::{
+    config::SensorCfg,
+    sensors::{SensorFactory, TemperatureSensor},
+};
+
+pub const SYSFS_ROOT: &str = "/sys";
+
+/// Input read from a drive's hwmon node: the composite temperature on NVMe, the only one
+/// on SATA drives with `drivetemp`.
+const DRIVE_INPUT: &str = "temp1_input";
+
+/// Temperature of a drive, by the name of its NVMe controller (`nvme0`) or block device
+/// (`nvme0n1`, `sda`).
+///
+/// The hwmon node is looked up on every read, so a drive that was removed fails its reads
+/// until it is back, and a renumbered `hwmonN` is followed.
+pub struct DriveSensor {
+    id: String,
+    device: String,
+    root: PathBuf,
+}
+
+/// Builds `drive` entries under `root`, normally [`SYSFS_ROOT`].
+pub struct DriveFactory {
+    pub root: PathBuf,
+}
+
+impl SensorFactory for DriveFactory {
+    fn build(&self, cfg: &SensorCfg) -> Result<Option<Box<dyn TemperatureSensor>>> {
+        let SensorCfg::Drive { id, device } = cfg else {
+            return Ok(None);
+        };
+        Ok(Some(Box::new(DriveSensor {
+            id: id.clone(),
+            device: device.clone(),
+            root: self.root.clone(),
+        })))
+    }
+}
+
+/// First `hwmonN` directory directly in `dir` or in its `hwmon` subdirectory.
+fn hwmon_in(dir: &Path) -> Option<PathBuf> {
+    [dir.to_path_buf(), dir.join("hwmon")]
+        .iter()
+        .filter_map(|dir| fs::read_dir(dir).ok())
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .find(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .and_then(|name| name.strip_prefix("hwmon"))
+                .is_some_and(|n| !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()))
+        })
+}
+
+impl DriveSensor {
+    /// The drive's hwmon node: NVMe registers it on the controller (`class/nvme/nvme0`),
+    /// which a namespace's block device links to as `device`, and `drivetemp` under the SCSI
+    /// device of `class/block/sda`.
+    fn resolve(&self) -> Result<PathBuf> {
+        let candidates = [
+            self.root.join("class/nvme").join(&self.device),
+            self.root
+                .join("class/block")
+                .join(&self.device)
+                .join("device"),
+        ];
+        let present: Vec<_> = candidates.iter().filter(|dir| dir.exists()).collect();
+        if present.is_empty() {
+            return Err(anyhow!("drive `{}` not found", self.device));
+        }
+        present
+            .into_iter()
+            .find_map(|dir| hwmon_in(dir))
+            .ok_or(anyhow!(
+                "drive `{}` has no hwmon temperature; NVMe needs CONFIG_NVME_HWMON, SATA the drivetemp module",
+                self.device
+            ))
+    }
+}
+
+#[async_trait]
+impl TemperatureSensor for DriveSensor {
+    async fn sensor_name(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+
+    async fn read_temperature(&self) -> Result<f32> {
+        let path = self.resolve()?.join(DRIVE_INPUT);
+        let raw =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        parse_millidegrees(&raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, os::unix::fs::symlink, process};
+
+    use super::DriveFactory;
+    use crate::{config::SensorCfg, sensors::SensorFactory};
+
+    #[tokio::test]
+    async fn resolves_nvme_and_sata_drives() {
+        let root = env::temp_dir().join(format!("tt_riingd_drive_{}", process::id()));
+        let nvme = root.join("class/nvme/nvme0");
+        fs::create_dir_all(nvme.join("hwmon2")).unwrap();
+        fs::write(nvme.join("hwmon2/temp1_input"), "38850\n").unwrap();
+        fs::create_dir_all(root.join("class/block/nvme0n1")).unwrap();
+        symlink(&nvme, root.join("class/block/nvme0n1/device")).unwrap();
+        let sata = root.join("class/block/sda/device/hwmon/hwmon5");
+        fs::create_dir_all(&sata).unwrap();
+        fs::write(sata.join("temp1_input"), "31000\n").unwrap();
+
+        let factory = DriveFactory { root: root.clone() };
+        let drive = |device: &str| {
+            factory
+                .build(&SensorCfg::Drive {
+                    id: String::from("disk"),
+                    device: device.to_string(),
+                })
+                .unwrap()
+                .unwrap()
+        };
+
+        assert_eq!(drive("nvme0").read_temperature().await.unwrap(), 38.85);
+        assert_eq!(drive("nvme0n1").read_temperature().await.unwrap(), 38.85);
+        assert_eq!(drive("sda").read_temperature().await.unwrap(), 31.0);
+
+        let err = drive("sdb").read_temperature().await.unwrap_err();
+        assert_eq!(err.to_string(), "drive `sdb` not found");
+        fs::remove_dir_all(&sata).unwrap();
+        let err = drive("sda").read_temperature().await.unwrap_err();
+        assert!(err.to_string().contains("no hwmon temperature"), "{err}");
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,137 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use anyhow::{Result, anyhow};
+use async_trait::async_trait;
+use dashmap::DashMap;
+
+use crate::{
+    config::SensorCfg,
+    sensors::{SensorFactory, TemperatureSensor},
+};
+
+/// Last values pushed from outside the daemon (e.g. over D-Bus), keyed by sensor id.
+#[derive(Debug, Clone, Default)]
+pub struct ExternalTemperatures(Arc<DashMap<String, Option<(f32, Instant)>>>);
+
+impl ExternalTemperatures {
+    pub fn from_cfg(cfg: &[SensorCfg]) -> Self {
+        let values = Self::default();
+        for c in cfg {
+            if let SensorCfg::External { id, .. } = c {
+                values.0.insert(id.clone(), None);
+            }
+        }
+        values
+    }
+
+    pub fn set(&self, id: &str, temp: f32) -> Result<()> {
+        self.set_at(id, temp, Instant::now())
+    }
+
+    fn set_at(&self, id: &str, temp: f32, at: Instant) -> Result<()> {
+        self.0
+            .get_mut(id)
+            .map(|mut entry| *entry = Some((temp, at)))
+            .ok_or(anyhow!("External sensor `{id}` is not configured"))
+    }
+
+    fn get(&self, id: &str) -> Option<(f32, Instant)> {
+        self.0.get(id).and_then(|entry| *entry)
+    }
+}
+
+pub struct ExternalSensor {
+    id: String,
+    max_age: Duration,
+    values: ExternalTemperatures,
+}
+
+/// Builds `external` entries reading the values pushed into `values`.
+pub struct ExternalFactory(pub ExternalTemperatures);
+
+impl SensorFactory for ExternalFactory {
+    fn build(&self, cfg: &SensorCfg) -> Result<Option<Box<dyn TemperatureSensor>>> {
+        let SensorCfg::External { id, max_age } = cfg else {
+            return Ok(None);
+        };
+        Ok(Some(Box::new(ExternalSensor {
+            id: id.clone(),
+            max_age: Duration::from_secs(*max_age),
+            values: self.0.clone(),
+        })))
+    }
+}
+
+#[async_trait]
+impl TemperatureSensor for ExternalSensor {
+    async fn sensor_name(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+
+    async fn read_temperature(&self) -> Result<f32> {
+        let (temp, at) = self
+            .values
+            .get(&self.id)
+            .ok_or(anyhow!("External sensor `{}` has no value yet", self.id))?;
+        if at.elapsed() > self.max_age {
+            return Err(anyhow!(
+                "External sensor `{}` is stale ({}s old)",
+                self.id,
+                at.elapsed().as_secs()
+            ));
+        }
+        Ok(temp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{ExternalSensor, ExternalTemperatures};
+    use crate::config::SensorCfg;
+
+    fn setup() -> (ExternalTemperatures, ExternalSensor) {
+        let cfg = [SensorCfg::External {
+            id: String::from("loop"),
+            max_age: 30,
+        }];
+        let values = ExternalTemperatures::from_cfg(&cfg);
+        let sensor = ExternalSensor {
+            id: String::from("loop"),
+            max_age: Duration::from_secs(30),
+            values: values.clone(),
+        };
+        (values, sensor)
+    }
+
+    #[tokio::test]
+    async fn returns_pushed_value() {
+        use crate::sensors::TemperatureSensor;
+
+        let (values, sensor) = setup();
+        assert!(sensor.read_temperature().await.is_err());
+
+        values.set("loop", 31.5).unwrap();
+        assert_eq!(sensor.read_temperature().await.unwrap(), 31.5);
+        assert!(values.set("unknown", 1.0).is_err());
+    }
+
+    #[tokio::test]
+    async fn stale_value_is_an_error() {
+        use crate::sensors::TemperatureSensor;
+
+        let (values, sensor) = setup();
+        // `Instant` can't reach back past boot, so a host up for under a minute skips this.
+        let Some(old) = Instant::now().checked_sub(Duration::from_secs(60)) else {
+            return;
+        };
+        values.set_at("loop", 31.5, old).unwrap();
+
+        let err = sensor.read_temperature().await.unwrap_err().to_string();
+        assert!(err.contains("stale"), "{err}");
+    }
+}
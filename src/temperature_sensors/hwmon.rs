@@ -0,0 +1,130 @@
+use std::{fs, path::PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use async_trait::async_trait;
+
+use crate::{
+    config::SensorCfg,
+    sensors::{SensorFactory, TemperatureSensor},
+};
+
+pub const HWMON_ROOT: &str = "/sys/class/hwmon";
+
+/// A `tempM_input` file of the hwmon device whose `name` attribute is `hwmon_name`.
+///
+/// `hwmonN` numbers are not stable across hotplug, so the device is looked up by name on
+/// every read; a device that is gone makes the read fail until it comes back.
+pub struct HwmonSensor {
+    id: String,
+    hwmon_name: String,
+    input: String,
+    root: PathBuf,
+}
+
+/// Builds `hwmon` entries under `root`, normally [`HWMON_ROOT`].
+pub struct HwmonFactory {
+    pub root: PathBuf,
+}
+
+impl SensorFactory for HwmonFactory {
+    fn build(&self, cfg: &SensorCfg) -> Result<Option<Box<dyn TemperatureSensor>>> {
+        let SensorCfg::Hwmon {
+            id,
+            hwmon_name,
+            input,
+        } = cfg
+        else {
+            return Ok(None);
+        };
+        Ok(Some(Box::new(HwmonSensor {
+            id: id.clone(),
+            hwmon_name: hwmon_name.clone(),
+            input: input.clone(),
+            root: self.root.clone(),
+        })))
+    }
+}
+
+impl HwmonSensor {
+    fn resolve(&self) -> Result<PathBuf> {
+        fs::read_dir(&self.root)
+            .with_context(|| format!("reading {}", self.root.display()))?
+            .filter_map(|entry| entry.ok().map(|e| e.path()))
+            .find(|dir| {
+                fs::read_to_string(dir.join("name"))
+                    .is_ok_and(|name| name.trim() == self.hwmon_name)
+            })
+            .ok_or(anyhow!("hwmon device `{}` not found", self.hwmon_name))
+    }
+}
+
+/// Parses a sysfs temperature in millidegrees Celsius.
+pub(super) fn parse_millidegrees(raw: &str) -> Result<f32> {
+    let milli: i64 = raw
+        .trim()
+        .parse()
+        .with_context(|| format!("invalid temperature `{}`", raw.trim()))?;
+    Ok(milli as f32 / 1000.0)
+}
+
+#[async_trait]
+impl TemperatureSensor for HwmonSensor {
+    async fn sensor_name(&self) -> Option<String> {
+        Some(self.id.clone())
+    }
+
+    async fn read_temperature(&self) -> Result<f32> {
+        let path = self.resolve()?.join(&self.input);
+        let raw =
+            fs::read_to_string(&path).with_context(|| format!("reading {}", path.display()))?;
+        parse_millidegrees(&raw)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs, path::Path, process};
+
+    use super::{HwmonFactory, parse_millidegrees};
+    use crate::{config::SensorCfg, sensors::SensorFactory};
+
+    fn device(root: &Path, dir: &str, name: &str, temp: &str) {
+        let dir = root.join(dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("name"), format!("{name}\n")).unwrap();
+        fs::write(dir.join("temp2_input"), temp).unwrap();
+    }
+
+    #[tokio::test]
+    async fn resolves_device_by_name_across_hotplug() {
+        let root = env::temp_dir().join(format!("tt_riingd_hwmon_{}", process::id()));
+        device(&root, "hwmon0", "acpitz", "27800\n");
+        device(&root, "hwmon3", "nct6687", "45500\n");
+        let cfg = SensorCfg::Hwmon {
+            id: String::from("vrm"),
+            hwmon_name: String::from("nct6687"),
+            input: String::from("temp2_input"),
+        };
+        let factory = HwmonFactory { root: root.clone() };
+        let sensor = factory.build(&cfg).unwrap().unwrap();
+
+        assert_eq!(sensor.sensor_name().await.as_deref(), Some("vrm"));
+        assert_eq!(sensor.read_temperature().await.unwrap(), 45.5);
+
+        fs::remove_dir_all(root.join("hwmon3")).unwrap();
+        let err = sensor.read_temperature().await.unwrap_err().to_string();
+        assert!(err.contains("`nct6687` not found"), "{err}");
+
+        device(&root, "hwmon4", "nct6687", "51000\n");
+        assert_eq!(sensor.read_temperature().await.unwrap(), 51.0);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn parses_millidegrees() {
+        assert_eq!(parse_millidegrees("45500\n").unwrap(), 45.5);
+        assert_eq!(parse_millidegrees("-2000").unwrap(), -2.0);
+        assert!(parse_millidegrees("n/a\n").is_err());
+    }
+}
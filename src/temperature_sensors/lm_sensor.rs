@@ -1,87 +1,162 @@
-use std::sync::Arc;
-
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use async_trait::async_trait;
-use lm_sensors::{
-    LMSensors, SubFeatureRef,
-    value::{Kind as ValueKind, Value},
-};
+use lm_sensors::{LMSensors, SubFeatureRef, value::Kind as ValueKind};
 #[cfg(debug_assertions)]
 use log::info;
-use tokio::sync::Mutex;
 
-use crate::{config::SensorCfg, sensors::TemperatureSensor};
+use crate::{
+    config::SensorCfg,
+    sensors::{SensorFactory, SensorLimits, TemperatureSensor},
+};
 
-pub struct Sensor {
-    key: String,
-    subf: SubFeatureRef<'static>,
+/// One value of a libsensors feature, such as its input or its critical limit.
+trait Subfeature: Send + Sync {
+    fn read(&self) -> Result<f64>;
 }
 
+struct LibSubfeature(SubFeatureRef<'static>);
+
 // SAFETY: libsensors (>= 3.6) guards all sensor access with an internal global mutex.
 //         The `SubFeatureRef::value()` call is read-only.
 //         Therefore, moving this pointer across threads cannot cause data races.
-unsafe impl Send for Sensor {}
-unsafe impl Sync for Sensor {}
+unsafe impl Send for LibSubfeature {}
+unsafe impl Sync for LibSubfeature {}
 
-pub struct LmSensorSource(Arc<Mutex<Sensor>>);
+impl Subfeature for LibSubfeature {
+    fn read(&self) -> Result<f64> {
+        Ok(self.0.value()?.raw_value())
+    }
+}
+
+/// A chip feature's temperature input, with its `max` and `crit` limits when the chip
+/// reports them.
+pub struct LmSensorSource {
+    key: String,
+    input: Box<dyn Subfeature>,
+    max: Option<Box<dyn Subfeature>>,
+    crit: Option<Box<dyn Subfeature>>,
+}
 
 impl LmSensorSource {
-    #[allow(unreachable_patterns)]
-    pub fn discover(
+    fn discover(
         lmsensors: &'static LMSensors,
-        cfg: &[SensorCfg],
-    ) -> Result<Vec<Box<dyn TemperatureSensor>>> {
-        Ok(cfg
-            .iter()
-            .filter_map(|c| match c {
-                SensorCfg::LmSensors { id, chip, feature } => {
-                    #[cfg(debug_assertions)]
-                    {
-                        info!("Discovering LM sensor: chip={}, feature={}", chip, feature);
-                    }
-                    let chip_ref = lmsensors
-                        .chip_iter(None)
-                        .find(|c| c.name().map(|n| n == *chip).unwrap_or(false))?;
-                    let feat_ref = chip_ref.feature_iter().find(|f| {
-                        f.name()
-                            .map(|n| n.unwrap_or("N/A"))
-                            .map(|s| s == *feature)
-                            .unwrap_or(false)
-                    })?;
-                    let subfeat_ref = feat_ref
-                        .sub_feature_iter()
-                        .find(|s| matches!(s.kind(), Some(ValueKind::TemperatureInput)))?;
-
-                    #[cfg(debug_assertions)]
-                    {
-                        let chip_name = chip_ref.name().unwrap();
-                        let chip_bus = chip_ref.bus();
-                        let feat_name = feat_ref.name()?.unwrap();
-                        let sensor_key = format!("lm:{chip_name}@{chip_bus}:{feat_name}");
-                        info!("Found LM sensor: {sensor_key}");
-                    }
-
-                    Some(Box::new(LmSensorSource(Arc::new(Mutex::new(Sensor {
-                        key: id.to_string(),
-                        subf: subfeat_ref,
-                    })))) as Box<dyn TemperatureSensor>)
-                }
-                _ => None,
-            })
-            .collect::<Vec<_>>())
+        id: &str,
+        chip: &str,
+        feature: &str,
+    ) -> Option<Self> {
+        #[cfg(debug_assertions)]
+        {
+            info!("Discovering LM sensor: chip={}, feature={}", chip, feature);
+        }
+        let chip_ref = lmsensors
+            .chip_iter(None)
+            .find(|c| c.name().map(|n| n == chip).unwrap_or(false))?;
+        let feat_ref = chip_ref.feature_iter().find(|f| {
+            f.name()
+                .map(|n| n.unwrap_or("N/A"))
+                .map(|s| s == feature)
+                .unwrap_or(false)
+        })?;
+        let subfeature = |kind: ValueKind| {
+            feat_ref
+                .sub_feature_iter()
+                .find(|s| s.kind() == Some(kind))
+                .map(|s| Box::new(LibSubfeature(s)) as Box<dyn Subfeature>)
+        };
+
+        #[cfg(debug_assertions)]
+        {
+            let chip_name = chip_ref.name().unwrap();
+            let chip_bus = chip_ref.bus();
+            let feat_name = feat_ref.name()?.unwrap();
+            let sensor_key = format!("lm:{chip_name}@{chip_bus}:{feat_name}");
+            info!("Found LM sensor: {sensor_key}");
+        }
+
+        Some(LmSensorSource {
+            key: id.to_string(),
+            input: subfeature(ValueKind::TemperatureInput)?,
+            max: subfeature(ValueKind::TemperatureMaximum),
+            crit: subfeature(ValueKind::TemperatureCritical),
+        })
+    }
+}
+
+/// Builds `lm-sensors` entries from the chips libsensors knows; a missing chip or feature
+/// skips the sensor.
+pub struct LmSensorFactory(pub &'static LMSensors);
+
+impl SensorFactory for LmSensorFactory {
+    fn build(&self, cfg: &SensorCfg) -> Result<Option<Box<dyn TemperatureSensor>>> {
+        let SensorCfg::LmSensors { id, chip, feature } = cfg else {
+            return Ok(None);
+        };
+        Ok(LmSensorSource::discover(self.0, id, chip, feature)
+            .map(|sensor| Box::new(sensor) as Box<dyn TemperatureSensor>))
     }
 }
 
 #[async_trait]
 impl TemperatureSensor for LmSensorSource {
     async fn sensor_name(&self) -> Option<String> {
-        Some(self.0.lock().await.key.clone())
+        Some(self.key.clone())
     }
 
     async fn read_temperature(&self) -> Result<f32> {
-        match self.0.lock().await.subf.value()? {
-            Value::TemperatureInput(t) => Ok(t as f32),
-            _ => Err(anyhow!("non-temperature value")),
+        Ok(self.input.read()? as f32)
+    }
+
+    /// A limit that fails to read is reported as absent.
+    async fn limits(&self) -> SensorLimits {
+        let read = |subfeature: &Option<Box<dyn Subfeature>>| {
+            subfeature.as_ref()?.read().ok().map(|value| value as f32)
+        };
+        SensorLimits {
+            max: read(&self.max),
+            crit: read(&self.crit),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::{Result, anyhow};
+
+    use super::{LmSensorSource, Subfeature};
+    use crate::sensors::{SensorLimits, TemperatureSensor};
+
+    struct Stub(Option<f64>);
+
+    impl Subfeature for Stub {
+        fn read(&self) -> Result<f64> {
+            self.0.ok_or(anyhow!("read failed"))
+        }
+    }
+
+    fn source(max: Option<Stub>, crit: Option<Stub>) -> LmSensorSource {
+        let boxed = |stub: Stub| Box::new(stub) as Box<dyn Subfeature>;
+        LmSensorSource {
+            key: String::from("cpu"),
+            input: boxed(Stub(Some(52.5))),
+            max: max.map(boxed),
+            crit: crit.map(boxed),
         }
     }
+
+    #[tokio::test]
+    async fn reads_limits_the_chip_reports() {
+        let full = source(Some(Stub(Some(80.0))), Some(Stub(Some(100.0))));
+        assert_eq!(full.read_temperature().await.unwrap(), 52.5);
+        assert_eq!(
+            full.limits().await,
+            SensorLimits {
+                max: Some(80.0),
+                crit: Some(100.0)
+            }
+        );
+
+        let bare = source(None, Some(Stub(None)));
+        assert_eq!(bare.read_temperature().await.unwrap(), 52.5);
+        assert_eq!(bare.limits().await, SensorLimits::default());
+    }
 }
@@ -1 +1,4 @@
+pub mod drive;
+pub mod external;
+pub mod hwmon;
 pub mod lm_sensor;
@@ -0,0 +1,163 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::{
+    config::{Direction, ThresholdCfg},
+    events::{Event, EventBus},
+};
+
+#[derive(Debug)]
+struct Threshold {
+    cfg: ThresholdCfg,
+    /// `None` until the first reading; the first reading never fires.
+    armed: Option<bool>,
+}
+
+impl Threshold {
+    /// Returns whether this reading fires the alert.
+    fn update(&mut self, temp: f32) -> bool {
+        let ThresholdCfg {
+            value,
+            direction,
+            hysteresis,
+            ..
+        } = self.cfg;
+        let (crossed, reset) = match direction {
+            Direction::Rising => (temp >= value, temp < value - hysteresis),
+            Direction::Falling => (temp <= value, temp > value + hysteresis),
+        };
+        match self.armed {
+            None => {
+                self.armed = Some(!crossed);
+                false
+            }
+            Some(true) if crossed => {
+                self.armed = Some(false);
+                true
+            }
+            Some(false) if reset => {
+                self.armed = Some(true);
+                false
+            }
+            Some(_) => false,
+        }
+    }
+}
+
+/// Publishes [`Event::ThresholdCrossed`] once per crossing of each configured threshold.
+#[derive(Debug)]
+pub struct Thresholds {
+    rules: Vec<Threshold>,
+    bus: EventBus,
+}
+
+impl Thresholds {
+    pub fn new(cfg: &[ThresholdCfg], bus: EventBus) -> Self {
+        Self {
+            rules: cfg
+                .iter()
+                .map(|cfg| Threshold {
+                    cfg: cfg.clone(),
+                    armed: None,
+                })
+                .collect(),
+            bus,
+        }
+    }
+
+    pub fn update(&mut self, sensor: &str, temp: f32) {
+        for rule in self.rules.iter_mut().filter(|r| r.cfg.sensor == sensor) {
+            if rule.update(temp) {
+                self.bus.publish(Event::ThresholdCrossed {
+                    sensor: sensor.to_string(),
+                    value: temp,
+                    alert: rule.cfg.alert.clone(),
+                });
+            }
+        }
+    }
+}
+
+/// Publishes [`Event::CriticalTemperature`] when a sensor reaches its critical limit and
+/// again when it drops back below.
+#[derive(Debug)]
+pub struct CriticalAlerts {
+    limits: HashMap<String, f32>,
+    active: HashSet<String>,
+    bus: EventBus,
+}
+
+impl CriticalAlerts {
+    pub fn new(limits: HashMap<String, f32>, bus: EventBus) -> Self {
+        Self {
+            limits,
+            active: HashSet::new(),
+            bus,
+        }
+    }
+
+    pub fn update(&mut self, sensor: &str, temp: f32) {
+        let Some(&crit) = self.limits.get(sensor) else {
+            return;
+        };
+        let active = temp >= crit;
+        let changed = if active {
+            self.active.insert(sensor.to_string())
+        } else {
+            self.active.remove(sensor)
+        };
+        if changed {
+            self.bus.publish(Event::CriticalTemperature {
+                sensor: sensor.to_string(),
+                temperature: temp,
+                active,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Thresholds;
+    use crate::{
+        config::{Direction, ThresholdCfg},
+        events::{Event, EventBus},
+    };
+
+    fn threshold(direction: Direction, alert: &str) -> ThresholdCfg {
+        ThresholdCfg {
+            sensor: String::from("cpu"),
+            value: 75.0,
+            direction,
+            alert: String::from(alert),
+            hysteresis: 2.0,
+        }
+    }
+
+    #[test]
+    fn fires_once_per_crossing() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut thresholds = Thresholds::new(
+            &[
+                threshold(Direction::Rising, "hot"),
+                threshold(Direction::Falling, "cooled"),
+            ],
+            bus,
+        );
+
+        // Wobbling within the hysteresis band after each crossing does not fire again.
+        for temp in [70.0, 80.0, 76.0, 80.0, 74.0, 73.0, 76.0] {
+            thresholds.update("cpu", temp);
+            thresholds.update("gpu", temp);
+        }
+
+        let crossed = |value: f32, alert: &str| Event::ThresholdCrossed {
+            sensor: String::from("cpu"),
+            value,
+            alert: String::from(alert),
+        };
+        assert_eq!(events.try_recv().unwrap(), crossed(80.0, "hot"));
+        assert_eq!(events.try_recv().unwrap(), crossed(74.0, "cooled"));
+        assert!(events.try_recv().is_err());
+    }
+}
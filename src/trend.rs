@@ -0,0 +1,123 @@
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    config::TrendCfg,
+    events::{Event, EventBus},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Trend {
+    Rising,
+    Falling,
+    Stable,
+}
+
+impl fmt::Display for Trend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Trend::Rising => "rising",
+            Trend::Falling => "falling",
+            Trend::Stable => "stable",
+        })
+    }
+}
+
+impl Trend {
+    /// Compares the first and last of the newest `cfg.window` samples; `None` until the
+    /// window is full.
+    pub fn classify(samples: &[(u64, f32)], cfg: TrendCfg) -> Option<Self> {
+        let window = samples.get(samples.len().checked_sub(cfg.window)?..)?;
+        let change = window.last()?.1 - window.first()?.1;
+        Some(if change > cfg.delta {
+            Trend::Rising
+        } else if change < -cfg.delta {
+            Trend::Falling
+        } else {
+            Trend::Stable
+        })
+    }
+}
+
+/// Publishes [`Event::ThermalTrend`] whenever a sensor's trend changes.
+#[derive(Debug)]
+pub struct Trends {
+    cfg: TrendCfg,
+    last: HashMap<String, Trend>,
+    bus: EventBus,
+}
+
+impl Trends {
+    pub fn new(cfg: TrendCfg, bus: EventBus) -> Self {
+        Self {
+            cfg,
+            last: HashMap::new(),
+            bus,
+        }
+    }
+
+    /// `samples` is the sensor's history, oldest first.
+    pub fn update(&mut self, sensor: &str, samples: &[(u64, f32)]) {
+        let Some(trend) = Trend::classify(samples, self.cfg) else {
+            return;
+        };
+        if self.last.insert(sensor.to_string(), trend) != Some(trend) {
+            self.bus.publish(Event::ThermalTrend {
+                sensor: sensor.to_string(),
+                trend,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Trend, Trends};
+    use crate::{
+        config::TrendCfg,
+        events::{Event, EventBus},
+    };
+
+    const CFG: TrendCfg = TrendCfg {
+        window: 3,
+        delta: 1.0,
+    };
+
+    fn samples(temps: &[f32]) -> Vec<(u64, f32)> {
+        temps
+            .iter()
+            .enumerate()
+            .map(|(i, &t)| (i as u64, t))
+            .collect()
+    }
+
+    #[test]
+    fn classifies_the_newest_window() {
+        let classify = |temps: &[f32]| Trend::classify(&samples(temps), CFG);
+        assert_eq!(classify(&[40.0, 41.0]), None);
+        assert_eq!(classify(&[40.0, 41.0, 42.0]), Some(Trend::Rising));
+        assert_eq!(classify(&[42.0, 41.0, 40.0]), Some(Trend::Falling));
+        assert_eq!(classify(&[40.0, 40.5, 41.0]), Some(Trend::Stable));
+        // Older samples outside the window don't count.
+        assert_eq!(classify(&[30.0, 40.0, 40.2, 40.1]), Some(Trend::Stable));
+    }
+
+    #[test]
+    fn publishes_only_on_change() {
+        let bus = EventBus::default();
+        let mut events = bus.subscribe();
+        let mut trends = Trends::new(CFG, bus);
+
+        let temps = [40.0, 40.0, 40.0, 40.0, 42.0, 44.0, 46.0];
+        for n in 1..=temps.len() {
+            trends.update("cpu", &samples(&temps[..n]));
+        }
+
+        let trend = |trend| Event::ThermalTrend {
+            sensor: String::from("cpu"),
+            trend,
+        };
+        assert_eq!(events.try_recv().unwrap(), trend(Trend::Stable));
+        assert_eq!(events.try_recv().unwrap(), trend(Trend::Rising));
+        assert!(events.try_recv().is_err());
+    }
+}
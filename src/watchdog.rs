@@ -0,0 +1,120 @@
+use std::{
+    sync::{Arc, Mutex, PoisonError},
+    time::Duration,
+};
+
+use log::{error, info};
+use tokio::time::{Instant, interval};
+
+use crate::controller::Controllers;
+
+/// Time of the last completed tick of a fan update loop.
+#[derive(Debug, Clone)]
+pub struct Heartbeat(Arc<Mutex<Instant>>);
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self(Arc::new(Mutex::new(Instant::now())))
+    }
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        *self.0.lock().unwrap_or_else(PoisonError::into_inner) = Instant::now();
+    }
+
+    pub fn age(&self) -> Duration {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .elapsed()
+    }
+}
+
+/// Dead man's switch for the fan update loops: once any `heartbeats` entry is older than
+/// `timeout`, every fan is set to `panic_speed` so a stalled loop can't leave them frozen at
+/// a low speed. The loop takes the fans back on its next tick.
+pub async fn watchdog(
+    controllers: Controllers,
+    heartbeats: Vec<(String, Heartbeat)>,
+    timeout: Duration,
+    panic_speed: u8,
+) {
+    let mut check = interval(timeout / 2);
+    let mut tripped = false;
+    loop {
+        check.tick().await;
+        match heartbeats.iter().find(|(_, hb)| hb.age() > timeout) {
+            Some((name, hb)) if !tripped => {
+                error!(
+                    "{name} has not ticked for {:.1?}; setting every fan to {panic_speed}%",
+                    hb.age()
+                );
+                // Retried on the next check if a controller could not be reached.
+                tripped = set_all(&controllers, panic_speed).await;
+            }
+            None if tripped => {
+                info!("Fan update loops are ticking again");
+                tripped = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+async fn set_all(controllers: &Controllers, speed: u8) -> bool {
+    let mut ok = true;
+    for (idx, count) in controllers.fan_counts().await.into_iter().enumerate() {
+        let targets: Vec<_> = (1..=count as u8).map(|channel| (channel, speed)).collect();
+        if let Err(e) = controllers
+            .set_channel_speeds((idx + 1) as u8, &targets)
+            .await
+        {
+            error!("Failed to set panic speed on controller {}: {e}", idx + 1);
+            ok = false;
+        }
+    }
+    ok
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::Ordering, time::Duration};
+
+    use super::{Heartbeat, watchdog};
+    use crate::controller::{Controllers, tests::MockController};
+
+    #[tokio::test(start_paused = true)]
+    async fn stalled_loop_triggers_panic_speed() {
+        let (first, second) = (MockController::with_fans(2), MockController::with_fans(1));
+        let (first_speeds, second_speeds) = (first.speeds.clone(), second.speeds.clone());
+        let writes = first.writes.clone();
+        let controllers = Controllers::from(vec![first.boxed(), second.boxed()]);
+        let heartbeat = Heartbeat::default();
+        let task = tokio::spawn(watchdog(
+            controllers,
+            vec![(String::from("monitoring"), heartbeat.clone())],
+            Duration::from_secs(4),
+            90,
+        ));
+
+        // Beating every second keeps the fans alone.
+        for _ in 0..10 {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            heartbeat.beat();
+        }
+        assert_eq!(*first_speeds.lock().unwrap(), [0, 0]);
+
+        // Stalled: the fans go to the panic speed once, not on every check.
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        assert_eq!(*first_speeds.lock().unwrap(), [90, 90]);
+        assert_eq!(*second_speeds.lock().unwrap(), [90]);
+        assert_eq!(writes.load(Ordering::Relaxed), 1);
+
+        // Ticking again re-arms the switch for the next stall.
+        heartbeat.beat();
+        tokio::time::sleep(Duration::from_secs(13)).await;
+        assert_eq!(writes.load(Ordering::Relaxed), 2);
+        task.abort();
+    }
+}